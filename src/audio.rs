@@ -0,0 +1,195 @@
+//! Playback of sound effects and music.
+//!
+//! Sound effects are read from `assets/sounds/<name>.wav` and music from
+//! `assets/music/<name>.ogg`. Without an audio device, nothing gets played.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use rodio::{self, Decoder, Device, Sink, Source};
+use rodio::decoder::DecoderError;
+use rodio::source::Buffered;
+
+use bundle;
+use utils::WeakCache;
+
+/// A decoded sound, that can be played any number of times.
+pub struct Clip {
+    source: Buffered<Decoder<Cursor<Vec<u8>>>>,
+}
+
+/// Error loading a sound, with the path of the file.
+#[derive(Debug)]
+pub enum AudioError {
+    /// The file couldn't be read.
+    Io(PathBuf, io::Error),
+    /// The file isn't in a format we can decode.
+    Decode(PathBuf, DecoderError),
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AudioError::Io(ref path, ref e) => {
+                write!(f, "Can't read sound {}: {}", path.display(), e)
+            }
+            AudioError::Decode(ref path, ref e) => {
+                write!(f, "Can't decode sound {}: {:?}", path.display(), e)
+            }
+        }
+    }
+}
+
+impl Error for AudioError {
+    fn description(&self) -> &str {
+        match *self {
+            AudioError::Io(_, _) => "Can't read sound",
+            AudioError::Decode(_, _) => "Can't decode sound",
+        }
+    }
+}
+
+/// Decode a sound file, from the bundle or the assets directory.
+fn load_sound(name: &str) -> Result<Decoder<Cursor<Vec<u8>>>, AudioError> {
+    let path = Path::new("assets").join(name);
+    let bytes = if let Some(bytes) = bundle::get(name) {
+        bytes.to_vec()
+    } else {
+        let mut bytes = Vec::new();
+        try!(File::open(&path).and_then(|mut f| f.read_to_end(&mut bytes))
+             .map_err(|e| AudioError::Io(path.clone(), e)));
+        bytes
+    };
+    Decoder::new(Cursor::new(bytes)).map_err(|e| AudioError::Decode(path, e))
+}
+
+pub struct SoundManager {
+    device: Option<Device>,
+    clips: WeakCache<Clip>,
+    /// The music being played, stopped when replaced.
+    music: Option<Sink>,
+    /// Master volume, between 0 and 1.
+    volume: f32,
+}
+
+impl SoundManager {
+    pub fn new() -> SoundManager {
+        let device = rodio::default_output_device();
+        if device.is_none() {
+            warn!("No audio device, the game will be silent");
+        }
+        SoundManager::with_device(device)
+    }
+
+    fn with_device(device: Option<Device>) -> SoundManager {
+        SoundManager {
+            device: device,
+            clips: WeakCache::new(),
+            music: None,
+            volume: 1.0,
+        }
+    }
+
+    /// Change the master volume, for sounds and the current music.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.max(0.0).min(1.0);
+        if let Some(ref mut music) = self.music {
+            music.set_volume(self.volume);
+        }
+    }
+
+    /// Load a sound effect, reusing it if it is still loaded.
+    pub fn load(&self, name: &str) -> Result<Rc<Clip>, AudioError> {
+        self.clips.get_or_load(name, || {
+            let source = try!(load_sound(&format!("sounds/{}.wav", name)));
+            Ok(Clip { source: source.buffered() })
+        })
+    }
+
+    /// Play a sound effect that was loaded.
+    pub fn play(&self, clip: &Clip) {
+        if let Some(ref device) = self.device {
+            rodio::play_raw(device, clip.source.clone().amplify(self.volume)
+                                        .convert_samples());
+        }
+    }
+
+    /// Load and play a sound effect.
+    ///
+    /// Clips are only kept loaded while something holds them, see `load()`.
+    pub fn play_sfx(&self, name: &str) {
+        match self.load(name) {
+            Ok(clip) => self.play(&clip),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    /// Start playing music, replacing the current one.
+    pub fn play_music(&mut self, name: &str, looping: bool) {
+        let mut sink = match self.device {
+            Some(ref device) => Sink::new(device),
+            None => return,
+        };
+        let source = match load_sound(&format!("music/{}.ogg", name)) {
+            Ok(source) => source,
+            Err(e) => {
+                warn!("{}", e);
+                return;
+            }
+        };
+        sink.set_volume(self.volume);
+        if looping {
+            sink.append(source.buffered().repeat_infinite());
+        } else {
+            sink.append(source);
+        }
+        self.music = Some(sink);
+    }
+
+    pub fn stop_music(&mut self) {
+        self.music = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::SoundManager;
+
+    #[test]
+    fn test_clip_cache() {
+        let manager = SoundManager::with_device(None);
+        let clip = manager.load("jump").unwrap();
+        let again = manager.load("jump").unwrap();
+        assert!(Rc::ptr_eq(&clip, &again));
+
+        // Not kept once nothing uses it
+        drop(clip);
+        drop(again);
+        assert!(!manager.clips.is_loaded("jump"));
+        manager.load("jump").unwrap();
+
+        let error = manager.load("missing").err().unwrap().to_string();
+        assert!(error.contains("assets/sounds/missing.wav"), "{}", error);
+
+        // Playing without a device does nothing
+        manager.play_sfx("jump");
+    }
+
+    #[test]
+    fn test_volume() {
+        let mut manager = SoundManager::with_device(None);
+        assert_eq!(manager.volume, 1.0);
+        manager.set_volume(0.5);
+        assert_eq!(manager.volume, 0.5);
+        manager.set_volume(2.0);
+        assert_eq!(manager.volume, 1.0);
+        manager.set_volume(-1.0);
+        assert_eq!(manager.volume, 0.0);
+    }
+}
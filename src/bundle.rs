@@ -0,0 +1,29 @@
+//! Assets embedded in the executable.
+//!
+//! When built with the `embed_assets` feature, the files from `assets/` are
+//! included in the binary, so the game doesn't depend on the working
+//! directory. Otherwise, nothing is bundled and assets are read from disk.
+
+/// Get the content of an embedded asset, from its name under `assets/`.
+#[cfg(feature = "embed_assets")]
+pub fn get(name: &str) -> Option<&'static [u8]> {
+    let bytes: &'static [u8] = match name {
+        "alien/green__0000_idle_1.json" =>
+            include_bytes!("../assets/alien/green__0000_idle_1.json"),
+        "alien/green__0000_idle_1.png" =>
+            include_bytes!("../assets/alien/green__0000_idle_1.png"),
+        "map/bg.png" => include_bytes!("../assets/map/bg.png"),
+        "map/bg_castle.png" => include_bytes!("../assets/map/bg_castle.png"),
+        "map/castleCenter.png" => include_bytes!("../assets/map/castleCenter.png"),
+        "map/liquidLava.png" => include_bytes!("../assets/map/liquidLava.png"),
+        "sounds/jump.wav" => include_bytes!("../assets/sounds/jump.wav"),
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+/// Get the content of an embedded asset, from its name under `assets/`.
+#[cfg(not(feature = "embed_assets"))]
+pub fn get(_name: &str) -> Option<&'static [u8]> {
+    None
+}
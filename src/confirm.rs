@@ -0,0 +1,122 @@
+use std::fmt::{self, Debug, Formatter};
+
+use conrod::{self, Labelable, Positionable, Sizeable, Widget};
+use piston;
+use piston_window::{Context, G2d};
+
+use ::{GameState, Resources, StateTransition};
+use gui::Gui;
+
+widget_ids!(struct ConfirmWidgetIds { canvas, prompt, yes, no });
+
+/// Asks the player to confirm an action, such as quitting.
+///
+/// "Yes" performs the transition the dialog was created with, "No" goes back
+/// to the previous state.
+pub struct ConfirmDialog {
+    gui: Gui,
+    widget_ids: ConfirmWidgetIds,
+    prompt: String,
+    /// Transition on confirm, taken once it happens.
+    on_confirm: Option<StateTransition>,
+}
+
+impl Debug for ConfirmDialog {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ConfirmDialog({:?})", self.prompt)
+    }
+}
+
+impl ConfirmDialog {
+    pub fn new(resources: &mut Resources, prompt: &str,
+               on_confirm: StateTransition) -> ConfirmDialog {
+        let mut gui = Gui::new(resources);
+        let ids = ConfirmWidgetIds::new(gui.ui.widget_id_generator());
+
+        ConfirmDialog {
+            gui: gui,
+            widget_ids: ids,
+            prompt: prompt.to_owned(),
+            on_confirm: Some(on_confirm),
+        }
+    }
+}
+
+/// The transition for the player's answer.
+fn answer(on_confirm: &mut Option<StateTransition>, confirmed: bool) -> StateTransition {
+    if confirmed {
+        on_confirm.take().unwrap_or(StateTransition::End)
+    } else {
+        StateTransition::End
+    }
+}
+
+impl GameState for ConfirmDialog {
+    fn handle_event(&mut self, event: &piston::input::Event,
+                    resources: &mut Resources) -> StateTransition
+    {
+        self.gui.handle_event(event, resources);
+
+        let (yes, no) = {
+            let ui = &mut self.gui.ui.set_widgets();
+
+            conrod::widget::Canvas::new().floating(true).w_h(250.0, 100.0).pad(10.0).middle()
+                .set(self.widget_ids.canvas, ui);
+
+            conrod::widget::Text::new(&self.prompt)
+                .mid_top_of(self.widget_ids.canvas)
+                .set(self.widget_ids.prompt, ui);
+
+            let yes = conrod::widget::Button::new()
+                .down(15.0)
+                .w_h(80.0, 25.0)
+                .label("Yes")
+                .set(self.widget_ids.yes, ui)
+                .was_clicked();
+            let no = conrod::widget::Button::new()
+                .right(15.0)
+                .w_h(80.0, 25.0)
+                .label("No")
+                .set(self.widget_ids.no, ui)
+                .was_clicked();
+            (yes, no)
+        };
+
+        if yes || no {
+            answer(&mut self.on_confirm, yes)
+        } else {
+            StateTransition::Continue
+        }
+    }
+
+    fn update(&mut self, dt: f64, resources: &mut Resources) -> StateTransition {
+        StateTransition::Continue
+    }
+
+    fn draw(&mut self, c: Context, g: &mut G2d, alpha: f64) {
+        self.gui.draw(c, g);
+    }
+
+    fn resume(&mut self, resources: &mut Resources) {
+        self.gui.fit_window(resources);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::StateTransition;
+    use super::answer;
+
+    #[test]
+    fn test_answer() {
+        let mut on_confirm = Some(StateTransition::Quit);
+        match answer(&mut on_confirm, false) {
+            StateTransition::End => {}
+            _ => panic!("Cancelling should go back"),
+        }
+        match answer(&mut on_confirm, true) {
+            StateTransition::Quit => {}
+            _ => panic!("Confirming should quit"),
+        }
+    }
+}
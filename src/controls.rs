@@ -0,0 +1,259 @@
+//! Menu to change the keys bound to each player's actions.
+//!
+//! The bindings are saved in `controls.json` under the configuration
+//! directory, and loaded when the game starts.
+
+use std::fmt::{self, Debug, Formatter};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+
+use conrod::{self, Labelable, Positionable, Sizeable, Widget};
+use piston;
+use piston::input::{Button, Key, PressEvent};
+use piston_window::{Context, G2d};
+
+use ::{GameState, Resources, StateTransition};
+use gui::Gui;
+use input::{ACTIONS, Action, InputManager};
+use utils;
+
+/// Number of players whose keys can be changed.
+const MAX_PLAYERS: usize = 4;
+
+widget_ids!(struct ControlsWidgetIds { canvas, player, device, actions[], back });
+
+fn controls_file() -> Option<PathBuf> {
+    utils::config_dir().map(|dir| dir.join("controls.json"))
+}
+
+/// Load the saved key bindings, if any.
+pub fn load_controls(input_manager: &mut InputManager) {
+    let path = match controls_file() {
+        Some(path) => path,
+        None => return,
+    };
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        // Nothing saved yet
+        Err(_) => return,
+    };
+    match input_manager.load_bindings(BufReader::new(file)) {
+        Ok(()) => info!("Loaded controls from {}", path.display()),
+        Err(e) => warn!("Can't read controls from {}: {}", path.display(), e),
+    }
+}
+
+/// Save the key bindings, to be loaded next time.
+fn save_controls(input_manager: &InputManager) {
+    let path = match controls_file() {
+        Some(path) => path,
+        None => {
+            warn!("No configuration directory, controls won't be saved");
+            return;
+        }
+    };
+    let result = path.parent().map_or(Ok(()), |dir| fs::create_dir_all(dir))
+        .and_then(|()| File::create(&path))
+        .and_then(|file| {
+            input_manager.save_bindings(BufWriter::new(file)).map_err(From::from)
+        });
+    match result {
+        Ok(()) => info!("Controls saved to {}", path.display()),
+        Err(e) => error!("Can't save controls: {}", e),
+    }
+}
+
+/// The joystick after this one, cycling through the keyboard (`None`) and
+/// one joystick per player.
+fn next_device(device: Option<i32>) -> Option<i32> {
+    match device {
+        None => Some(0),
+        Some(id) if id + 1 < MAX_PLAYERS as i32 => Some(id + 1),
+        Some(_) => None,
+    }
+}
+
+/// Bind the next key pressed to a player's action.
+///
+/// Escape cancels instead. Returns `true` once the capture is over.
+fn capture_key(event: &piston::input::Event, player: usize, action: Action,
+               input_manager: &mut InputManager) -> bool {
+    match event.press_args() {
+        Some(Button::Keyboard(Key::Escape)) => true,
+        Some(Button::Keyboard(key)) => {
+            info!("Binding {:?} to {:?} for player {}", key, action, player + 1);
+            input_manager.set_binding(player, action, key);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Screen listing each action with its key, clicking one to change it.
+pub struct ControlsMenu {
+    gui: Gui,
+    widget_ids: ControlsWidgetIds,
+    /// The player whose keys are shown.
+    player: usize,
+    /// The action waiting for a key press, if any.
+    capturing: Option<Action>,
+}
+
+impl Debug for ControlsMenu {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ControlsMenu")
+    }
+}
+
+impl ControlsMenu {
+    pub fn new(resources: &mut Resources) -> ControlsMenu {
+        let mut gui = Gui::new(resources);
+        let mut ids = ControlsWidgetIds::new(gui.ui.widget_id_generator());
+        ids.actions.resize(ACTIONS.len(), &mut gui.ui.widget_id_generator());
+
+        ControlsMenu {
+            gui: gui,
+            widget_ids: ids,
+            player: 0,
+            capturing: None,
+        }
+    }
+}
+
+impl GameState for ControlsMenu {
+    fn handle_event(&mut self, event: &piston::input::Event,
+                    resources: &mut Resources) -> StateTransition
+    {
+        if let Some(action) = self.capturing {
+            if capture_key(event, self.player, action, &mut resources.input_manager) {
+                self.capturing = None;
+                save_controls(&resources.input_manager);
+            }
+        } else {
+            if let Some(Button::Keyboard(Key::Escape)) = event.press_args() {
+                return StateTransition::End;
+            }
+            self.gui.handle_event(event, resources);
+        }
+
+        let ui = &mut self.gui.ui.set_widgets();
+
+        conrod::widget::Canvas::new().floating(true).w_h(220.0, 325.0).pad(10.0).middle()
+            .set(self.widget_ids.canvas, ui);
+
+        if conrod::widget::Button::new()
+            .mid_top_of(self.widget_ids.canvas)
+            .w_h(200.0, 25.0)
+            .label(&format!("Player {}", self.player + 1))
+            .set(self.widget_ids.player, ui)
+            .was_clicked()
+        {
+            self.player = (self.player + 1) % MAX_PLAYERS;
+        }
+
+        let device = resources.input_manager.device(self.player);
+        let label = match device {
+            Some(id) => format!("Controller {}", id),
+            None => "Keyboard".to_owned(),
+        };
+        if conrod::widget::Button::new()
+            .down(10.0)
+            .w_h(200.0, 25.0)
+            .label(&label)
+            .set(self.widget_ids.device, ui)
+            .was_clicked()
+        {
+            resources.input_manager.set_device(self.player, next_device(device));
+        }
+
+        for (i, &action) in ACTIONS.iter().enumerate() {
+            let label = if self.capturing == Some(action) {
+                format!("{:?}: press a key", action)
+            } else {
+                match resources.input_manager.binding(self.player, action) {
+                    Some(key) => format!("{:?}: {:?}", action, key),
+                    None => format!("{:?}: -", action),
+                }
+            };
+            if conrod::widget::Button::new()
+                .down(10.0)
+                .w_h(200.0, 25.0)
+                .label(&label)
+                .set(self.widget_ids.actions[i], ui)
+                .was_clicked()
+            {
+                self.capturing = Some(action);
+            }
+        }
+
+        if conrod::widget::Button::new()
+            .down(10.0)
+            .w_h(80.0, 25.0)
+            .label("Back")
+            .set(self.widget_ids.back, ui)
+            .was_clicked()
+        {
+            StateTransition::End
+        } else {
+            StateTransition::Continue
+        }
+    }
+
+    fn update(&mut self, dt: f64, resources: &mut Resources) -> StateTransition {
+        StateTransition::Continue
+    }
+
+    fn draw(&mut self, c: Context, g: &mut G2d, alpha: f64) {
+        self.gui.draw(c, g);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use piston::input::{Button, ButtonArgs, ButtonState, Event, Input, Key};
+
+    use input::{Action, InputManager};
+    use super::{capture_key, next_device};
+
+    fn press(key: Key) -> Event {
+        Event::Input(Input::Button(ButtonArgs {
+            state: ButtonState::Press,
+            button: Button::Keyboard(key),
+            scancode: None,
+        }))
+    }
+
+    #[test]
+    fn test_capture_key() {
+        let mut input_manager = InputManager::new(2);
+
+        // Other events are ignored
+        let release = Event::Input(Input::Button(ButtonArgs {
+            state: ButtonState::Release,
+            button: Button::Keyboard(Key::X),
+            scancode: None,
+        }));
+        assert!(!capture_key(&release, 1, Action::Jump, &mut input_manager));
+        assert_eq!(input_manager.binding(1, Action::Jump), None);
+
+        assert!(capture_key(&press(Key::X), 1, Action::Jump, &mut input_manager));
+        assert_eq!(input_manager.binding(1, Action::Jump), Some(Key::X));
+        assert_eq!(input_manager.binding(0, Action::Jump), None);
+
+        // Escape cancels
+        assert!(capture_key(&press(Key::Escape), 1, Action::Jump, &mut input_manager));
+        assert_eq!(input_manager.binding(1, Action::Jump), Some(Key::X));
+    }
+
+    #[test]
+    fn test_next_device() {
+        let mut device = None;
+        let mut seen = Vec::new();
+        for _ in 0..5 {
+            device = next_device(device);
+            seen.push(device);
+        }
+        assert_eq!(seen, [Some(0), Some(1), Some(2), Some(3), None]);
+    }
+}
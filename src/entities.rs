@@ -1,47 +1,796 @@
-use std::mem::swap;
+use std::f64::consts::PI;
+use std::fmt;
 
-use ::Resources;
-use sprites::Sprite;
-use world::{EntityLogic, EntityPhysics, WorldView};
+use rand::Rng;
+use serde_json;
 
+use sprites::{DrawEffect, NO_TINT, Sprite};
+use vecmath::*;
+use world::{Effect, Entity, EntityLogic, EntityPhysics, EntityRegistry, HitTarget, Map,
+            Physics, SimContext, Tile, TileEntityFactory, TileType, WorldEvent, WorldView};
+
+/// Room that must be free of walls for a spawned entity, around its feet.
+const SPAWN_CLEARANCE: [f64; 2] = [0.7, 1.3];
+/// How far (in tiles) to look for a clear spot if the spawn point is blocked.
+const SPAWN_SEARCH_DIST: i32 = 3;
+/// Speed of bullets, in tiles per second.
+const BULLET_SPEED: f64 = 15.0;
+/// Time after which a bullet that didn't hit anything disappears.
+const BULLET_LIFETIME: f64 = 2.0;
+/// Bullets are drawn over characters.
+const BULLET_Z_ORDER: i32 = 20;
+/// Size of the box with which bullets hit entities.
+const BULLET_SIZE: [f64; 2] = [0.2, 0.2];
+/// Health taken from the entities hit by a bullet.
+const BULLET_DAMAGE: f64 = 0.5;
+/// Distance from which entities make doors open.
+const DOOR_RANGE: f64 = 1.5;
+/// Speed of moving platforms, in tiles per second.
+const PLATFORM_SPEED: f64 = 2.0;
+/// Width and thickness of moving platforms.
+const PLATFORM_SIZE: [f64; 2] = [2.0, 0.5];
+/// How far the platforms placed on maps travel, to the right.
+const PLATFORM_TRAVEL: f64 = 4.0;
+/// Width and height of enemies.
+const ENEMY_SIZE: [f64; 2] = [0.8, 0.8];
+/// Speed of enemies on patrol, in tiles per second.
+const ENEMY_PATROL_SPEED: f64 = 1.5;
+/// Speed of enemies chasing a character.
+const ENEMY_CHASE_SPEED: f64 = 3.0;
+/// How far enemies placed on maps walk to each side of their position.
+const ENEMY_PATROL_DIST: f64 = 3.0;
+/// Distance from which enemies placed on maps notice characters.
+const ENEMY_RANGE: f64 = 5.0;
+/// Small gap kept between enemies and the ground, for wall checks.
+const ENEMY_MARGIN: f64 = 0.05;
+/// Highest drop enemies on patrol walk down, they turn around at higher
+/// ledges.
+const ENEMY_MAX_DROP: f64 = 1.0;
+/// Health per second lost by characters touching an enemy.
+const ENEMY_CONTACT_DAMAGE: f64 = 0.5;
+/// Size of items, that entities touching them pick up.
+const PICKUP_SIZE: [f64; 2] = [0.5, 0.5];
+/// Health restored by the health pickups placed on maps.
+const HEAL_AMOUNT: f64 = 0.5;
+/// Particles are drawn over everything else.
+const PARTICLE_Z_ORDER: i32 = 30;
+
+/// Find a tile entity type from its name, as used in map files.
+pub fn tile_entity_factory(name: &str) -> Option<TileEntityFactory> {
+    match name {
+        "f.door" => Some(&Door::create),
+        _ => None,
+    }
+}
+
+/// The entity types that can be placed on maps.
+///
+/// Characters are not in there, they are created by the game for each player.
+pub fn entity_registry() -> EntityRegistry {
+    let mut registry = EntityRegistry::new();
+    registry.register("f.spawn", |pos, _| Some(Entity::new(*pos, Spawn::new())));
+    registry.register("f.platform", |pos, _| Some(MovingPlatform::horizontal(*pos)));
+    registry.register("f.enemy", |pos, _| Some(Enemy::patrol(*pos)));
+    registry.register("f.heal", |pos, _| Some(Collectible::new(*pos, Effect::Heal(HEAL_AMOUNT))));
+    // Platforms and enemies have moved away from where they started
+    registry.register_restore("f.platform", |pos, state| MovingPlatform::restore(*pos, state));
+    registry.register_restore("f.enemy", |pos, state| Enemy::restore(*pos, state));
+    registry
+}
+
+/// Pick which of the waiting spawnables gets to spawn first.
+fn first_spawnable<R: Rng>(rng: &mut R, count: usize) -> usize {
+    if count <= 1 {
+        0
+    } else {
+        rng.gen_range(0, count)
+    }
+}
+
+/// Where the waiting spawnables come in, its position being their feet.
 #[derive(Debug)]
 pub struct Spawn {
+    /// Whether it was blocked last frame, to only warn once about it.
+    blocked: bool,
 }
 
 impl Spawn {
     pub fn new() -> Spawn {
-        Spawn {}
+        Spawn { blocked: false }
     }
 }
 
 impl EntityLogic for Spawn {
+    fn type_id(&self) -> Option<&str> {
+        Some("f.spawn")
+    }
+
     fn update(&mut self, entity: &mut EntityPhysics, dt: f64,
-              world: &mut WorldView, resources: &Resources, sprite: &mut Option<Sprite>) -> bool {
-        // Move out all spawnables to avoid multiple borrows from Game
-        let mut spawnables = Vec::new();
-        swap(&mut spawnables, &mut world.spawnables);
+              world: &mut WorldView, ctx: &SimContext, sprite: &mut Option<Sprite>) -> bool {
+        // Don't spawn into a wall, find a clear spot nearby or wait
+        let pos = match world.map.nearest_clear(entity.pos, SPAWN_CLEARANCE,
+                                                SPAWN_SEARCH_DIST) {
+            Some(pos) => pos,
+            None => {
+                if !self.blocked {
+                    warn!("Spawn point @ {:?} is blocked", entity.pos);
+                    self.blocked = true;
+                }
+                return true;
+            }
+        };
+        self.blocked = false;
+        // Start from a random one, so players don't always come in order
+        let first = first_spawnable(&mut *world.rng, world.spawnables.len());
+        world.spawnables.rotate_left(first);
         // Loop on spawnables, spawning at most one entity
-        let mut spawned_one = false;
-        let mut spawnables = spawnables.into_iter().filter_map(|mut spawnable| {
-            if spawned_one {
-                Some(spawnable)
+        let mut i = 0;
+        while i < world.spawnables.len() {
+            let (keep, spawned) = world.spawnables[i].spawn(&pos);
+            if keep {
+                i += 1;
+            } else {
+                world.spawnables.remove(i);
+            }
+            if let Some(new_entity) = spawned {
+                info!("Spawning an entity @ {:?}", pos);
+                world.spawn_entity(new_entity);
+                break;
+            }
+        }
+
+        true
+    }
+}
+
+/// A projectile flying in a straight line until it hits a wall.
+#[derive(Debug)]
+pub struct Bullet {
+    lifetime: f64,
+}
+
+impl Bullet {
+    /// Create a bullet entity, going left or right.
+    pub fn fire(pos: Vector2, facing_left: bool) -> Entity {
+        Bullet::fire_toward(pos, [if facing_left { -1.0 } else { 1.0 }, 0.0])
+    }
+
+    /// Create a bullet entity, going in the given direction (normalized).
+    pub fn fire_toward(pos: Vector2, dir: Vector2) -> Entity {
+        let mut entity = Entity::new(pos, Bullet { lifetime: BULLET_LIFETIME });
+        entity.physics.speed = vec2_scale(dir, BULLET_SPEED);
+        entity.physics.gravity_scale = 0.0;
+        entity
+    }
+
+    /// Move the bullet for one frame, returns false once it's gone.
+    fn step(&mut self, entity: &mut EntityPhysics, dt: f64, map: &Map) -> bool {
+        self.lifetime -= dt;
+        if self.lifetime <= 0.0 {
+            return false;
+        }
+        let to = vec2_add(entity.pos, vec2_scale(entity.speed, dt));
+        // Disappear when hitting walls or leaving the map
+        if map.cast(entity.pos, to).is_some() {
+            return false;
+        }
+        entity.pos = to;
+        map.tilef(entity.pos.x(), entity.pos.y()).is_some()
+    }
+}
+
+impl EntityLogic for Bullet {
+    fn update(&mut self, entity: &mut EntityPhysics, dt: f64,
+              world: &mut WorldView, ctx: &SimContext, sprite: &mut Option<Sprite>) -> bool {
+        if !self.step(entity, dt, world.map) {
+            return false;
+        }
+        // Hit the first entity in the way, and disappear
+        let area = Aabb::from_center_size(entity.pos, BULLET_SIZE);
+        let target = world.entities.iter()
+            .find(|e| e.logic.hitbox(e.physics.pos).map_or(false, |h| h.intersects(&area)))
+            .map(|e| e.id);
+        match target {
+            Some(target) => {
+                world.emit(WorldEvent::Damage { target: target, amount: BULLET_DAMAGE });
+                false
+            }
+            None => true,
+        }
+    }
+
+    fn z_order(&self) -> i32 {
+        BULLET_Z_ORDER
+    }
+}
+
+/// Creates a burst of particles at its position, then goes away.
+///
+/// The particles fly off in random directions and fall, disappearing after
+/// their lifetime. They don't collide with anything.
+pub struct ParticleEmitter {
+    count: usize,
+    /// Smallest and largest initial speed of the particles.
+    speed: (f64, f64),
+    /// Time the particles last, in seconds.
+    lifetime: f64,
+    sprite: Option<Sprite>,
+    /// Color the sprite is tinted with.
+    color: [f32; 4],
+}
+
+impl fmt::Debug for ParticleEmitter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ParticleEmitter(count={})", self.count)
+    }
+}
+
+impl ParticleEmitter {
+    pub fn new(pos: Vector2, count: usize, speed: (f64, f64), lifetime: f64,
+               sprite: Option<Sprite>, color: [f32; 4]) -> Entity {
+        let mut entity = Entity::new(pos, ParticleEmitter {
+            count: count,
+            speed: speed,
+            lifetime: lifetime,
+            sprite: sprite,
+            color: color,
+        });
+        entity.physics.gravity_scale = 0.0;
+        entity
+    }
+
+    /// Create the particles, from the world's randomness.
+    fn emit<R: Rng>(&self, pos: Vector2, rng: &mut R) -> Vec<Entity> {
+        (0..self.count).map(|_| {
+            let angle = rng.gen_range(0.0, 2.0 * PI);
+            let speed = if self.speed.1 > self.speed.0 {
+                rng.gen_range(self.speed.0, self.speed.1)
             } else {
-                let (keep, spawned) = spawnable.spawn(&entity.pos);
-                if let Some(new_entity) = spawned {
-                    info!("Spawning an entity @ {:?}", entity.pos);
-                    world.entities.push(new_entity);
-                    spawned_one = true;
+                self.speed.0
+            };
+            let mut particle = Entity::new(pos, Particle);
+            particle.physics.speed = [angle.cos() * speed, angle.sin() * speed];
+            particle.ttl = Some(self.lifetime);
+            particle.sprite = self.sprite.clone();
+            if self.color != NO_TINT {
+                particle.effect = Some(DrawEffect::Flash(self.color));
+            }
+            particle
+        }).collect()
+    }
+}
+
+impl EntityLogic for ParticleEmitter {
+    fn update(&mut self, entity: &mut EntityPhysics, dt: f64,
+              world: &mut WorldView, ctx: &SimContext, sprite: &mut Option<Sprite>) -> bool {
+        for particle in self.emit(entity.pos, &mut *world.rng) {
+            world.spawn_entity(particle);
+        }
+        false
+    }
+}
+
+/// A particle created by a `ParticleEmitter`, removed by its `ttl`.
+#[derive(Debug)]
+struct Particle;
+
+impl EntityLogic for Particle {
+    fn update(&mut self, entity: &mut EntityPhysics, dt: f64,
+              world: &mut WorldView, ctx: &SimContext, sprite: &mut Option<Sprite>) -> bool {
+        entity.apply_gravity(world.physics.gravity, dt);
+        entity.pos = vec2_add(entity.pos, vec2_scale(entity.speed, dt));
+        true
+    }
+
+    fn z_order(&self) -> i32 {
+        PARTICLE_Z_ORDER
+    }
+}
+
+/// A platform going back and forth between two points, carrying characters.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MovingPlatform {
+    from: Vector2,
+    to: Vector2,
+    /// Whether it is going towards `to`, else back towards `from`.
+    forward: bool,
+}
+
+impl MovingPlatform {
+    pub fn new(from: Vector2, to: Vector2) -> Entity {
+        MovingPlatform::entity(from, MovingPlatform {
+            from: from,
+            to: to,
+            forward: true,
+        })
+    }
+
+    /// Create a platform going right from its position, as placed on maps.
+    pub fn horizontal(pos: Vector2) -> Entity {
+        MovingPlatform::new(pos, [pos.x() + PLATFORM_TRAVEL, pos.y()])
+    }
+
+    /// Create a platform from its position and state in a saved game.
+    pub fn restore(pos: Vector2, state: &serde_json::Value) -> Option<Entity> {
+        match serde_json::from_value(state.clone()) {
+            Ok(platform) => Some(MovingPlatform::entity(pos, platform)),
+            Err(e) => {
+                warn!("Invalid platform state: {}", e);
+                None
+            }
+        }
+    }
+
+    fn entity(pos: Vector2, platform: MovingPlatform) -> Entity {
+        let mut entity = Entity::new(pos, platform);
+        entity.physics.gravity_scale = 0.0;
+        entity
+    }
+
+    /// Move the platform for one frame, its speed being what it moved.
+    fn step(&mut self, entity: &mut EntityPhysics, dt: f64) {
+        let old = entity.pos;
+        let target = if self.forward { self.to } else { self.from };
+        let delta = vec2_sub(target, old);
+        let dist = vec2_len(delta);
+        let travel = PLATFORM_SPEED * dt;
+        if travel >= dist {
+            entity.pos = target;
+            self.forward = !self.forward;
+        } else {
+            entity.pos = vec2_add(old, vec2_scale(delta, travel / dist));
+        }
+        entity.speed = vec2_scale(vec2_sub(entity.pos, old), 1.0 / dt);
+    }
+}
+
+impl EntityLogic for MovingPlatform {
+    fn type_id(&self) -> Option<&str> {
+        Some("f.platform")
+    }
+
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or(serde_json::Value::Null)
+    }
+
+    fn platform_size(&self) -> Option<Vector2> {
+        Some(PLATFORM_SIZE)
+    }
+
+    fn update(&mut self, entity: &mut EntityPhysics, dt: f64,
+              world: &mut WorldView, ctx: &SimContext, sprite: &mut Option<Sprite>) -> bool {
+        // TODO: Sprite
+        self.step(entity, dt);
+        true
+    }
+}
+
+/// An enemy walking back and forth, that chases characters coming close
+/// into its sight.
+///
+/// Its position is at its feet, in the middle of the bottom of its hitbox.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Enemy {
+    /// Bounds of the patrol, along X.
+    min_x: f64,
+    max_x: f64,
+    /// Distance from which characters get noticed.
+    range: f64,
+    /// Direction of the patrol, -1 (left) or 1 (right).
+    dir: f64,
+}
+
+impl Enemy {
+    pub fn new(pos: Vector2, min_x: f64, max_x: f64, range: f64) -> Entity {
+        Entity::new(pos, Enemy {
+            min_x: min_x,
+            max_x: max_x,
+            range: range,
+            dir: 1.0,
+        })
+    }
+
+    /// Create an enemy patrolling around its position, as placed on maps.
+    pub fn patrol(pos: Vector2) -> Entity {
+        Enemy::new(pos, pos.x() - ENEMY_PATROL_DIST, pos.x() + ENEMY_PATROL_DIST, ENEMY_RANGE)
+    }
+
+    /// Create an enemy from its position and state in a saved game.
+    pub fn restore(pos: Vector2, state: &serde_json::Value) -> Option<Entity> {
+        match serde_json::from_value::<Enemy>(state.clone()) {
+            Ok(enemy) => Some(Entity::new(pos, enemy)),
+            Err(e) => {
+                warn!("Invalid enemy state: {}", e);
+                None
+            }
+        }
+    }
+
+    /// The box an enemy takes up, standing on its position.
+    fn bounds(pos: Vector2) -> Aabb {
+        Aabb::new([pos.x() - ENEMY_SIZE[0] / 2.0, pos.y()],
+                  [pos.x() + ENEMY_SIZE[0] / 2.0, pos.y() + ENEMY_SIZE[1]])
+    }
+
+    /// Move for one frame, towards the target if any, else along the patrol.
+    fn step(&mut self, entity: &mut EntityPhysics, dt: f64, map: &Map, physics: &Physics,
+            target: Option<Vector2>) {
+        let (dir, speed) = match target {
+            Some(target) => {
+                let dx = target.x() - entity.pos.x();
+                let dir = if dx.abs() < ENEMY_SIZE[0] / 2.0 { 0.0 } else { dx.signum() };
+                (dir, ENEMY_CHASE_SPEED)
+            }
+            None => {
+                if entity.pos.x() <= self.min_x {
+                    self.dir = 1.0;
+                } else if entity.pos.x() >= self.max_x {
+                    self.dir = -1.0;
                 }
-                if keep {
-                    Some(spawnable)
-                } else {
-                    None
+                // Don't walk off ledges
+                let ahead = [entity.pos.x() + self.dir * (ENEMY_SIZE[0] / 2.0 + ENEMY_MARGIN),
+                             entity.pos.y() + ENEMY_MARGIN];
+                if entity.speed.y() == 0.0 &&
+                    map.ground_below(ahead, ENEMY_MAX_DROP + ENEMY_MARGIN).is_none()
+                {
+                    self.dir = -self.dir;
                 }
+                (self.dir, ENEMY_PATROL_SPEED)
             }
-        }).collect::<Vec<_>>();
-        // Put back the remaining spawnables
-        swap(&mut spawnables, &mut world.spawnables);
+        };
+
+        // Walk, stopping at walls
+        let half = ENEMY_SIZE[0] / 2.0;
+        let x = entity.pos.x() + dir * speed * dt;
+        let y = entity.pos.y();
+        if map.area_clear([x - half, y + ENEMY_MARGIN], [x + half, y + ENEMY_SIZE[1]]) {
+            entity.pos[0] = x;
+            entity.speed[0] = dir * speed;
+        } else {
+            entity.speed[0] = 0.0;
+            if target.is_none() {
+                self.dir = -self.dir;
+            }
+        }
+
+        // Fall, landing on the ground
+        entity.apply_gravity(physics.gravity, dt);
+        let new_y = y + entity.speed.y() * dt;
+        match map.ground_at(entity.pos.x(), new_y) {
+            Some((ground, _)) if entity.speed.y() <= 0.0 && ground >= new_y &&
+                ground <= y + ENEMY_MARGIN => {
+                entity.pos[1] = ground;
+                entity.speed[1] = 0.0;
+            }
+            _ => entity.pos[1] = new_y,
+        }
+    }
+}
+
+impl EntityLogic for Enemy {
+    fn type_id(&self) -> Option<&str> {
+        Some("f.enemy")
+    }
 
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or(serde_json::Value::Null)
+    }
+
+    fn hitbox(&self, pos: Vector2) -> Option<Aabb> {
+        Some(Enemy::bounds(pos))
+    }
+
+    fn update(&mut self, entity: &mut EntityPhysics, dt: f64,
+              world: &mut WorldView, ctx: &SimContext, sprite: &mut Option<Sprite>) -> bool {
+        // Chase the closest character in range, that it can see: not behind
+        // walls or other entities
+        let pos = entity.pos;
+        let eye = [pos.x(), pos.y() + ENEMY_SIZE[1] / 2.0];
+        let target = world.neighbors(pos, self.range).into_iter()
+            .filter_map(|id| world.entities.iter().find(|e| e.id == id))
+            .filter(|e| e.logic.type_id() == Some("f.character"))
+            .filter(|e| {
+                let sight = vec2_sub(e.physics.pos, eye);
+                world.raycast(eye, sight, vec2_len(sight), true)
+                    .map_or(false, |hit| hit.target == HitTarget::Entity(e.id))
+            })
+            .map(|e| e.physics.pos)
+            .fold(None, |closest: Option<Vector2>, p| match closest {
+                Some(c) if c.distance(pos) <= p.distance(pos) => Some(c),
+                _ => Some(p),
+            });
+
+        self.step(entity, dt, world.map, world.physics, target);
+
+        // Hurt the characters touching it
+        let hitbox = Enemy::bounds(entity.pos);
+        let touching: Vec<_> = world.entities.iter()
+            .filter(|e| e.logic.type_id() == Some("f.character"))
+            .filter(|e| e.logic.hitbox(e.physics.pos).map_or(false, |h| h.intersects(&hitbox)))
+            .map(|e| e.id)
+            .collect();
+        for target in touching {
+            world.emit(WorldEvent::Damage { target: target, amount: ENEMY_CONTACT_DAMAGE * dt });
+        }
         true
     }
 }
+
+/// An item floating in place, that gets used by the first entity to touch it.
+///
+/// The item doesn't change its entity's sprite, whoever creates it can set
+/// one; the items placed on maps have none since the `EntityRegistry` doesn't
+/// load sprite sheets.
+#[derive(Debug)]
+pub struct Collectible {
+    effect: Effect,
+}
+
+impl Collectible {
+    pub fn new(pos: Vector2, effect: Effect) -> Entity {
+        let mut entity = Entity::new(pos, Collectible { effect: effect });
+        entity.physics.gravity_scale = 0.0;
+        entity
+    }
+
+    /// Give the item to an entity close enough that takes it, if any.
+    ///
+    /// Entities without a hitbox need their position close enough.
+    fn pick_up(&self, pos: Vector2, entities: &mut [Entity]) -> bool {
+        let area = Aabb::from_center_size(pos, PICKUP_SIZE);
+        for e in entities.iter_mut() {
+            let near = match e.logic.hitbox(e.physics.pos) {
+                Some(hitbox) => hitbox.intersects(&area),
+                None => area.contains(e.physics.pos),
+            };
+            if near && e.logic.collect(&mut e.physics, &self.effect) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl EntityLogic for Collectible {
+    fn type_id(&self) -> Option<&str> {
+        match self.effect {
+            Effect::Heal(_) => Some("f.heal"),
+        }
+    }
+
+    fn update(&mut self, entity: &mut EntityPhysics, dt: f64,
+              world: &mut WorldView, ctx: &SimContext, sprite: &mut Option<Sprite>) -> bool {
+        !self.pick_up(entity.pos, world.entities)
+    }
+}
+
+/// A door, opening when entities come close.
+///
+/// This swaps the tile between the closed door and the open one, given by
+/// the `open_tile` of the closed door's tile type.
+#[derive(Debug)]
+pub struct Door {
+    pos: (usize, usize),
+    closed: Tile,
+    open: Tile,
+    is_open: bool,
+}
+
+impl Door {
+    /// A closed door on a tile, if its tile type has an open tile.
+    fn new(tile: Tile, tile_type: &TileType, pos: (usize, usize)) -> Option<Door> {
+        match tile_type.open_tile {
+            Some(open) => Some(Door {
+                pos: pos,
+                closed: tile,
+                open: open,
+                is_open: false,
+            }),
+            None => {
+                warn!("Door @ {:?} has no open_tile", pos);
+                None
+            }
+        }
+    }
+
+    fn create(tile: Tile, tile_type: &TileType, pos: (usize, usize)) -> Option<Entity> {
+        Door::new(tile, tile_type, pos).map(|door| {
+            let center = [pos.0 as f64 + 0.5, pos.1 as f64 + 0.5];
+            let mut entity = Entity::new(center, door);
+            entity.physics.gravity_scale = 0.0;
+            entity
+        })
+    }
+
+    /// Open or close the door, changing its tile.
+    fn set_open(&mut self, open: bool, map: &mut Map) {
+        if open == self.is_open {
+            return;
+        }
+        let tile = if open { self.open } else { self.closed };
+        if map.set_tile(self.pos.0, self.pos.1, tile) {
+            self.is_open = open;
+        } else {
+            warn!("Door @ {:?} has no tile type {}", self.pos, tile);
+        }
+    }
+}
+
+impl EntityLogic for Door {
+    fn update(&mut self, entity: &mut EntityPhysics, dt: f64,
+              world: &mut WorldView, ctx: &SimContext, sprite: &mut Option<Sprite>) -> bool {
+        let near = world.entities.iter().any(|e| {
+            e.physics.pos.distance(entity.pos) < DOOR_RANGE
+        });
+        self.set_open(near, world.map);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use vecmath::*;
+    use world::{EntityLogic, Physics, Tile, grid_map, seeded_rng};
+    use super::{Bullet, Door, Enemy, MovingPlatform, first_spawnable};
+
+    #[test]
+    fn test_spawn_order() {
+        let sequence = |seed| {
+            let mut rng = seeded_rng(seed);
+            (0..20).map(|_| first_spawnable(&mut rng, 4)).collect::<Vec<_>>()
+        };
+        let first = sequence(42);
+        assert_eq!(first, sequence(42));
+        assert!(first.iter().all(|&i| i < 4));
+        assert_ne!(first, sequence(43));
+
+        let mut rng = seeded_rng(42);
+        assert_eq!(first_spawnable(&mut rng, 1), 0);
+        assert_eq!(first_spawnable(&mut rng, 0), 0);
+    }
+
+    #[test]
+    fn test_bullet() {
+        let map = grid_map(&[
+            "#.........",
+            "#.........",
+        ]);
+        let mut entity = Bullet::fire([5.0, 1.5], true);
+        assert!(entity.physics.speed.x() < 0.0);
+        assert_eq!(entity.physics.speed.y(), 0.0);
+        assert!(Bullet::fire([5.0, 1.5], false).physics.speed.x() > 0.0);
+
+        // Goes left until the wall
+        let mut bullet = Bullet { lifetime: 2.0 };
+        let mut frames = 0;
+        while bullet.step(&mut entity.physics, 0.01, &map) {
+            frames += 1;
+            assert!(frames < 100);
+        }
+        assert!(entity.physics.pos.x() >= 1.0);
+        assert!(entity.physics.pos.x() < 1.2);
+
+        // Times out in the open
+        let mut entity = Bullet::fire([5.0, 1.5], false);
+        entity.physics.speed = [0.0, 0.0];
+        let mut bullet = Bullet { lifetime: 0.5 };
+        for _ in 0..4 {
+            assert!(bullet.step(&mut entity.physics, 0.1, &map));
+        }
+        assert!(!bullet.step(&mut entity.physics, 0.2, &map));
+    }
+
+    #[test]
+    fn test_moving_platform() {
+        let mut entity = MovingPlatform::new([1.0, 2.0], [2.0, 2.0]);
+        let mut platform = MovingPlatform {
+            from: [1.0, 2.0],
+            to: [2.0, 2.0],
+            forward: true,
+        };
+        platform.step(&mut entity.physics, 0.25);
+        assert_eq!(entity.physics.pos, [1.5, 2.0]);
+        assert_eq!(entity.physics.speed, [2.0, 0.0]);
+        // Stops at the end and comes back
+        platform.step(&mut entity.physics, 0.5);
+        assert_eq!(entity.physics.pos, [2.0, 2.0]);
+        assert_eq!(entity.physics.speed, [1.0, 0.0]);
+        platform.step(&mut entity.physics, 0.25);
+        assert_eq!(entity.physics.pos, [1.5, 2.0]);
+        assert_eq!(entity.physics.speed, [-2.0, 0.0]);
+
+        // Saved and restored
+        let state = platform.save_state();
+        let restored = MovingPlatform::restore([1.5, 2.0], &state).unwrap();
+        assert_eq!(restored.physics.pos, [1.5, 2.0]);
+        assert_eq!(restored.logic.save_state(), state);
+        assert!(MovingPlatform::restore([1.5, 2.0], &Value::Null).is_none());
+    }
+
+    #[test]
+    fn test_enemy() {
+        let map = grid_map(&[
+            "#.........#",
+            "###########",
+        ]);
+        let physics = Physics::default();
+        let mut entity = Enemy::new([5.0, 1.0], 3.0, 7.0, 4.0);
+        let mut enemy = Enemy { min_x: 3.0, max_x: 7.0, range: 4.0, dir: 1.0 };
+
+        // Chases a character to its left, on the ground
+        for _ in 0..10 {
+            enemy.step(&mut entity.physics, 0.1, &map, &physics, Some([2.0, 1.0]));
+        }
+        assert!(entity.physics.pos.x() < 3.0, "{:?}", entity.physics.pos);
+        assert_eq!(entity.physics.pos.y(), 1.0);
+
+        // Stops at the wall
+        for _ in 0..10 {
+            enemy.step(&mut entity.physics, 0.1, &map, &physics, Some([0.5, 1.0]));
+        }
+        assert!(entity.physics.pos.x() > 1.3, "{:?}", entity.physics.pos);
+
+        // Patrols back within its bounds
+        for _ in 0..100 {
+            enemy.step(&mut entity.physics, 0.1, &map, &physics, None);
+            assert!(entity.physics.pos.x() <= 7.0 + 0.15);
+        }
+        assert!(entity.physics.pos.x() >= 3.0 - 0.15);
+
+        // Falls to the ground
+        let mut entity = Enemy::new([5.0, 1.5], 3.0, 7.0, 4.0);
+        for _ in 0..10 {
+            enemy.step(&mut entity.physics, 0.1, &map, &physics, None);
+        }
+        assert_eq!(entity.physics.pos.y(), 1.0);
+
+        // Turns around at ledges on patrol
+        let map = grid_map(&[
+            "......",
+            "###...",
+            "......",
+            "......",
+        ]);
+        let mut entity = Enemy::new([1.5, 3.0], 0.0, 10.0, 0.0);
+        let mut enemy = Enemy { min_x: 0.0, max_x: 10.0, range: 0.0, dir: 1.0 };
+        for _ in 0..100 {
+            enemy.step(&mut entity.physics, 0.1, &map, &physics, None);
+            assert!(entity.physics.pos.x() < 3.0);
+        }
+        assert_eq!(entity.physics.pos.y(), 3.0);
+
+        // Saved and restored
+        let state = enemy.save_state();
+        let restored = Enemy::restore([5.0, 1.0], &state).unwrap();
+        assert_eq!(restored.logic.save_state(), state);
+    }
+
+    #[test]
+    fn test_door() {
+        let mut map = grid_map(&[
+            "...",
+            ".D.",
+        ]);
+        let (empty, closed) = (map.tiles()[0], map.tiles()[1]);
+        // Only tiles with an open tile make doors
+        assert!(Door::new(empty, map.tile(0, 0).unwrap(), (0, 0)).is_none());
+
+        // Open tile that doesn't exist: can't open
+        let mut door = Door { pos: (1, 0), closed: closed, open: Tile::max_value(),
+                              is_open: false };
+        door.set_open(true, &mut map);
+        assert!(!door.is_open);
+        assert!(map.tile(1, 0).unwrap().collide);
+
+        let mut door = Door::new(closed, map.tile(1, 0).unwrap(), (1, 0)).unwrap();
+        assert_eq!(door.open, empty);
+        door.set_open(true, &mut map);
+        assert!(door.is_open);
+        assert!(!map.tile(1, 0).unwrap().collide);
+        door.set_open(false, &mut map);
+        assert!(!door.is_open);
+        assert!(map.tile(1, 0).unwrap().collide);
+    }
+}
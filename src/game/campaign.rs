@@ -0,0 +1,70 @@
+use std::path::{Path, PathBuf};
+
+use world::{MapFactory, MapLoadError};
+
+/// A sequence of levels, played one after the other.
+#[derive(Debug)]
+pub struct Campaign {
+    levels: Vec<PathBuf>,
+    current: usize,
+}
+
+impl Campaign {
+    pub fn new(levels: Vec<PathBuf>) -> Campaign {
+        Campaign {
+            levels: levels,
+            current: 0,
+        }
+    }
+
+    /// Index of the level being played.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// All the levels, in order.
+    pub fn levels(&self) -> &[PathBuf] {
+        &self.levels
+    }
+
+    /// Path of the map file for the level being played.
+    pub fn level(&self) -> &Path {
+        &self.levels[self.current]
+    }
+
+    /// Load the map for the level being played.
+    pub fn load_map(&self) -> Result<MapFactory, MapLoadError> {
+        MapFactory::from_file(&self.levels[self.current])
+    }
+
+    /// Move on to the next level, returns false if this was the last one.
+    pub fn advance(&mut self) -> bool {
+        if self.current + 1 < self.levels.len() {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::Campaign;
+
+    #[test]
+    fn test_progression() {
+        let mut campaign = Campaign::new(vec![
+            PathBuf::from("assets/maps/test.tmx"),
+            PathBuf::from("assets/maps/test2.tmx"),
+        ]);
+        assert_eq!(campaign.load_map().unwrap().width, 4);
+        assert!(campaign.advance());
+        assert_eq!(campaign.current(), 1);
+        assert_eq!(campaign.load_map().unwrap().width, 2);
+        assert!(!campaign.advance());
+        assert_eq!(campaign.current(), 1);
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt::{self, Debug, Formatter};
+
+use conrod::{self, Labelable, Positionable, Sizeable, Widget};
+use piston;
+use piston_window::{Context, G2d};
+
+use ::{GameState, Resources, StateTransition};
+use gui::Gui;
+
+widget_ids!(struct CreditsWidgetIds { canvas, title, text, done });
+
+/// Screen shown once the last level of a campaign is complete.
+pub struct Credits {
+    gui: Gui,
+    widget_ids: CreditsWidgetIds,
+}
+
+impl Debug for Credits {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Credits")
+    }
+}
+
+impl Credits {
+    pub fn new(resources: &mut Resources) -> Credits {
+        let mut gui = Gui::new(resources);
+        let ids = CreditsWidgetIds::new(gui.ui.widget_id_generator());
+
+        Credits {
+            gui: gui,
+            widget_ids: ids,
+        }
+    }
+}
+
+impl GameState for Credits {
+    fn handle_event(&mut self, event: &piston::input::Event,
+                    resources: &mut Resources) -> StateTransition
+    {
+        self.gui.handle_event(event, resources);
+
+        let ui = &mut self.gui.ui.set_widgets();
+
+        conrod::widget::Canvas::new().floating(true).w_h(300.0, 160.0).pad(10.0).middle()
+            .set(self.widget_ids.canvas, ui);
+
+        conrod::widget::Text::new("Congratulations!")
+            .mid_top_of(self.widget_ids.canvas)
+            .font_size(24)
+            .set(self.widget_ids.title, ui);
+        conrod::widget::Text::new("fluffy-fiesta, by Remi Rampin")
+            .down(15.0)
+            .set(self.widget_ids.text, ui);
+
+        if conrod::widget::Button::new()
+            .down(15.0)
+            .w_h(80.0, 25.0)
+            .label("Done")
+            .set(self.widget_ids.done, ui)
+            .was_clicked()
+        {
+            StateTransition::End
+        } else {
+            StateTransition::Continue
+        }
+    }
+
+    fn update(&mut self, dt: f64, resources: &mut Resources) -> StateTransition {
+        StateTransition::Continue
+    }
+
+    fn draw(&mut self, c: Context, g: &mut G2d, alpha: f64) {
+        self.gui.draw(c, g);
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt::{self, Debug, Formatter};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use conrod::{self, Labelable, Positionable, Sizeable, Widget};
+use piston;
+use piston_window::{Context, G2d};
+
+use ::{GameState, Resources, StateTransition};
+use gui::Gui;
+use menu::MainMenu;
+use world::MapFactory;
+use super::Game;
+use super::campaign::Campaign;
+
+widget_ids!(struct GameOverWidgetIds { canvas, title, text, retry, menu });
+
+/// Screen shown once all the players are dead.
+pub struct GameOver {
+    gui: Gui,
+    widget_ids: GameOverWidgetIds,
+    /// How long the players survived, in seconds.
+    time: f64,
+    /// The level that was lost, to try it again.
+    map_factory: Rc<MapFactory>,
+    map_file: Option<PathBuf>,
+    campaign: Option<Campaign>,
+}
+
+impl Debug for GameOver {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "GameOver")
+    }
+}
+
+impl GameOver {
+    pub fn new(time: f64, map_factory: Rc<MapFactory>, map_file: Option<PathBuf>,
+               campaign: Option<Campaign>, resources: &mut Resources) -> GameOver {
+        let mut gui = Gui::new(resources);
+        let ids = GameOverWidgetIds::new(gui.ui.widget_id_generator());
+
+        GameOver {
+            gui: gui,
+            widget_ids: ids,
+            time: time,
+            map_factory: map_factory,
+            map_file: map_file,
+            campaign: campaign,
+        }
+    }
+}
+
+impl GameState for GameOver {
+    fn handle_event(&mut self, event: &piston::input::Event,
+                    resources: &mut Resources) -> StateTransition
+    {
+        self.gui.handle_event(event, resources);
+
+        let (retry, menu) = {
+            let ui = &mut self.gui.ui.set_widgets();
+
+            conrod::widget::Canvas::new().floating(true).w_h(300.0, 160.0).pad(10.0).middle()
+                .set(self.widget_ids.canvas, ui);
+
+            conrod::widget::Text::new("Game Over")
+                .mid_top_of(self.widget_ids.canvas)
+                .font_size(24)
+                .set(self.widget_ids.title, ui);
+            conrod::widget::Text::new(&format!("Survived {:.1} seconds", self.time))
+                .down(15.0)
+                .set(self.widget_ids.text, ui);
+
+            let retry = conrod::widget::Button::new()
+                .down(15.0)
+                .w_h(80.0, 25.0)
+                .label("Retry")
+                .set(self.widget_ids.retry, ui)
+                .was_clicked();
+            let menu = conrod::widget::Button::new()
+                .right(15.0)
+                .w_h(80.0, 25.0)
+                .label("Menu")
+                .set(self.widget_ids.menu, ui)
+                .was_clicked();
+            (retry, menu)
+        };
+
+        if retry {
+            match Game::from_factory(self.map_factory.clone(), resources) {
+                Ok(mut game) => {
+                    game.map_file = self.map_file.take();
+                    game.campaign = self.campaign.take();
+                    StateTransition::Replace(Box::new(game))
+                }
+                Err(e) => {
+                    error!("Can't restart game: {}", e);
+                    StateTransition::Continue
+                }
+            }
+        } else if menu {
+            let levels = self.campaign.as_ref()
+                .map_or(Vec::new(), |campaign| campaign.levels().to_vec());
+            StateTransition::Replace(Box::new(MainMenu::new(resources, levels)))
+        } else {
+            StateTransition::Continue
+        }
+    }
+
+    fn update(&mut self, dt: f64, resources: &mut Resources) -> StateTransition {
+        StateTransition::Continue
+    }
+
+    fn draw(&mut self, c: Context, g: &mut G2d, alpha: f64) {
+        self.gui.draw(c, g);
+    }
+}
@@ -1,28 +1,428 @@
-use std::cmp::{max, min};
+use std::cmp::Ordering;
+use std::error::Error;
 use std::fmt::{self, Debug, Formatter};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use piston;
 use piston::input::*;
 use piston::window::{AdvancedWindow, Window};
-use piston_window::{Context, G2d};
+use piston_window::{Context, G2d, Glyphs, TextureSettings};
+use graphics::Transformed;
+use graphics::math::{Matrix2d, identity};
+use serde_json;
 
 use {GameState, Resources, StateTransition};
-use sprites::{Sprite, SpriteSheet};
-use utils::one_rest_split_iter;
+use audio::Clip;
+use entities::{Bullet, ParticleEmitter};
+use input::InputManager;
+use loading::{LoadJob, LoadProgress};
+use sprites::{DrawEffect, NO_TINT, Sprite, SpriteBatch, SpriteError, SpriteHandle, SpriteSheet, modulate};
+use utils::{Cooldown, FpsCounter};
 use vecmath::*;
-use world::{Entity, EntityLogic, EntityPhysics, MapFactory, Spawnable, World, WorldView};
+use world::{CameraConfig, Effect, Entity, EntityLogic, EntityPhysics, Map, MapFactory,
+            MapLoadError, Physics, PlatformBounds, RenderOrder, SavedEntity, SimContext,
+            Spawnable, Tile, TriggerState, World, WorldEvent, WorldView};
 
+mod campaign;
+mod credits;
+mod gameover;
 mod pausemenu;
 
+use self::campaign::Campaign;
+use self::gameover::GameOver;
+
 const CAMERA_MARGIN_X: f64 = 5.0;
 const CAMERA_MARGIN_Y: f64 = 5.0;
+/// File written by quick-save (F5) and read by quick-load (F9).
+const QUICKSAVE_FILE: &'static str = "quicksave.json";
+/// File the state of the world is dumped to (F7), to attach to bug reports.
+const DEBUG_DUMP_FILE: &'static str = "debug_dump.json";
+/// Font of the debug overlay.
+const OVERLAY_FONT: &'static str = "assets/NotoSans-Regular.ttf";
+/// Period over which the frame rate is averaged, in seconds.
+const FPS_PERIOD: f64 = 0.5;
+/// Speed of the free camera, in view widths per second.
+const FREE_CAMERA_SPEED: f64 = 1.0;
+/// Factor applied to the free camera's size for each step of the wheel.
+const FREE_CAMERA_ZOOM: f64 = 0.9;
+/// Smallest width of the free camera's view.
+const FREE_CAMERA_MIN_SIZE: f64 = 1.0;
+/// Outline drawn around the entity under the mouse cursor.
+const HOVER_OUTLINE: DrawEffect = DrawEffect::Outline([1.0, 1.0, 1.0, 1.0], 0.05);
+
+/// Position of `value` within a repeating pattern of the given period.
+fn wrap(value: f64, period: f64) -> f64 {
+    ((value % period) + period) % period
+}
+
+/// A step of drawing the world, see `draw_passes()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DrawPass {
+    /// A layer of tiles, by index.
+    Layer(usize),
+    Entities,
+}
+
+/// The order in which to draw the tile layers and the entities.
+///
+/// The entities go over the background and main layers, but under the
+/// foreground ones.
+fn draw_passes(map: &Map) -> Vec<DrawPass> {
+    let mut passes = Vec::new();
+    let mut entities_drawn = false;
+    for (i, layer) in map.layers().iter().enumerate() {
+        if !entities_drawn && layer.render_order == RenderOrder::Foreground {
+            passes.push(DrawPass::Entities);
+            entities_drawn = true;
+        }
+        passes.push(DrawPass::Layer(i));
+    }
+    if !entities_drawn {
+        passes.push(DrawPass::Entities);
+    }
+    passes
+}
+
+/// An entity of the world, either attached to a tile or not.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EntityRef {
+    Tile((usize, usize)),
+    Free(usize),
+}
+
+/// An entity to draw, with what it is sorted by.
+struct DrawItem {
+    entity: EntityRef,
+    z_order: i32,
+    y: f64,
+}
+
+/// Sort the entities in the order to draw them, into `order`.
+///
+/// They are drawn by increasing `z_order()`. With the same one, higher
+/// entities are drawn first, so lower ones appear in front of them; after
+/// that, entities attached to tiles come first.
+fn entity_draw_order(world: &World, alpha: f64, order: &mut Vec<DrawItem>) {
+    order.clear();
+    order.extend(world.tile_entities.iter().map(|(&key, entity)| DrawItem {
+        entity: EntityRef::Tile(key),
+        z_order: entity.logic.z_order(),
+        y: entity.draw_pos(alpha).y(),
+    }));
+    order.extend(world.entities.iter().enumerate().map(|(i, entity)| DrawItem {
+        entity: EntityRef::Free(i),
+        z_order: entity.logic.z_order(),
+        y: entity.draw_pos(alpha).y(),
+    }));
+    order.sort_by(|a, b| {
+        a.z_order.cmp(&b.z_order)
+            .then(b.y.partial_cmp(&a.y).unwrap_or(Ordering::Equal))
+    });
+}
+
+/// Tracks which jumps a character is still allowed.
+struct JumpState {
+    /// Jumps left before touching the ground again.
+    jumps_remaining: u8,
+    /// Time left to jump from the ground after walking off a ledge.
+    coyote_timer: f64,
+}
+
+impl JumpState {
+    fn new() -> JumpState {
+        JumpState {
+            jumps_remaining: MAX_JUMPS,
+            coyote_timer: 0.0,
+        }
+    }
+
+    /// Update from this frame's state, returns whether to jump now.
+    ///
+    /// `pressed` is whether the button got pressed this frame: jumps don't
+    /// repeat while it's held.
+    fn update(&mut self, on_ground: bool, pressed: bool, dt: f64) -> bool {
+        if on_ground {
+            self.jumps_remaining = MAX_JUMPS;
+            self.coyote_timer = COYOTE_TIME;
+        } else if self.coyote_timer > 0.0 {
+            self.coyote_timer -= dt;
+            if self.coyote_timer <= 0.0 && self.jumps_remaining == MAX_JUMPS {
+                // Walked off a ledge without jumping, lose the ground jump
+                self.jumps_remaining -= 1;
+            }
+        }
+        if pressed && self.jumps_remaining > 0 {
+            self.jumps_remaining -= 1;
+            self.coyote_timer = 0.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The movement of a character, from its inputs.
+///
+/// This is separate from `Character` so it can run without a window or an
+/// input manager. The entity's position is the center of the character.
+struct Movement {
+    dir: f64,
+    /// Whether jump got pressed this frame.
+    jump_pressed: bool,
+    /// Whether jump got released this frame.
+    jump_released: bool,
+    /// Whether down is held, to drop through one-way platforms.
+    down: bool,
+    /// Vertical input, to climb ladders.
+    climb: f64,
+    /// Whether the character holds on to a ladder, ignoring gravity.
+    climbing: bool,
+    /// Whether a wall was touching the character's left side after the last
+    /// step.
+    touching_wall_left: bool,
+    /// Whether a wall was touching the character's right side after the last
+    /// step.
+    touching_wall_right: bool,
+    jump_state: JumpState,
+    /// Time left falling through one-way platforms.
+    drop_timer: f64,
+    /// Whether the character jumped on the last step.
+    jumped: bool,
+    /// Whether the character is going up from a jump, which letting go of
+    /// the button cuts short.
+    jump_rising: bool,
+    /// Whether the character faces left, kept while standing still.
+    facing_left: bool,
+}
+
+impl Movement {
+    fn new() -> Movement {
+        Movement {
+            dir: 0.0,
+            jump_pressed: false,
+            jump_released: false,
+            down: false,
+            climb: 0.0,
+            climbing: false,
+            touching_wall_left: false,
+            touching_wall_right: false,
+            jump_state: JumpState::new(),
+            drop_timer: 0.0,
+            jumped: false,
+            jump_rising: false,
+            facing_left: false,
+        }
+    }
+
+    /// Move the character for one frame, returns whether it landed hard.
+    ///
+    /// Besides the map, the character can stand on the given platforms.
+    fn step(&mut self, entity: &mut EntityPhysics, dt: f64,
+            map: &Map, physics: &Physics, platforms: &[PlatformBounds]) -> bool {
+        if self.dir < 0.0 {
+            self.facing_left = true;
+        } else if self.dir > 0.0 {
+            self.facing_left = false;
+        }
+
+        if self.drop_timer > 0.0 {
+            self.drop_timer -= dt;
+        }
+
+        // Ladders are grabbed by pushing up or down, and let go when leaving
+        // them or jumping off
+        let on_ladder = map.tilef(entity.pos.x(), entity.pos.y())
+            .map_or(false, |tile| tile.climbable);
+        if !on_ladder {
+            self.climbing = false;
+        } else if self.climb != 0.0 {
+            self.climbing = true;
+        }
+
+        let mut on_ground = false;
+        let mut on_one_way = false;
+        let mut hard_landing = false;
+        let mut ground_velocity = [0.0, 0.0];
+        if entity.speed.y() <= 0.05 && !(self.climbing && self.climb > 0.0) {
+            // Snap to the ground, following the shape of slopes; walking
+            // down a ramp lowers it by up to the distance walked
+            let feet = entity.pos.y() - CHAR_H / 2.;
+            let snap = MARGIN + entity.speed.x().abs() * dt;
+            let mut ground = None;
+            if let Some((height, tile)) = map.ground_at(entity.pos.x(), feet - snap) {
+                // One-way platforms only hold what was above them
+                let solid = !tile.one_way ||
+                    (self.drop_timer <= 0.0 && feet + MARGIN >= height);
+                if solid && feet - snap <= height {
+                    ground = Some((height, tile.surface_velocity));
+                    on_one_way = tile.one_way;
+                }
+            }
+            // Platform entities hold what lands on them, and carry it along
+            for platform in platforms {
+                let top = platform.max.y();
+                if entity.pos.x() >= platform.min.x() && entity.pos.x() <= platform.max.x() &&
+                    feet - snap <= top && feet + MARGIN >= top &&
+                    ground.map_or(true, |(height, _)| top > height)
+                {
+                    ground = Some((top, platform.velocity));
+                    on_one_way = false;
+                }
+            }
+            if let Some((height, velocity)) = ground {
+                on_ground = true;
+                ground_velocity = velocity;
+                hard_landing = entity.speed.y() < -HARD_LANDING_SPEED;
+                entity.speed[1] = 0.0;
+                entity.pos[1] = height + CHAR_H / 2.;
+            }
+        }
+        // Pressing into a wall in the air slides down it, and jumping pushes
+        // off of it; touching a wall gives back the jumps like the ground
+        let wall_sliding = !on_ground && !self.climbing &&
+            (self.dir < 0.0 && self.touching_wall_left ||
+             self.dir > 0.0 && self.touching_wall_right);
+        let mut jump = self.jump_state.update(on_ground || self.climbing || wall_sliding,
+                                              self.jump_pressed, dt);
+        if jump && on_one_way && self.down {
+            // Drop through the platform instead of jumping
+            self.drop_timer = DROP_THROUGH_TIME;
+            jump = false;
+        }
+        if jump && self.climbing {
+            // Pushing up climbs, other jumps let go of the ladder
+            if self.climb > 0.0 {
+                jump = false;
+            } else {
+                self.climbing = false;
+            }
+        }
+        if on_ground {
+            // Only the horizontal part of the surface velocity carries us
+            entity.speed[0] = self.dir * physics.run_speed + ground_velocity.x();
+        } else if self.climbing {
+            entity.speed[0] = self.dir * CLIMB_SPEED;
+            entity.speed[1] = self.climb * CLIMB_SPEED;
+        } else {
+            if entity.speed.x() * self.dir.signum() < self.dir.abs() * physics.run_speed {
+                entity.speed[0] += self.dir * physics.air_accel * dt;
+            }
+            entity.apply_gravity(physics.gravity, dt);
+            if wall_sliding {
+                entity.speed[1] = entity.speed.y().max(-WALL_SLIDE_SPEED);
+            }
+        }
+        if jump && wall_sliding {
+            entity.speed[0] = -self.dir.signum() * WALL_JUMP_SPEED_X;
+            entity.speed[1] = WALL_JUMP_SPEED_Y;
+        } else if jump {
+            entity.speed[1] = physics.jump_speed;
+        }
+        self.jumped = jump;
+        // Releasing the button early makes a lower jump
+        if jump {
+            self.jump_rising = true;
+        } else if on_ground || self.climbing || entity.speed.y() <= 0.0 {
+            self.jump_rising = false;
+        } else if self.jump_rising && self.jump_released {
+            entity.speed[1] = entity.speed.y().min(MIN_JUMP_SPEED);
+            self.jump_rising = false;
+        }
+
+        // Walls are checked at the head and a step above the feet
+        let heights = [STEP_HEIGHT - CHAR_H / 2., CHAR_H / 2. - MARGIN];
+        let dir = entity.speed.x().signum();
+        for height in heights.iter() {
+            let (x, y) = (entity.pos.x() + (CHAR_W / 2. + MARGIN) * dir,
+                          entity.pos.y() + height);
+            if map.is_solid(x, y) {
+                let (min, max) = map.tile_rect(x, y);
+                entity.pos[0] = if dir > 0.0 {
+                    min.x() - CHAR_W / 2.
+                } else {
+                    max.x() + CHAR_W / 2.
+                };
+                entity.speed[0] = 0.0;
+                break;
+            }
+        }
+
+        // Move, stopping at the first wall even when going fast enough to
+        // skip over it in one frame
+        let mut motion = vec2_scale(entity.speed, dt);
+        if motion.x() != 0.0 {
+            let side = motion.x().signum() * CHAR_W / 2.;
+            for height in heights.iter() {
+                let from = [entity.pos.x() + side, entity.pos.y() + height];
+                if let Some(stop) = map.cast(from, [from.x() + motion.x(), from.y()]) {
+                    motion[0] = stop.x() - from.x();
+                    entity.speed[0] = 0.0;
+                }
+            }
+        }
+        entity.pos[0] += motion.x();
+        let pos = entity.pos;
+        let touching = |side: f64| heights.iter().any(|height| {
+            map.is_solid(pos.x() + (CHAR_W / 2. + MARGIN) * side, pos.y() + height)
+        });
+        self.touching_wall_left = touching(-1.0);
+        self.touching_wall_right = touching(1.0);
+        if motion.y() != 0.0 {
+            let side = motion.y().signum() * CHAR_H / 2.;
+            for offset in [-1.0f64, 1.0].iter() {
+                let from = [entity.pos.x() + (CHAR_W / 2. - MARGIN) * offset,
+                            entity.pos.y() + side];
+                if let Some(stop) = map.cast(from, [from.x(), from.y() + motion.y()]) {
+                    motion[1] = stop.y() - from.y();
+                    entity.speed[1] = 0.0;
+                }
+                if motion.y() < 0.0 && self.drop_timer <= 0.0 {
+                    // Land on one-way platforms passed while falling
+                    if let Some(top) = map.platform_crossed(from.x(), from.y(),
+                                                            from.y() + motion.y()) {
+                        motion[1] = top - from.y();
+                        entity.speed[1] = 0.0;
+                    }
+                }
+                for platform in platforms {
+                    let top = platform.max.y();
+                    if motion.y() < 0.0 &&
+                        from.x() >= platform.min.x() && from.x() <= platform.max.x() &&
+                        from.y() >= top && from.y() + motion.y() < top
+                    {
+                        motion[1] = top - from.y();
+                        entity.speed[1] = 0.0;
+                    }
+                }
+            }
+        }
+        entity.pos[1] += motion.y();
+
+        hard_landing
+    }
+}
 
+/// A character controlled by a player.
+///
+/// Its position is at its center, see `Movement`.
 struct Character {
     player: usize,
-    dir: f64,
-    jump: bool,
-    sprite_sheet: Rc<SpriteSheet>,
+    movement: Movement,
+    /// Whether the shoot button was held on the previous frame.
+    was_shooting: bool,
+    /// Limits how fast the character shoots when the button is pressed.
+    shoot_cooldown: Cooldown,
+    /// `None` when simulating without a window.
+    sprite_sheet: Option<Rc<SpriteSheet>>,
+    /// Part of the sprite sheet to draw.
+    idle_coords: [f64; 4],
+    /// Kept so that it stays loaded, `None` if it couldn't be.
+    jump_sound: Option<Rc<Clip>>,
+    trigger: TriggerState,
 }
 
 impl fmt::Debug for Character {
@@ -32,13 +432,46 @@ impl fmt::Debug for Character {
 }
 
 impl Character {
-    fn new(player: usize, resources: &mut Resources) -> Character {
+    fn new(player: usize, resources: &mut Resources) -> Result<Character, SpriteError> {
+        let jump_sound = match resources.sound_manager.load("jump") {
+            Ok(clip) => Some(clip),
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        };
+        let sprite_sheet = try!(resources.load_spritesheet(CHARACTER_SHEET));
+        let idle_coords = match sprite_sheet.region("idle_1") {
+            Some(region) => region.coords(),
+            None => {
+                warn!("Character sprite sheet has no idle_1 region");
+                [0.0, 0.0, sprite_sheet.width as f64, sprite_sheet.height as f64]
+            }
+        };
+        Ok(Character {
+            player: player,
+            movement: Movement::new(),
+            was_shooting: false,
+            shoot_cooldown: Cooldown::new(SHOOT_COOLDOWN),
+            sprite_sheet: Some(sprite_sheet),
+            idle_coords: idle_coords,
+            jump_sound: jump_sound,
+            trigger: TriggerState::default(),
+        })
+    }
+
+    /// A character without sprite or sounds, for headless simulation.
+    #[cfg(test)]
+    fn headless(player: usize) -> Character {
         Character {
             player: player,
-            dir: 0.0,
-            jump: false,
-            sprite_sheet: resources.load_spritesheet(
-                "alien/green__0000_idle_1.png"),
+            movement: Movement::new(),
+            was_shooting: false,
+            shoot_cooldown: Cooldown::new(SHOOT_COOLDOWN),
+            sprite_sheet: None,
+            idle_coords: [0.0, 0.0, 1.0, 1.0],
+            jump_sound: None,
+            trigger: TriggerState::default(),
         }
     }
 }
@@ -46,71 +479,158 @@ impl Character {
 const CHAR_W: f64 = 0.63;
 const CHAR_H: f64 = 1.29;
 const MARGIN: f64 = 0.05;
+/// Height above the feet under which walls don't block, so that characters
+/// can walk from a ramp onto the ledge at its top.
+const STEP_HEIGHT: f64 = 0.5;
+/// Time during which one-way platforms are ignored after dropping through.
+const DROP_THROUGH_TIME: f64 = 0.25;
+/// Number of jumps before landing, including the one from the ground.
+const MAX_JUMPS: u8 = 2;
+/// Upward speed left when releasing jump early, giving the lowest jump; the
+/// highest one keeps the map's `Physics::jump_speed`.
+const MIN_JUMP_SPEED: f64 = 2.0;
+/// Grace time to still jump after leaving a ledge, in seconds.
+const COYOTE_TIME: f64 = 0.1;
+/// Falling speed above which landing makes the controller rumble.
+const HARD_LANDING_SPEED: f64 = 8.0;
+/// Speed at which characters move on ladders.
+const CLIMB_SPEED: f64 = 3.0;
+/// Fastest fall while sliding down a wall.
+const WALL_SLIDE_SPEED: f64 = 2.0;
+/// Speed away from the wall given by a wall jump.
+const WALL_JUMP_SPEED_X: f64 = 4.0;
+/// Upward speed given by a wall jump.
+const WALL_JUMP_SPEED_Y: f64 = 5.0;
+/// Time between shots, in seconds.
+const SHOOT_COOLDOWN: f64 = 0.25;
+/// Characters are drawn over most other entities.
+const CHARACTER_Z_ORDER: i32 = 10;
+/// Sprite sheet of the characters.
+const CHARACTER_SHEET: &'static str = "alien/green__0000_idle_1.png";
+/// Number of particles bursting out of a character when it dies.
+const DEATH_PARTICLES: usize = 12;
+/// Smallest and largest speed of those particles.
+const DEATH_PARTICLE_SPEED: (f64, f64) = (2.0, 5.0);
+/// Time those particles last, in seconds.
+const DEATH_PARTICLE_LIFETIME: f64 = 0.6;
+/// Size of those particles, drawn with a bit of the character's sprite.
+const DEATH_PARTICLE_SIZE: [f64; 2] = [0.15, 0.15];
 
 impl EntityLogic for Character {
+    fn type_id(&self) -> Option<&str> {
+        Some("f.character")
+    }
+
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::Value::from(self.player)
+    }
+
+    fn z_order(&self) -> i32 {
+        CHARACTER_Z_ORDER
+    }
+
+    fn hitbox(&self, pos: Vector2) -> Option<Aabb> {
+        Some(Aabb::from_center_size(pos, [CHAR_W, CHAR_H]))
+    }
+
+    fn collect(&mut self, entity: &mut EntityPhysics, effect: &Effect) -> bool {
+        match *effect {
+            // Leave the item for someone who needs it
+            Effect::Heal(_) if entity.health >= 1.0 => false,
+            Effect::Heal(amount) => {
+                info!("Player {} healed by {}", self.player, amount);
+                entity.heal(amount);
+                true
+            }
+        }
+    }
+
+    fn on_death(&mut self, entity: &mut EntityPhysics, world: &mut WorldView) {
+        let sprite = self.sprite_sheet.as_ref().map(|sheet| Sprite {
+            sheet: sheet.clone(),
+            coords: self.idle_coords,
+            size: DEATH_PARTICLE_SIZE,
+            flip_h: false,
+            flip_v: false,
+        });
+        world.spawn_entity(ParticleEmitter::new(entity.pos, DEATH_PARTICLES,
+                                                DEATH_PARTICLE_SPEED, DEATH_PARTICLE_LIFETIME,
+                                                sprite, [1.0, 0.3, 0.3, 1.0]));
+    }
+
     fn update(&mut self, entity: &mut EntityPhysics, dt: f64,
-              world: &mut WorldView, resources: &Resources, sprite: &mut Option<Sprite>) -> bool {
+              world: &mut WorldView, ctx: &SimContext, sprite: &mut Option<Sprite>) -> bool {
         // Characters should be in focus
         world.focus(&entity.pos);
 
         // Read input
-        if let Some(i) = resources.input_manager.player_input(0) {
-            self.dir = i.x();
-            self.jump = i.jump();
+        let mut shoot = false;
+        if let Some(i) = ctx.input.player_input(self.player) {
+            self.movement.dir = i.x();
+            self.movement.jump_pressed = i.jump_pressed();
+            self.movement.jump_released = i.jump_released();
+            self.movement.down = i.down();
+            self.movement.climb = i.y();
+            shoot = i.shoot();
         };
 
         // Movements
-        let mut on_ground = false;
-        if entity.speed.y() <= 0.05 {
-            if let Some(tile) = world.map.tilef(entity.pos.x(),
-                                                entity.pos.y() - CHAR_H / 2. - MARGIN) {
-                if tile.collide {
-                    on_ground = true;
-                    entity.speed[1] = 0.0;
-                    entity.pos[1] = (entity.pos.y() - CHAR_H / 2. - MARGIN).floor() +
-                                    1. + CHAR_H / 2.;
-                }
-            }
+        let platforms = world.platforms();
+        if self.movement.step(entity, dt, world.map, world.physics, &platforms) {
+            ctx.input.rumble(self.player, 0.5, 0.2);
         }
-        if on_ground {
-            entity.speed[0] = self.dir * 5.0;
-            if self.jump {
-                entity.speed[1] = 5.0;
-            }
-        } else {
-            if entity.speed.x() * self.dir.signum() < self.dir.abs() * 5.0 {
-                entity.speed[0] += self.dir * 20.0 * dt;
-            }
-            entity.speed[1] += -10.0 * dt;
+        // The clip stays loaded for the event, don't retry if it failed
+        if self.movement.jumped && self.jump_sound.is_some() {
+            world.emit(WorldEvent::PlaySound("jump".to_owned()));
         }
 
-        let dir = entity.speed.x().signum();
-        for height in [-1.0f64, 1.0].iter() {
-            if let Some(tile) = world.map.tilef(entity.pos.x() + (CHAR_W / 2. + MARGIN) * dir,
-                                                entity.pos.y() - (CHAR_H / 2. - MARGIN) * height) {
-                if tile.collide {
-                    entity.pos[0] = (entity.pos.x() + (CHAR_W / 2. + MARGIN) * dir).floor() +
-                        -dir * (CHAR_W / 2. + 0.5) + 0.5;
-                    entity.speed[0] = 0.0;
-                    break;
-                }
+        // Fire when the button gets pressed, toward the mouse for player one,
+        // not faster than the cooldown allows
+        self.shoot_cooldown.tick(dt);
+        if shoot && !self.was_shooting && self.shoot_cooldown.trigger() {
+            let mut aim = if self.player == 0 {
+                ctx.input.aim_direction(entity.pos)
+            } else {
+                [0.0, 0.0]
+            };
+            if aim == [0.0, 0.0] {
+                aim = [if self.movement.facing_left { -1.0 } else { 1.0 }, 0.0];
             }
+            let pos = [entity.pos.x() + aim.x() * (CHAR_W / 2. + MARGIN),
+                       entity.pos.y() + aim.y() * (CHAR_H / 2. + MARGIN)];
+            world.spawn_entity(Bullet::fire_toward(pos, aim));
         }
 
-        entity.pos = vec2_add(entity.pos, vec2_scale(entity.speed, dt));
+        self.was_shooting = shoot;
+
+        // Hurting tiles (lava)
+        let damage = world.damage_at(entity.pos);
+        if !entity.hurt(damage * dt) {
+            info!("Player {} died", self.player);
+            return false;
+        }
 
-        // Set sprite
-        // TODO: Animation
-        *sprite = Some(Sprite {
-            sheet: self.sprite_sheet.clone(),
-            coords: [0., 0., 213., 428.],
+        // Trigger zones, fired once when entering them
+        let trigger = world.map.tilef(entity.pos.x(), entity.pos.y())
+            .and_then(|tile| tile.trigger.as_ref());
+        if let Some(trigger) = self.trigger.update(trigger) {
+            world.emit(WorldEvent::Trigger(trigger));
+        }
+
+        // Set sprite, the single idle frame facing the movement direction
+        *sprite = self.sprite_sheet.as_ref().map(|sheet| Sprite {
+            sheet: sheet.clone(),
+            coords: self.idle_coords,
             size: [CHAR_W, CHAR_H],
+            flip_h: self.movement.facing_left,
+            flip_v: false,
         });
 
         true
     }
 }
 
+/// Spawns a character once, from the spawn point at its feet.
 struct SimpleSpawn {
     entity_logic: Option<Box<EntityLogic>>,
 }
@@ -135,65 +655,707 @@ impl fmt::Debug for SimpleSpawn {
 
 impl Spawnable for SimpleSpawn {
     fn spawn(&mut self, pos: &Vector2) -> (bool, Option<Entity>) {
+        // Characters are positioned by their center
+        let center = [pos.x(), pos.y() + CHAR_H / 2.];
         (false, self.entity_logic.take().map(|entity_logic| {
-            Some(Entity {
-                physics: EntityPhysics {
-                    pos: pos.clone(),
-                    speed: [0.0, 0.0],
-                },
-                logic: entity_logic,
-                sprite: None,
-            })
-        }).unwrap_or(None))
+            Entity::with_logic(center, entity_logic)
+        }))
     }
 }
 
+/// The view of the world drawn to the window.
+///
+/// Cameras always draw straight to the window: rendering to an offscreen
+/// texture (picture-in-picture) would need a render target from the gfx
+/// factory and a `Gfx2d` pass outside of `PistonWindow::draw_2d`, while
+/// states only draw from inside it.
+#[derive(Clone)]
 struct Camera {
     aspect_ratio: f64,
     pos: Vector2,
     size: f64,
-    update_rate: f64,
+    config: CameraConfig,
+    /// Go to the focus right away, instead of following it smoothly.
+    snap: bool,
+    /// Position and size before the last update, to interpolate.
+    prev_pos: Vector2,
+    prev_size: f64,
+}
+
+impl Camera {
+    /// Remember the current position, before updating it.
+    fn save_previous(&mut self) {
+        self.prev_pos = self.pos;
+        self.prev_size = self.size;
+    }
+
+    /// The camera `alpha` of the way from the previous update.
+    fn interpolated(&self, alpha: f64) -> Camera {
+        let mut camera = self.clone();
+        camera.pos = self.prev_pos.lerp(self.pos, alpha);
+        camera.size = self.prev_size + (self.size - self.prev_size) * alpha;
+        camera
+    }
+
+    /// The area seen by the camera, as its center and half extents.
+    ///
+    /// `size` is the width of the view, its height following the window's
+    /// aspect ratio.
+    fn view_rect(&self) -> (Vector2, Vector2) {
+        let half = [self.size / 2.0, self.size * self.aspect_ratio / 2.0];
+        (vec2_add(self.pos, half), half)
+    }
+
+    /// Follow the window's shape, so the scene doesn't get stretched.
+    ///
+    /// An empty window (minimized) keeps the previous ratio.
+    fn resize(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.aspect_ratio = height as f64 / width as f64;
+        }
+    }
+
+    /// Move towards a view of the area from `a` to `b`.
+    ///
+    /// The center of the view only follows the target once it leaves the
+    /// dead zone, covering part of the distance and no faster than the
+    /// maximum speed.
+    fn follow(&mut self, a: Vector2, b: Vector2, dt: f64) {
+        let size = (b.x() - a.x()).max((b.y() - a.y()) / self.aspect_ratio);
+        let target = vec2_scale(vec2_add(a, b), 0.5);
+        let (mut center, _) = self.view_rect();
+        if self.snap {
+            center = target;
+            self.size = size;
+        } else {
+            let config = self.config;
+            let max_step = config.max_speed * dt;
+            for i in 0..2 {
+                let offset = target[i] - center[i];
+                if offset.abs() > config.deadzone[i] {
+                    let distance = offset - offset.signum() * config.deadzone[i];
+                    center[i] += (distance * config.smoothing[i]).max(-max_step).min(max_step);
+                }
+            }
+            self.size += (size - self.size) * config.smoothing[0];
+        }
+        let half = self.view_rect().1;
+        self.pos = vec2_sub(center, half);
+    }
+
+    /// Keep the visible area within a map of the given size.
+    ///
+    /// If the map is smaller than the view along an axis, it gets centered.
+    fn clamp(&mut self, width: f64, height: f64) {
+        let view = vec2_scale(self.view_rect().1, 2.0);
+        for (i, &extent) in [width, height].iter().enumerate() {
+            self.pos[i] = if view[i] >= extent {
+                (extent - view[i]) / 2.0
+            } else {
+                self.pos[i].max(0.0).min(extent - view[i])
+            };
+        }
+    }
+}
+
+/// What moves the camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CameraMode {
+    /// Keep the focused entities in view.
+    Follow,
+    /// Debug: moved with WASD and zoomed with the wheel (toggled with F4).
+    Free,
+}
+
+/// Input moving the camera in `CameraMode::Free`.
+#[derive(Default)]
+struct FreeCameraInput {
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+    /// Wheel steps since the last update, positive to zoom in.
+    scroll: f64,
+}
+
+impl FreeCameraInput {
+    /// Record a key press or release, returns whether the key moves the
+    /// camera.
+    fn handle_key(&mut self, key: Key, pressed: bool) -> bool {
+        match key {
+            Key::A => self.left = pressed,
+            Key::D => self.right = pressed,
+            Key::W => self.up = pressed,
+            Key::S => self.down = pressed,
+            _ => return false,
+        }
+        true
+    }
+
+    fn direction(&self) -> Vector2 {
+        let axis = |neg: bool, pos: bool| (pos as i32 - neg as i32) as f64;
+        [axis(self.left, self.right), axis(self.down, self.up)]
+    }
+
+    /// Pan and zoom the camera, keeping the center of the view in place.
+    fn apply(&mut self, camera: &mut Camera, dt: f64) {
+        let (center, _) = camera.view_rect();
+        let center = vec2_add(center,
+                              vec2_scale(self.direction(), FREE_CAMERA_SPEED * camera.size * dt));
+        camera.size = (camera.size * FREE_CAMERA_ZOOM.powf(self.scroll))
+            .max(FREE_CAMERA_MIN_SIZE);
+        self.scroll = 0.0;
+        let half = camera.view_rect().1;
+        camera.pos = vec2_sub(center, half);
+    }
+}
+
+/// Transformation from world coordinates to the pixels of a window.
+///
+/// The scale is the same on both axes, from the width of the view, with Y
+/// going up. The camera is centered in the window.
+fn projection(camera: &Camera, width: f64, height: f64) -> Matrix2d {
+    let (center, half) = camera.view_rect();
+    let zoom = width / (2.0 * half.x());
+    identity()
+        .trans(width / 2.0, height / 2.0)
+        .scale(zoom, -zoom)
+        .trans(-center.x(), -center.y())
+}
+
+/// Where a point of the window is in the world, the reverse of `projection`.
+fn screen_to_world(camera: &Camera, width: f64, height: f64, pos: Vector2) -> Vector2 {
+    let (center, half) = camera.view_rect();
+    let zoom = width / (2.0 * half.x());
+    [center.x() + (pos.x() - width / 2.0) / zoom,
+     center.y() - (pos.y() - height / 2.0) / zoom]
+}
+
+/// Pick the seed for a new game: the forced one if any, else from the clock.
+fn choose_seed(forced: Option<u32>) -> u32 {
+    forced.unwrap_or_else(|| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .expect("System clock is before the epoch");
+        (now.as_secs() as u32) ^ now.subsec_nanos()
+    })
+}
+
+/// Error starting a game.
+#[derive(Debug)]
+pub enum GameError {
+    /// The map is meant for 0 players.
+    NoPlayers,
+    /// A tile of the map uses a tile type that doesn't exist.
+    TileOutOfRange { x: usize, y: usize, tile: Tile },
+    /// An image the game needs couldn't be loaded.
+    MissingAsset(SpriteError),
+    /// The map or saved game couldn't be read.
+    Map(MapLoadError),
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GameError::NoPlayers => write!(f, "Can't play on map meant for 0 players"),
+            GameError::TileOutOfRange { x, y, tile } => {
+                write!(f, "Tile {} at ({}, {}) is out of range", tile, x, y)
+            }
+            GameError::MissingAsset(ref e) => write!(f, "Missing asset: {}", e),
+            GameError::Map(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for GameError {
+    fn description(&self) -> &str {
+        match *self {
+            GameError::NoPlayers => "Map has no players",
+            GameError::TileOutOfRange { .. } => "Tile out of range",
+            GameError::MissingAsset(_) => "Missing asset",
+            GameError::Map(_) => "Can't load map",
+        }
+    }
+}
+
+impl From<SpriteError> for GameError {
+    fn from(e: SpriteError) -> GameError {
+        GameError::MissingAsset(e)
+    }
+}
+
+impl From<MapLoadError> for GameError {
+    fn from(e: MapLoadError) -> GameError {
+        match e {
+            MapLoadError::Sprite(e) => GameError::MissingAsset(e),
+            e => GameError::Map(e),
+        }
+    }
+}
+
+impl From<io::Error> for GameError {
+    fn from(e: io::Error) -> GameError {
+        GameError::Map(MapLoadError::Io(e))
+    }
+}
+
+impl From<serde_json::Error> for GameError {
+    fn from(e: serde_json::Error) -> GameError {
+        GameError::Map(MapLoadError::Json(e))
+    }
+}
+
+/// Whether all the players are dead: no character left, and none waiting to
+/// be spawned.
+fn players_dead(entities: &[Entity], waiting: usize) -> bool {
+    waiting == 0 && !entities.iter().any(|e| e.logic.type_id() == Some("f.character"))
+}
+
+/// Outline the entity whose hitbox is under the cursor, removing the outline
+/// from the one that was before.
+///
+/// Other effects are left alone: an entity flashing isn't outlined.
+fn outline_hovered(entities: &mut [Entity], cursor: Vector2) {
+    let mut found = false;
+    for entity in entities.iter_mut() {
+        let hovered = !found && entity.logic.hitbox(entity.physics.pos)
+            .map_or(false, |h| h.contains(cursor));
+        if hovered {
+            found = true;
+            if entity.effect.is_none() {
+                entity.effect = Some(HOVER_OUTLINE);
+            }
+        } else if entity.effect == Some(HOVER_OUTLINE) {
+            entity.effect = None;
+        }
+    }
+}
+
+/// Check that a game can be played on a map, before creating anything.
+fn check_map(map_factory: &MapFactory) -> Result<(), GameError> {
+    if map_factory.nb_players < 1 {
+        return Err(GameError::NoPlayers);
+    }
+    if let Some((x, y, tile)) = map_factory.invalid_tile() {
+        return Err(GameError::TileOutOfRange { x: x, y: y, tile: tile });
+    }
+    Ok(())
+}
+
+/// What gets written in a saved game.
+#[derive(Serialize, Deserialize)]
+struct SaveState {
+    /// The map file, `None` for the example map.
+    map_file: Option<PathBuf>,
+    seed: u32,
+    clock: f64,
+    camera_pos: Vector2,
+    camera_size: f64,
+    entities: Vec<SavedEntity>,
 }
 
 pub struct Game {
     pub world: World,
+    /// The seed the world was created from, for reproducing bugs.
+    pub seed: u32,
+    /// The map this level was created from, kept to play it again.
+    map_factory: Rc<MapFactory>,
+    /// The map file this level was loaded from, `None` for the example map.
+    map_file: Option<PathBuf>,
     camera: Camera,
+    camera_mode: CameraMode,
+    free_camera: FreeCameraInput,
+    /// The campaign this level is part of, if any.
+    campaign: Option<Campaign>,
+    /// Debug: draw the tile grid (toggled with F1).
+    show_grid: bool,
+    /// Debug: circle the entities that have no sprite (toggled with F2).
+    show_invisible: bool,
+    /// Debug: show the frame rate, entity count and camera (toggled with F3).
+    show_overlay: bool,
+    /// Glyphs for the overlay, loaded when it's first shown.
+    overlay_glyphs: Option<Glyphs>,
+    fps: FpsCounter,
+    /// Scratch buffer sorting the entities when drawing, kept to reuse it.
+    draw_order: Vec<DrawItem>,
+    /// Whether another state is on top of this one, the world doesn't
+    /// advance meanwhile.
+    paused: bool,
 }
 
 impl Game {
-    pub fn new(map_factory: MapFactory, resources: &mut Resources) -> Game {
+    pub fn new(map_factory: MapFactory,
+               resources: &mut Resources) -> Result<Game, GameError> {
+        Game::from_factory(Rc::new(map_factory), resources)
+    }
+
+    /// Create a game from a map that might be shared with a previous game.
+    fn from_factory(map_factory: Rc<MapFactory>,
+                    resources: &mut Resources) -> Result<Game, GameError> {
+        let seed = choose_seed(resources.forced_seed);
+        Game::with_seed(map_factory, seed, resources)
+    }
+
+    fn with_seed(map_factory: Rc<MapFactory>, seed: u32,
+                 resources: &mut Resources) -> Result<Game, GameError> {
         info!("Creating game...");
 
-        if map_factory.nb_players < 1 {
-            panic!("Can't play on map meant for 0 players");
-        }
+        try!(check_map(&map_factory));
 
-        info!("Creating map");
-        let mut world = map_factory.create(resources, 42);
+        info!("Using seed {}", seed);
+        resources.window.set_title(format!("fluffy-fiesta (seed {})", seed));
+
+        let world = try!(Game::create_world(&map_factory, seed, resources));
 
-        info!("Creating {} characters", 1);
-        let character = Character::new(0, resources);
-        world.spawnables.push(Box::new(SimpleSpawn::new(Box::new(character))));
+        match map_factory.music {
+            Some(ref music) => resources.sound_manager.play_music(music, true),
+            None => resources.sound_manager.stop_music(),
+        }
 
         let window_size = resources.window.size();
-        let mut game = Game {
+        let mut game = Game::with_world(world, seed, map_factory);
+        game.camera.resize(window_size.width, window_size.height);
+        game.start_camera(resources);
+        info!("Camera: aspect_ratio = {:?}", game.camera.aspect_ratio);
+
+        Ok(game)
+    }
+
+    /// Wrap a world that was already created, without touching the window.
+    fn with_world(world: World, seed: u32, map_factory: Rc<MapFactory>) -> Game {
+        let camera_config = map_factory.camera;
+        Game {
             world: world,
+            seed: seed,
+            map_factory: map_factory,
+            map_file: None,
             camera: Camera {
-                aspect_ratio: window_size.height as f64 / window_size.width as f64,
+                aspect_ratio: 1.0,
                 pos: [0.0, 0.0],
                 size: 10.0,
-                update_rate: 1.0,
+                config: camera_config,
+                snap: true,
+                prev_pos: [0.0, 0.0],
+                prev_size: 10.0,
+            },
+            camera_mode: CameraMode::Follow,
+            free_camera: FreeCameraInput::default(),
+            campaign: None,
+            show_grid: false,
+            show_invisible: false,
+            show_overlay: false,
+            overlay_glyphs: None,
+            fps: FpsCounter::new(FPS_PERIOD),
+            draw_order: Vec::new(),
+            paused: false,
+        }
+    }
+
+    /// Create the live world from a map, with the characters to spawn.
+    fn create_world(map_factory: &MapFactory, seed: u32,
+                    resources: &mut Resources) -> Result<World, GameError> {
+        info!("Creating map");
+        let registry = resources.entity_registry.clone();
+        let mut world = try!(map_factory.create(resources, &registry, seed));
+
+        info!("Creating {} characters", map_factory.nb_players);
+        resources.input_manager.set_nb_players(map_factory.nb_players);
+        for player in 0..map_factory.nb_players {
+            let character = try!(Character::new(player, resources));
+            world.spawnables.push(Box::new(SimpleSpawn::new(Box::new(character))));
+        }
+        Ok(world)
+    }
+
+    /// Put the camera on the characters right away, then have it follow them.
+    fn start_camera(&mut self, resources: &mut Resources) {
+        self.camera.pos = [0.0, 0.0];
+        self.camera.size = 10.0;
+        self.camera.snap = true;
+
+        // Initial update: spawns characters, set camera, ...
+        self.update(0.0, resources);
+
+        self.camera.snap = false;
+        self.camera.save_previous();
+        info!("Camera: Initial position: {:?}, {:?}", self.camera.pos, self.camera.size);
+    }
+
+    /// Advance the world and the camera by one step, with the given input.
+    ///
+    /// This doesn't use the window, sounds being given to `play_sound`.
+    /// Nothing happens while the game is paused.
+    fn step<F: FnMut(&str)>(&mut self, dt: f64, input: &InputManager, play_sound: F) {
+        if self.paused {
+            return;
+        }
+        self.world.clock += dt;
+        let ctx = SimContext {
+            input: input,
+            time: self.world.clock,
+        };
+
+        for layer in self.world.backgrounds.iter_mut() {
+            layer.scroll.advance(dt);
+        }
+
+        self.world.advance_pause(dt);
+        self.world.sort_entities();
+        self.world.rebuild_grid();
+        let mut focus = None;
+        // Movement phase
+        self.world.entities_retain_pass(&mut focus, |entity, world_view| {
+            entity.logic.update(&mut entity.physics, dt,
+                                world_view, &ctx, &mut entity.sprite)
+        });
+        self.world.tile_entities_pass(&mut focus, |entity, world_view| {
+            entity.logic.update(&mut entity.physics, dt,
+                                world_view, &ctx, &mut entity.sprite)
+        });
+        self.world.expire_entities(dt);
+        self.world.notify_tiles();
+        // Reaction phase
+        self.world.entities_pass(&mut focus, |entity, world_view| {
+            entity.logic.react(&mut entity.physics, dt, world_view, &ctx);
+        });
+        self.world.apply_events(play_sound);
+        for entity in self.world.entities.iter_mut() {
+            entity.smooth_render_pos(dt);
+        }
+        self.camera.save_previous();
+        if self.camera_mode == CameraMode::Free {
+            // Debug camera, ignores the focus
+            self.free_camera.apply(&mut self.camera, dt);
+        } else if let Some((a, b)) = focus {
+            let a = [a.x() - CAMERA_MARGIN_X, a.y() - CAMERA_MARGIN_Y];
+            let b = [b.x() + CAMERA_MARGIN_X, b.y() + CAMERA_MARGIN_Y];
+            self.camera.follow(a, b, dt);
+            self.camera.clamp(self.world.map.width as f64, self.world.map.height as f64);
+        }
+    }
+
+    /// Give an input event to the free camera if it's on, and to the players.
+    ///
+    /// The keys moving the free camera don't reach the players, they would
+    /// also move the one they are bound to. Releases do, so that no key stays
+    /// held.
+    fn route_input(&mut self, event: &piston::input::Event, input_manager: &mut InputManager) {
+        if self.camera_mode == CameraMode::Free {
+            if let Some(Button::Keyboard(key)) = event.press_args() {
+                if self.free_camera.handle_key(key, true) {
+                    return;
+                }
             }
+            if let Some(Button::Keyboard(key)) = event.release_args() {
+                self.free_camera.handle_key(key, false);
+            }
+            if let Some(scroll) = event.mouse_scroll_args() {
+                self.free_camera.scroll += scroll[1];
+            }
+        }
+        input_manager.handle_event(event);
+    }
+
+    /// Advance the world without a window, ignoring sounds.
+    #[cfg(test)]
+    fn step_headless(&mut self, dt: f64, input: &InputManager) {
+        self.step(dt, input, |_| {});
+    }
+
+    /// Start the level over, from the map it was created from.
+    pub fn restart(&mut self, resources: &mut Resources) -> Result<(), GameError> {
+        info!("Restarting level");
+        self.world = try!(Game::create_world(&self.map_factory, self.seed, resources));
+        self.start_camera(resources);
+        Ok(())
+    }
+
+    /// Start the current level of a campaign.
+    pub fn from_campaign(campaign: Campaign,
+                         resources: &mut Resources) -> Result<Game, GameError> {
+        info!("Loading level {} of campaign", campaign.current() + 1);
+        let map_factory = try!(campaign.load_map());
+        Game::from_level(map_factory, Some(campaign), resources)
+    }
+
+    /// Start a level that was already read, from a campaign or not.
+    fn from_level(map_factory: MapFactory, campaign: Option<Campaign>,
+                  resources: &mut Resources) -> Result<Game, GameError> {
+        let mut game = try!(Game::new(map_factory, resources));
+        if let Some(campaign) = campaign {
+            game.map_file = Some(campaign.level().to_owned());
+            game.campaign = Some(campaign);
+        }
+        Ok(game)
+    }
+
+    /// Job for the `Loading` screen, starting a new game on the given
+    /// levels, or the example map if none.
+    ///
+    /// The map is read first, then its sprite sheets are loaded in the
+    /// background, and the game is created once they are all ready. If the
+    /// map can't be read or the game created, the job fails.
+    pub fn loader(levels: Vec<PathBuf>) -> LoadJob {
+        let mut levels = Some(levels);
+        let mut level = None;
+        let mut handles: Vec<SpriteHandle> = Vec::new();
+        Box::new(move |resources: &mut Resources| {
+            if let Some(levels) = levels.take() {
+                let (map_factory, campaign) = if levels.is_empty() {
+                    (MapFactory::example(), None)
+                } else {
+                    let campaign = Campaign::new(levels);
+                    match campaign.load_map() {
+                        Ok(map_factory) => (map_factory, Some(campaign)),
+                        Err(e) => return LoadProgress::Failed(format!("Can't load level: {}", e)),
+                    }
+                };
+                handles = map_factory.sprite_sheets().iter()
+                    .chain(Some(&CHARACTER_SHEET))
+                    .map(|name| resources.load_spritesheet_async(name))
+                    .collect();
+                level = Some((map_factory, campaign));
+                return LoadProgress::Working(0.0);
+            }
+
+            let done = handles.iter().filter(|h| h.is_done()).count();
+            if done < handles.len() {
+                return LoadProgress::Working(done as f64 / handles.len() as f64);
+            }
+            // The handles keep the sheets loaded until the game uses them
+            let (map_factory, campaign) = level.take().expect("Loading is already over");
+            match Game::from_level(map_factory, campaign, resources) {
+                Ok(game) => LoadProgress::Done(Box::new(game)),
+                Err(e) => LoadProgress::Failed(format!("Can't create game: {}", e)),
+            }
+        })
+    }
+
+    /// Write the state of the game, to be resumed with `load()`.
+    ///
+    /// Only the current level is saved, not the rest of the campaign.
+    pub fn save<W: Write>(&self, writer: W) -> io::Result<()> {
+        let state = SaveState {
+            map_file: self.map_file.clone(),
+            seed: self.seed,
+            clock: self.world.clock,
+            camera_pos: self.camera.pos,
+            camera_size: self.camera.size,
+            entities: self.world.save_entities(),
         };
+        try!(serde_json::to_writer(writer, &state));
+        Ok(())
+    }
 
-        // Initial update: spawns characters, set camera, ...
-        game.update(0.0, resources);
+    /// Resume a game written by `save()`.
+    pub fn load<R: Read>(reader: R,
+                         resources: &mut Resources) -> Result<Game, GameError> {
+        let state: SaveState = try!(serde_json::from_reader(reader));
+        let map_factory = match state.map_file {
+            Some(ref path) => try!(MapFactory::from_file(path)),
+            None => MapFactory::example(),
+        };
+        let nb_players = map_factory.nb_players;
+        let mut game = try!(Game::with_seed(Rc::new(map_factory), state.seed, resources));
+        game.map_file = state.map_file;
+        game.world.clock = state.clock;
+        game.camera.pos = state.camera_pos;
+        game.camera.size = state.camera_size;
+        game.camera.save_previous();
 
-        game.camera.update_rate = 0.1;
-        info!("Camera: aspect_ratio = {:?}", game.camera.aspect_ratio);
-        info!("Camera: Initial position: {:?}, {:?}", game.camera.pos, game.camera.size);
+        // Replace the entities, characters need the resources
+        let seed = state.seed;
+        let registry = resources.entity_registry.clone();
+        let mut players = Vec::new();
+        try!(game.world.restore_entities(state.entities, |saved| {
+            if saved.type_id != "f.character" {
+                return Ok(saved.create(&registry, seed));
+            }
+            let player = match saved.state.as_u64() {
+                Some(player) if (player as usize) < nb_players => player as usize,
+                _ => return Err(MapLoadError::Invalid(
+                    format!("Invalid character {}", saved.state))),
+            };
+            players.push(player);
+            let character = try!(Character::new(player, resources));
+            Ok(Some(Entity::new([0.0, 0.0], character)))
+        }));
+
+        // Players that weren't spawned yet
+        game.world.spawnables.clear();
+        for player in (0..nb_players).filter(|p| !players.contains(p)) {
+            let character = try!(Character::new(player, resources));
+            game.world.spawnables.push(Box::new(SimpleSpawn::new(Box::new(character))));
+        }
+
+        Ok(game)
+    }
+
+    /// Show or hide the debug overlay, loading its font if needed.
+    fn toggle_overlay(&mut self, resources: &mut Resources) {
+        if self.overlay_glyphs.is_none() {
+            let factory = resources.window.factory.clone();
+            match Glyphs::new(OVERLAY_FONT, factory, TextureSettings::new()) {
+                Ok(glyphs) => self.overlay_glyphs = Some(glyphs),
+                Err(e) => {
+                    error!("Can't load overlay font: {}", e);
+                    return;
+                }
+            }
+        }
+        self.show_overlay = !self.show_overlay;
+    }
 
-        game
+    fn quick_save(&self) {
+        let result = File::create(QUICKSAVE_FILE)
+            .and_then(|file| self.save(BufWriter::new(file)));
+        match result {
+            Ok(()) => info!("Game saved to {}", QUICKSAVE_FILE),
+            Err(e) => error!("Can't save game: {}", e),
+        }
+    }
+
+    fn debug_dump(&self) {
+        let result = File::create(DEBUG_DUMP_FILE)
+            .map_err(serde_json::Error::io)
+            .and_then(|file| self.world.debug_dump(BufWriter::new(file)));
+        match result {
+            Ok(()) => info!("World dumped to {}", DEBUG_DUMP_FILE),
+            Err(e) => error!("Can't dump world: {}", e),
+        }
+    }
+
+    fn quick_load(resources: &mut Resources) -> Result<Game, GameError> {
+        let file = try!(File::open(QUICKSAVE_FILE));
+        Game::load(BufReader::new(file), resources)
+    }
+
+    /// Show the game over screen, from which the level can be tried again.
+    fn game_over(&mut self, resources: &mut Resources) -> StateTransition {
+        info!("All players are dead");
+        resources.sound_manager.stop_music();
+        StateTransition::Replace(Box::new(GameOver::new(
+            self.world.clock, self.map_factory.clone(), self.map_file.take(),
+            self.campaign.take(), resources)))
+    }
+
+    /// Move on to the next level of the campaign, or to the credits.
+    fn complete_level(&mut self, resources: &mut Resources) -> StateTransition {
+        info!("Level complete");
+        resources.sound_manager.stop_music();
+        let mut campaign = match self.campaign.take() {
+            Some(campaign) => campaign,
+            None => return StateTransition::Replace(Box::new(credits::Credits::new(resources))),
+        };
+        if !campaign.advance() {
+            return StateTransition::Replace(Box::new(credits::Credits::new(resources)));
+        }
+        match Game::from_campaign(campaign, resources) {
+            Ok(game) => StateTransition::Replace(Box::new(game)),
+            Err(e) => {
+                error!("Can't load next level: {}", e);
+                StateTransition::End
+            }
+        }
     }
 }
 
@@ -207,141 +1369,1239 @@ impl GameState for Game {
     fn handle_event(&mut self, event: &piston::input::Event,
                     resources: &mut Resources) -> StateTransition
     {
+        if let Some(size) = event.resize_args() {
+            self.camera.resize(size[0], size[1]);
+        }
+
         if let Some(Button::Keyboard(key)) = event.press_args() {
-            if key == Key::Escape {
-                return StateTransition::Push(Box::new(
-                    pausemenu::PauseMenu::new(resources)));
+            match key {
+                Key::Escape => {
+                    return StateTransition::Push(Box::new(
+                        pausemenu::PauseMenu::new(resources)));
+                }
+                Key::F1 => self.show_grid = !self.show_grid,
+                Key::F2 => self.show_invisible = !self.show_invisible,
+                Key::F3 => self.toggle_overlay(resources),
+                Key::F4 => {
+                    self.camera_mode = match self.camera_mode {
+                        CameraMode::Follow => CameraMode::Free,
+                        CameraMode::Free => CameraMode::Follow,
+                    };
+                    self.free_camera = FreeCameraInput::default();
+                    info!("Camera mode: {:?}", self.camera_mode);
+                }
+                Key::F5 => self.quick_save(),
+                Key::F7 => self.debug_dump(),
+                Key::F8 => if let Err(e) = self.restart(resources) {
+                    error!("Can't restart level: {}", e);
+                },
+                Key::F9 => match Game::quick_load(resources) {
+                    Ok(game) => return StateTransition::Replace(Box::new(game)),
+                    Err(e) => error!("Can't load game: {}", e),
+                },
+                _ => {}
             }
         }
 
-        resources.input_manager.handle_event(event);
+        self.route_input(event, &mut resources.input_manager);
 
         StateTransition::Continue
     }
 
     fn update(&mut self, dt: f64, resources: &mut Resources) -> StateTransition {
+        // The time spent paused is dropped, not caught up with on resume
+        if self.paused {
+            return StateTransition::Continue;
+        }
+        self.fps.advance(dt);
+        if let Some(cursor) = resources.input_manager.cursor() {
+            let size = resources.window.size();
+            let target = screen_to_world(&self.camera, size.width as f64, size.height as f64,
+                                         cursor);
+            resources.input_manager.set_aim_target(Some(target));
+            outline_hovered(&mut self.world.entities, target);
+        }
+        {
+            let sound_manager = &resources.sound_manager;
+            self.step(dt, &resources.input_manager, |name| sound_manager.play_sfx(name));
+        }
+        // After the step, so it sees which buttons changed since the last one
         resources.input_manager.update(dt);
 
-        let map = &mut self.world.map;
-        let spawnables = &mut self.world.spawnables;
-        let mut focus = None;
-        one_rest_split_iter(&mut self.world.entities, |entity, other_entities| {
-            let mut world_view = WorldView {
-                map: map,
-                entities: other_entities,
-                spawnables: spawnables,
-                focus: &mut focus,
-            };
-            entity.logic.update(&mut entity.physics, dt,
-                                &mut world_view, resources, &mut entity.sprite);
-        });
-        if let Some((a, b)) = focus {
-            let a = [a.x() - CAMERA_MARGIN_X, a.y() - CAMERA_MARGIN_Y];
-            let b = [b.x() + CAMERA_MARGIN_X, b.y() + CAMERA_MARGIN_Y];
-
-            let mut camera = &mut self.camera;
-
-            // Compute desired camera position
-            let ratio = camera.aspect_ratio;
-            let size = (b.x() - a.x()).max((b.y() - a.y())/ratio);
-            let pos = [(a.x() + b.x() - size)/2.0,
-                       (a.y() + b.y() - size * ratio)/2.0];
-
-            // Update current camera position according to update rate
-            camera.pos = vec2_add(vec2_scale(camera.pos, 1.0 - camera.update_rate),
-                                  vec2_scale(pos, camera.update_rate));
-            camera.size = camera.size * (1.0 - camera.update_rate) + size * camera.update_rate;
+        if self.world.level_complete {
+            return self.complete_level(resources);
+        }
+        if players_dead(&self.world.entities, self.world.spawnables.len()) {
+            return self.game_over(resources);
         }
 
         StateTransition::Continue
     }
 
-    fn draw(&mut self, c: Context, g: &mut G2d) {
+    fn draw(&mut self, c: Context, g: &mut G2d, alpha: f64) {
         use graphics::*;
 
+        self.fps.frame();
+        let mut camera = self.camera.interpolated(alpha);
+
         let (width, height) = if let Some(v) = c.viewport {
             (v.rect[2], v.rect[3])
         } else {
             warn!("Got Context with no attached Viewport");
             return;
         };
+        // Use the actual shape of the viewport
+        camera.resize(width as u32, height as u32);
+
+        // Everything is modulated by the ambient color
+        let ambient = self.world.ambient_color();
 
         // Clear the screen.
-        clear([0.0, 0.0, 0.5, 1.0], g);
+        clear(modulate([0.0, 0.0, 0.5, 1.0], ambient), g);
 
         // Compute transformation from camera
-        let zoom = width as f64 / self.camera.size as f64;
-        let transform = c.transform
-            .trans(0.0, height as f64)
-            .scale(1.0, -1.0)
-            .scale(zoom, zoom)
-            .trans(-self.camera.pos.x(), -self.camera.pos.y());
-
-        let x1 = max(self.camera.pos.x() as i32 - 1, 0);
-        let y1 = max(self.camera.pos.y() as i32 - 1, 0);
-        let x2 = min((self.camera.pos.x() + self.camera.size + 1.0) as i32,
-                     self.world.map.width as i32);
-        let y2 = min((self.camera.pos.y() + self.camera.size * self.camera.aspect_ratio + 1.0) as i32,
-                            self.world.map.height as i32);
-
-        // Draw map
-        for y in y1..y2 {
-            for x in x1..x2 {
-                if let Some(ref sprite) = self.world.map.tile(x, y).unwrap().sprite {
-                    let image = Image::new()
-                        .src_rect(sprite.coords)
-                        .rect([(x as f64 + 0.5 - sprite.size[0] / 2.0),
-                               (y as f64 + 0.5 + sprite.size[1] / 2.0),
-                               sprite.size[0] as f64,
-                               -sprite.size[1] as f64]);
-                    image.draw(&sprite.sheet.texture, &DrawState::default(),
-                               transform, g);
+        let zoom = width as f64 / camera.size as f64;
+        let transform = c.transform.append_transform(
+            projection(&camera, width as f64, height as f64));
+
+        let (center, half) = camera.view_rect();
+        let (x1, y1, x2, y2) = self.world.map.visible_rect(center, half);
+        // Tiles are positioned in tile units
+        let ts = self.world.map.tile_size;
+        let tile_transform = transform.scale(ts, ts);
+
+        // Draw background layers, repeated over the whole screen
+        for layer in self.world.backgrounds.iter() {
+            let offset = layer.offset(camera.pos);
+            let (w, h) = (layer.sheet.width as f64, layer.sheet.height as f64);
+            let mut y = wrap(offset.y() * zoom, h) - h;
+            while y < height as f64 {
+                let mut x = -wrap(offset.x() * zoom, w);
+                while x < width as f64 {
+                    Image::new().rect([x, y, w, h]).color(ambient)
+                        .draw(&layer.sheet.texture, &DrawState::default(), c.transform, g);
+                    x += w;
                 }
+                y += h;
             }
         }
 
-        // Debug: draw grid
-        for x in x1..x2 {
-            rectangle([1.0, 1.0, 1.0, 1.0],
-                      rectangle::centered([x as f64, (y1 + y2) as f64 * 0.5,
-                                           0.5 / zoom as f64, (y2 - y1) as f64 * 0.5]),
-                      transform, g);
-        }
-        for y in y1..y2 {
-            rectangle([1.0, 1.0, 1.0, 1.0],
-                      rectangle::centered([(x1 + x2) as f64 * 0.5, y as f64,
-                                           (x2 - x1) as f64 * 0.5, 0.5 / zoom as f64]),
-                      transform, g);
-        }
-
-        // Draw entities
-        for entity in self.world.entities.iter() {
-            if let Some(ref sprite) = entity.sprite {
-                let image = Image::new()
-                    .src_rect(sprite.coords)
-                    .rect([(entity.physics.pos.x() - sprite.size[0] / 2.0),
-                           (entity.physics.pos.y() + sprite.size[1] / 2.0),
-                           sprite.size[0],
-                           -sprite.size[1]]);
-                image.draw(&sprite.sheet.texture, &DrawState::default(),
-                           transform, g);
-            } else {
-                // Debug: circle invisible entities
-                let circle = CircleArc::new(
-                    [1.0, 0.0, 0.0, 1.0],
-                    0.05, 0.0, 2.0 * ::std::f64::consts::PI);
-                circle.draw(
-                    rectangle::centered([entity.physics.pos.x(), entity.physics.pos.y(), 0.5, 0.5]),
-                    &DrawState::default(), transform, g);
+        // Draw the map layers, and the entities before the foreground ones
+        for pass in draw_passes(&self.world.map) {
+            match pass {
+                DrawPass::Layer(layer) => {
+                    // Consecutive tiles from the same sheet are drawn at once
+                    let mut batch = SpriteBatch::new();
+                    let mut batch_sheet: Option<Rc<SpriteSheet>> = None;
+                    let tiles = self.world.map.visible_tiles(layer, center, half);
+                    for (x, y, tile) in tiles {
+                        if let Some(ref sprite) = tile.sprite {
+                            let same_sheet = match batch_sheet {
+                                Some(ref sheet) => Rc::ptr_eq(sheet, &sprite.sheet),
+                                None => false,
+                            };
+                            if !same_sheet {
+                                if let Some(ref sheet) = batch_sheet {
+                                    batch.draw(&sheet.texture, ambient,
+                                               &DrawState::default(), g);
+                                }
+                                batch_sheet = Some(sprite.sheet.clone());
+                            }
+                            batch.push(tile_transform, sprite.rect(tile.render_center(x, y)),
+                                       sprite.coords,
+                                       (sprite.sheet.width as u32, sprite.sheet.height as u32));
+                        }
+                    }
+                    if let Some(ref sheet) = batch_sheet {
+                        batch.draw(&sheet.texture, ambient, &DrawState::default(), g);
+                    }
+                }
+                DrawPass::Entities => {
+                    // Debug: draw grid
+                    if self.show_grid {
+                        for x in x1..x2 {
+                            rectangle([1.0, 1.0, 1.0, 1.0],
+                                      rectangle::centered([x as f64 * ts,
+                                                           (y1 + y2) as f64 * 0.5 * ts,
+                                                           0.5 / zoom as f64,
+                                                           (y2 - y1) as f64 * 0.5 * ts]),
+                                      transform, g);
+                        }
+                        for y in y1..y2 {
+                            rectangle([1.0, 1.0, 1.0, 1.0],
+                                      rectangle::centered([(x1 + x2) as f64 * 0.5 * ts,
+                                                           y as f64 * ts,
+                                                           (x2 - x1) as f64 * 0.5 * ts,
+                                                           0.5 / zoom as f64]),
+                                      transform, g);
+                        }
+                    }
+
+                    // Draw entities, sorted by z-order
+                    entity_draw_order(&self.world, alpha, &mut self.draw_order);
+                    for item in self.draw_order.iter() {
+                        let entity = match item.entity {
+                            EntityRef::Tile(key) => &self.world.tile_entities[&key],
+                            EntityRef::Free(i) => &self.world.entities[i],
+                        };
+                        let pos = entity.draw_pos(alpha);
+                        if let Some(ref sprite) = entity.sprite {
+                            let image = Image::new()
+                                .src_rect(sprite.coords)
+                                .rect(sprite.rect(pos));
+                            if let Some(effect) = entity.effect {
+                                for (offset, color) in effect.underlays() {
+                                    image.color(color).draw(
+                                        &sprite.sheet.texture, &DrawState::default(),
+                                        transform.trans(offset.x(), offset.y()), g);
+                                }
+                            }
+                            let tint = entity.effect.map_or(NO_TINT, |effect| effect.tint());
+                            image.color(modulate(tint, ambient)).draw(
+                                &sprite.sheet.texture, &DrawState::default(), transform, g);
+                        } else if self.show_invisible {
+                            // Debug: circle invisible entities
+                            let circle = CircleArc::new(
+                                [1.0, 0.0, 0.0, 1.0],
+                                0.05, 0.0, 2.0 * ::std::f64::consts::PI);
+                            circle.draw(
+                                rectangle::centered([pos.x(), pos.y(), 0.5, 0.5]),
+                                &DrawState::default(), transform, g);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Debug: overlay
+        if self.show_overlay {
+            if let Some(ref mut glyphs) = self.overlay_glyphs {
+                let lines = [
+                    format!("{:.1} FPS", self.fps.fps),
+                    format!("{} entities, {} on tiles",
+                            self.world.entities.len(), self.world.tile_entities.len()),
+                    format!("Camera: {:.1}, {:.1} ({:.1} wide)",
+                            camera.pos.x(), camera.pos.y(), camera.size),
+                ];
+                for (i, line) in lines.iter().enumerate() {
+                    let transform = c.transform.trans(10.0, 20.0 + 18.0 * i as f64);
+                    if text([1.0, 1.0, 1.0, 1.0], 14, line, glyphs, transform, g).is_err() {
+                        warn!("Can't draw overlay text");
+                    }
+                }
             }
         }
     }
 
     fn pause(&mut self, resources: &mut Resources) {
+        self.paused = true;
         resources.window.set_capture_cursor(false);
     }
 
     fn resume(&mut self, resources: &mut Resources) {
+        self.paused = false;
         resources.window.set_capture_cursor(true);
+        // The window might have been resized from the menus
+        let window_size = resources.window.size();
+        self.camera.resize(window_size.width, window_size.height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use graphics::math::transform_pos;
+    use piston::input::{Button, ButtonArgs, ButtonState, Event, Input, Key};
+
+    use entities::{Bullet, Enemy, ParticleEmitter, entity_registry};
+    use input::InputManager;
+    use sprites::{DrawEffect, Sprite};
+    use vecmath::*;
+    use world::{CameraConfig, Entity, EntityLogic, EntityPhysics, Map, MapFactory,
+                MapFactoryBuilder, Physics, PlatformBounds, RenderOrder, SimContext, TileLayer, TileTypeDefinition, Trigger,
+                WorldView, grid_map, grid_map_factory};
+    use super::{CHAR_H, CHAR_W, CLIMB_SPEED, COYOTE_TIME, Camera, CameraMode, Character, DrawPass,
+                EntityRef, FreeCameraInput, Game, GameError, HOVER_OUTLINE, JumpState, Movement,
+                SimpleSpawn, WALL_JUMP_SPEED_X, WALL_JUMP_SPEED_Y, WALL_SLIDE_SPEED, check_map,
+                choose_seed, draw_passes, entity_draw_order, outline_hovered, players_dead,
+                projection, screen_to_world};
+
+    /// An entity at rest with full health, as characters spawn.
+    fn standing(pos: Vector2) -> EntityPhysics {
+        EntityPhysics { pos: pos, speed: [0.0, 0.0], gravity_scale: 1.0, health: 1.0 }
+    }
+
+    /// Run a character's movement for a sequence of (direction, jump)
+    /// inputs, one fixed step each, and return its final state.
+    fn simulate(map: &Map, start: Vector2, inputs: &[(f64, bool)], dt: f64) -> EntityPhysics {
+        let physics = Physics::default();
+        let mut entity = standing(start);
+        let mut movement = Movement::new();
+        let mut was_jumping = false;
+        for &(dir, jump) in inputs {
+            movement.dir = dir;
+            movement.jump_pressed = jump && !was_jumping;
+            movement.jump_released = !jump && was_jumping;
+            was_jumping = jump;
+            movement.step(&mut entity, dt, map, &physics, &[]);
+        }
+        entity
+    }
+
+    #[test]
+    fn test_run_right() {
+        let map = grid_map(&[
+            "..........",
+            "##########",
+        ]);
+        let ground = 1.0 + CHAR_H / 2.0;
+        let inputs = vec![(1.0, false); 60];
+        let end = simulate(&map, [2.0, ground], &inputs, 1.0 / 60.0);
+        assert!((end.pos.x() - 7.0).abs() < 1e-9);
+        assert!((end.pos.y() - ground).abs() < 1e-9);
+
+        // Runs into a wall
+        let map = grid_map(&[
+            "......#...",
+            "......#...",
+            "##########",
+        ]);
+        let end = simulate(&map, [2.0, ground], &inputs, 1.0 / 60.0);
+        assert!(end.pos.x() < 6.0 - CHAR_W / 2.0 + 1e-9);
+        assert!(end.pos.x() > 5.0);
+    }
+
+    #[test]
+    fn test_conveyor() {
+        let mut builder = MapFactoryBuilder::new();
+        builder.dimensions(10, 2);
+        builder.tile_type(TileTypeDefinition::default());
+        let mut wall = TileTypeDefinition::default();
+        wall.collide = true;
+        let wall = builder.tile_type(wall);
+        let mut belt = TileTypeDefinition::default();
+        belt.collide = true;
+        belt.surface_velocity = [2.0, 0.0];
+        let belt = builder.tile_type(belt);
+        builder.rect(0, 0, 10, 1, wall).rect(0, 0, 4, 1, belt);
+        let map_factory = builder.build().unwrap();
+        let world = map_factory.create_with(|_| unreachable!(), &entity_registry(), 1)
+            .unwrap();
+        let physics = Physics::default();
+        let mut entity = standing([1.0, 1.0 + CHAR_H / 2.0]);
+        let mut movement = Movement::new();
+        // Standing still on the belt drifts along with it
+        for _ in 0..30 {
+            movement.step(&mut entity, 1.0 / 60.0, &world.map, &physics, &[]);
+            assert_eq!(entity.speed.x(), 2.0);
+        }
+        assert!((entity.pos.x() - 2.0).abs() < 1e-9);
+        // Past the end of the belt, the drift stops
+        for _ in 0..90 {
+            movement.step(&mut entity, 1.0 / 60.0, &world.map, &physics, &[]);
+        }
+        assert_eq!(entity.speed.x(), 0.0);
+        assert!(entity.pos.x() >= 4.0 - 1e-9);
+        assert!(entity.pos.x() < 4.0 + 2.0 / 60.0 + 1e-9);
+        assert!((entity.pos.y() - (1.0 + CHAR_H / 2.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_gravity_scale() {
+        let map = grid_map(&[
+            "..........",
+            "..........",
+            "..........",
+            "..........",
+            "..........",
+            "##########",
+        ]);
+        let physics = Physics::default();
+        let fall = |gravity_scale: f64| {
+            let mut entity = standing([5.0, 4.0]);
+            entity.gravity_scale = gravity_scale;
+            let mut movement = Movement::new();
+            for _ in 0..20 {
+                movement.step(&mut entity, 1.0 / 60.0, &map, &physics, &[]);
+            }
+            4.0 - entity.pos.y()
+        };
+        let normal = fall(1.0);
+        assert!(normal > 0.0);
+        assert!((fall(0.2) - normal * 0.2).abs() < 1e-9);
+        assert!((fall(2.0) - normal * 2.0).abs() < 1e-9);
+        // Without gravity, it floats
+        assert_eq!(fall(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_ramp() {
+        let map = grid_map(&[
+            "........",
+            "..../###",
+            ".../####",
+            "########",
+        ]);
+        let physics = Physics::default();
+        let mut entity = standing([1.0, 1.0 + CHAR_H / 2.0]);
+        let mut movement = Movement::new();
+        // Walks up without leaving the ground
+        movement.dir = 1.0;
+        for _ in 0..60 {
+            let y = entity.pos.y();
+            movement.step(&mut entity, 1.0 / 60.0, &map, &physics, &[]);
+            assert_eq!(entity.speed.y(), 0.0);
+            assert!(entity.pos.y() >= y);
+        }
+        assert!((entity.pos.x() - 6.0).abs() < 1e-9);
+        assert!((entity.pos.y() - 3.0 - CHAR_H / 2.0).abs() < 1e-9);
+
+        // Walks back down without falling
+        movement.dir = -1.0;
+        for _ in 0..60 {
+            let y = entity.pos.y();
+            movement.step(&mut entity, 1.0 / 60.0, &map, &physics, &[]);
+            assert_eq!(entity.speed.y(), 0.0);
+            assert!(entity.pos.y() <= y);
+        }
+        assert!((entity.pos.x() - 1.0).abs() < 1e-9);
+        assert!((entity.pos.y() - 1.0 - CHAR_H / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_one_way_platform() {
+        let map = grid_map(&[
+            "....",
+            "....",
+            "====",
+            "####",
+        ]);
+        let physics = Physics::default();
+        let mut entity = standing([1.5, 1.0 + CHAR_H / 2.0]);
+        let mut movement = Movement::new();
+        // Jumps up through the platform, lands on it
+        let mut max_y = entity.pos.y();
+        for i in 0..120 {
+            movement.jump_pressed = i == 0;
+            movement.jump_released = i == 30;
+            movement.step(&mut entity, 1.0 / 60.0, &map, &physics, &[]);
+            max_y = max_y.max(entity.pos.y());
+        }
+        assert!(max_y > 2.0 + CHAR_H / 2.0);
+        assert!((entity.pos.y() - 2.0 - CHAR_H / 2.0).abs() < 1e-9);
+
+        // Drops through it with down and jump
+        for i in 0..120 {
+            movement.jump_pressed = i == 0;
+            movement.jump_released = i == 1;
+            movement.down = i == 0;
+            movement.step(&mut entity, 1.0 / 60.0, &map, &physics, &[]);
+        }
+        assert!((entity.pos.y() - 1.0 - CHAR_H / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_moving_platform() {
+        let map = grid_map(&[
+            "..........",
+            "..........",
+            "..........",
+        ]);
+        let physics = Physics::default();
+        let mut entity = standing([1.0, 2.0 + CHAR_H / 2.0]);
+        let mut platform = PlatformBounds {
+            min: [0.0, 1.5],
+            max: [2.0, 2.0],
+            velocity: [2.0, 0.0],
+        };
+        let mut movement = Movement::new();
+        // Rides along while standing still
+        for _ in 0..60 {
+            movement.step(&mut entity, 1.0 / 60.0, &map, &physics, &[platform]);
+            platform.min[0] += 2.0 / 60.0;
+            platform.max[0] += 2.0 / 60.0;
+        }
+        assert!((entity.pos.x() - 3.0).abs() < 1e-9);
+        assert!((entity.pos.y() - 2.0 - CHAR_H / 2.0).abs() < 1e-9);
+
+        // Falls without it
+        movement.step(&mut entity, 1.0 / 60.0, &map, &physics, &[]);
+        assert!(entity.pos.y() < 2.0 + CHAR_H / 2.0);
+    }
+
+    #[test]
+    fn test_ladder() {
+        let map = grid_map(&[
+            "......",
+            ".H....",
+            ".H....",
+            ".H....",
+            ".H....",
+            "######",
+        ]);
+        let physics = Physics::default();
+        let start = 1.0 + CHAR_H / 2.0;
+        let mut entity = standing([1.5, start]);
+        let mut movement = Movement::new();
+        // Pushing up climbs instead of jumping
+        movement.climb = 1.0;
+        for i in 0..30 {
+            movement.jump_pressed = i == 0;
+            movement.step(&mut entity, 1.0 / 60.0, &map, &physics, &[]);
+            assert_eq!(entity.speed.y(), CLIMB_SPEED);
+        }
+        assert!(!movement.jumped);
+        assert!((entity.pos.y() - start - 0.5 * CLIMB_SPEED).abs() < 1e-9);
+
+        // Holds on without falling
+        movement.climb = 0.0;
+        let y = entity.pos.y();
+        for i in 0..30 {
+            movement.jump_released = i == 0;
+            movement.step(&mut entity, 1.0 / 60.0, &map, &physics, &[]);
+        }
+        assert_eq!(entity.pos.y(), y);
+
+        // Falls once off the ladder
+        movement.dir = 1.0;
+        for _ in 0..30 {
+            movement.step(&mut entity, 1.0 / 60.0, &map, &physics, &[]);
+        }
+        assert!(!movement.climbing);
+        assert!(entity.pos.y() < y);
+    }
+
+    #[test]
+    fn test_variable_jump() {
+        let map = grid_map(&[
+            "..........",
+            "..........",
+            "..........",
+            "##########",
+        ]);
+        let ground = 1.0 + CHAR_H / 2.0;
+        let peak = |held: usize| {
+            let physics = Physics::default();
+            let mut entity = standing([2.0, ground]);
+            let mut movement = Movement::new();
+            let mut max_y = entity.pos.y();
+            for i in 0..120 {
+                movement.jump_pressed = i == 0;
+                movement.jump_released = i == held;
+                movement.step(&mut entity, 1.0 / 60.0, &map, &physics, &[]);
+                max_y = max_y.max(entity.pos.y());
+            }
+            assert!((entity.pos.y() - ground).abs() < 1e-9);
+            max_y - ground
+        };
+        let (tap, held) = (peak(3), peak(60));
+        assert!(tap < held - 0.5, "tap {}, held {}", tap, held);
+        // Letting go after the apex changes nothing
+        assert_eq!(peak(40), held);
+    }
+
+    #[test]
+    fn test_wall_jump() {
+        let map = grid_map(&[
+            "......#",
+            "......#",
+            "......#",
+            "......#",
+            "......#",
+            "#######",
+        ]);
+        let physics = Physics::default();
+        let mut entity = standing([6.0 - CHAR_W / 2.0, 4.0]);
+        let mut movement = Movement::new();
+        // Pressing into the wall slides down it slowly
+        movement.dir = 1.0;
+        for _ in 0..30 {
+            movement.step(&mut entity, 1.0 / 60.0, &map, &physics, &[]);
+            assert!(entity.speed.y() >= -WALL_SLIDE_SPEED);
+        }
+        assert!(movement.touching_wall_right && !movement.touching_wall_left);
+        assert!(entity.pos.y() < 4.0);
+
+        // Jumping pushes away from it
+        movement.jump_pressed = true;
+        movement.step(&mut entity, 1.0 / 60.0, &map, &physics, &[]);
+        assert!(movement.jumped);
+        assert_eq!(entity.speed, [-WALL_JUMP_SPEED_X, WALL_JUMP_SPEED_Y]);
+        assert!(entity.pos.x() < 6.0 - CHAR_W / 2.0);
+        assert!(!movement.touching_wall_right);
+    }
+
+    #[test]
+    fn test_no_tunneling() {
+        // Moving 2.5 tiles per step doesn't go through the thin wall
+        let map = grid_map(&[
+            "...#......",
+            "...#......",
+            "##########",
+        ]);
+        let ground = 1.0 + CHAR_H / 2.0;
+        let end = simulate(&map, [1.0, ground], &[(1.0, false); 4], 0.5);
+        assert!((end.pos.x() - (3.0 - CHAR_W / 2.0)).abs() < 1e-9);
+
+        // Falling fast doesn't go through the floor
+        let map = grid_map(&[
+            "..........",
+            "..........",
+            "..........",
+            "..........",
+            "##########",
+        ]);
+        let mut entity = standing([2.0, 4.0]);
+        entity.speed = [0.0, -40.0];
+        Movement::new().step(&mut entity, 0.1, &map, &Physics::default(), &[]);
+        assert!((entity.pos.y() - ground).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_forced_seed() {
+        assert_eq!(choose_seed(Some(12345)), 12345);
+        assert_eq!(choose_seed(Some(0)), 0);
+    }
+
+    #[test]
+    fn test_double_jump() {
+        let mut state = JumpState::new();
+        assert!(!state.update(true, false, 0.05));
+        // Jump from the ground
+        assert!(state.update(true, true, 0.05));
+        assert!(!state.update(false, false, 0.05));
+        // Jump in the air, once
+        assert!(state.update(false, true, 0.05));
+        assert!(!state.update(false, false, 0.05));
+        assert!(!state.update(false, true, 0.05));
+        // Landing gives the jumps back
+        assert!(!state.update(true, false, 0.05));
+        assert!(state.update(true, true, 0.05));
+    }
+
+    #[test]
+    fn test_coyote_time() {
+        // Walk off a ledge then jump within the grace time: two jumps left
+        let mut state = JumpState::new();
+        state.update(true, false, 0.05);
+        state.update(false, false, COYOTE_TIME / 2.0);
+        assert!(state.update(false, true, 0.01));
+        assert_eq!(state.jumps_remaining, 1);
+
+        // Too late: only the air jump is left
+        let mut state = JumpState::new();
+        state.update(true, false, 0.05);
+        state.update(false, false, COYOTE_TIME * 2.0);
+        assert!(state.update(false, true, 0.01));
+        assert!(!state.update(false, false, 0.01));
+        assert!(!state.update(false, true, 0.01));
+    }
+
+    #[test]
+    fn test_camera_resize() {
+        let mut camera = Camera {
+            aspect_ratio: 0.75,
+            pos: [0.0, 0.0],
+            size: 10.0,
+            config: CameraConfig::default(),
+            snap: true,
+            prev_pos: [0.0, 0.0],
+            prev_size: 10.0,
+        };
+        camera.resize(1280, 720);
+        assert_eq!(camera.aspect_ratio, 0.5625);
+        camera.resize(400, 800);
+        assert_eq!(camera.aspect_ratio, 2.0);
+        // Minimized
+        camera.resize(0, 0);
+        assert_eq!(camera.aspect_ratio, 2.0);
+    }
+
+    #[test]
+    fn test_projection() {
+        let mut camera = Camera {
+            aspect_ratio: 0.75,
+            pos: [2.0, 3.0],
+            size: 16.0,
+            config: CameraConfig::default(),
+            snap: true,
+            prev_pos: [0.0, 0.0],
+            prev_size: 16.0,
+        };
+        camera.resize(1600, 900);
+        assert_eq!(camera.view_rect(), ([10.0, 7.5], [8.0, 4.5]));
+
+        // A square in the world is a square on screen, upside down
+        let m = projection(&camera, 1600.0, 900.0);
+        let a = transform_pos(m, [4.0, 5.0]);
+        let b = transform_pos(m, [6.0, 7.0]);
+        assert_eq!(b[0] - a[0], 200.0);
+        assert_eq!(a[1] - b[1], 200.0);
+        // The view fills the window
+        assert_eq!(transform_pos(m, [2.0, 3.0]), [0.0, 900.0]);
+        assert_eq!(transform_pos(m, [18.0, 12.0]), [1600.0, 0.0]);
+
+        // And back
+        assert_eq!(screen_to_world(&camera, 1600.0, 900.0, [0.0, 900.0]), [2.0, 3.0]);
+        assert_eq!(screen_to_world(&camera, 1600.0, 900.0, [800.0, 450.0]), [10.0, 7.5]);
+        let a = screen_to_world(&camera, 1600.0, 900.0, transform_pos(m, [4.5, 5.25]));
+        assert!(vec2_len(vec2_sub(a, [4.5, 5.25])) < 1e-9);
+    }
+
+    #[test]
+    fn test_camera_follow() {
+        let mut camera = Camera {
+            aspect_ratio: 0.5,
+            pos: [0.0, 0.0],
+            size: 10.0,
+            config: CameraConfig {
+                smoothing: [0.5, 0.25],
+                deadzone: [1.0, 2.0],
+                max_speed: 10.0,
+            },
+            snap: true,
+            prev_pos: [0.0, 0.0],
+            prev_size: 10.0,
+        };
+        let target = |x: f64, y: f64| ([x - 5.0, y - 2.5], [x + 5.0, y + 2.5]);
+        let center = |camera: &Camera| camera.view_rect().0;
+
+        // Snapping on the target
+        let (a, b) = target(20.0, 10.0);
+        camera.follow(a, b, 0.01);
+        assert_eq!(center(&camera), [20.0, 10.0]);
+        assert_eq!(camera.size, 10.0);
+        camera.snap = false;
+
+        // Small movements within the dead zone don't move the camera
+        for &(x, y) in [(20.5, 10.0), (19.2, 11.5), (20.9, 8.1)].iter() {
+            let (a, b) = target(x, y);
+            camera.follow(a, b, 0.01);
+            assert_eq!(center(&camera), [20.0, 10.0]);
+        }
+
+        // Leaving it moves the camera towards its edge, smoothed per axis
+        let (a, b) = target(20.1, 12.2);
+        camera.follow(a, b, 1.0);
+        assert!((center(&camera).x() - 20.0).abs() < 1e-9);
+        assert!((center(&camera).y() - 10.05).abs() < 1e-9, "{:?}", center(&camera));
+
+        // Large movements are limited by the maximum speed
+        let (a, b) = target(100.0, 10.0);
+        let before = center(&camera);
+        camera.follow(a, b, 0.1);
+        assert!((center(&camera).x() - before.x() - 1.0).abs() < 1e-9);
+        assert_eq!(camera.size, 10.0);
+    }
+
+    #[test]
+    fn test_camera_clamp() {
+        let mut camera = Camera {
+            aspect_ratio: 0.5,
+            pos: [-3.0, 18.0],
+            size: 10.0,
+            config: CameraConfig::default(),
+            snap: true,
+            prev_pos: [0.0, 0.0],
+            prev_size: 10.0,
+        };
+        // Focus near the top-left corner of a 40x20 map
+        camera.clamp(40.0, 20.0);
+        assert_eq!(camera.pos, [0.0, 15.0]);
+        camera.pos = [35.0, -1.0];
+        camera.clamp(40.0, 20.0);
+        assert_eq!(camera.pos, [30.0, 0.0]);
+
+        // The map is narrower than the view: center it
+        camera.pos = [3.0, 2.0];
+        camera.clamp(6.0, 20.0);
+        assert_eq!(camera.pos, [-2.0, 2.0]);
+    }
+
+    #[test]
+    fn test_check_map() {
+        let mut map_factory = grid_map_factory(&[
+            "...",
+            "###",
+        ]);
+        assert!(check_map(&map_factory).is_ok());
+        map_factory.nb_players = 0;
+        match check_map(&map_factory) {
+            Err(GameError::NoPlayers) => {}
+            r => panic!("Unexpected result {:?}", r),
+        }
+
+        let map_factory = grid_map_factory(&[
+            "..7",
+            "###",
+        ]);
+        match check_map(&map_factory) {
+            Err(GameError::TileOutOfRange { x: 2, y: 1, tile: 7 }) => {}
+            r => panic!("Unexpected result {:?}", r),
+        }
+    }
+
+    /// Stands in for an entity type, without needing the resources.
+    #[derive(Debug)]
+    struct Typed(&'static str);
+
+    impl EntityLogic for Typed {
+        fn type_id(&self) -> Option<&str> {
+            Some(self.0)
+        }
+
+        fn update(&mut self, _entity: &mut EntityPhysics, _dt: f64,
+                  _world: &mut WorldView, _ctx: &SimContext,
+                  _sprite: &mut Option<Sprite>) -> bool {
+            true
+        }
+    }
+
+    /// Stands in for an entity drawn at some z-order.
+    #[derive(Debug)]
+    struct Layered(i32);
+
+    impl EntityLogic for Layered {
+        fn update(&mut self, _entity: &mut EntityPhysics, _dt: f64,
+                  _world: &mut WorldView, _ctx: &SimContext,
+                  _sprite: &mut Option<Sprite>) -> bool {
+            true
+        }
+
+        fn z_order(&self) -> i32 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_entity_draw_order() {
+        let map_factory = grid_map_factory(&["....", "####"]);
+        let mut world = map_factory.create_with(|_| unreachable!(), &entity_registry(), 1)
+            .unwrap();
+        world.entities.clear();
+        for &(z_order, y) in [(10, 1.0), (0, 1.0), (20, 1.0), (0, 3.0), (10, 1.0)].iter() {
+            world.entities.push(Entity::new([1.0, y], Layered(z_order)));
+        }
+
+        let mut order = Vec::new();
+        entity_draw_order(&world, 1.0, &mut order);
+        let entities: Vec<_> = order.iter().map(|item| item.entity).collect();
+        // Higher one first for the same z-order, then in order
+        assert_eq!(entities, vec![EntityRef::Free(3), EntityRef::Free(1), EntityRef::Free(0),
+                                  EntityRef::Free(4), EntityRef::Free(2)]);
+        let z_orders: Vec<_> = order.iter().map(|item| item.z_order).collect();
+        assert_eq!(z_orders, vec![0, 0, 10, 10, 20]);
+
+        // The buffer is reused
+        let capacity = order.capacity();
+        entity_draw_order(&world, 1.0, &mut order);
+        assert_eq!(order.len(), 5);
+        assert_eq!(order.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_players_dead() {
+        let character = || Entity::new([0.0, 0.0], Typed("f.character"));
+        let spawn = || Entity::new([0.0, 0.0], Typed("f.spawn"));
+        assert!(!players_dead(&[spawn(), character()], 0));
+        assert!(!players_dead(&[character(), character()], 0));
+        // Still waiting to be spawned
+        assert!(!players_dead(&[spawn()], 1));
+        assert!(players_dead(&[spawn()], 0));
+        assert!(players_dead(&[], 0));
+    }
+
+    #[test]
+    fn test_outline_hovered() {
+        let mut entities = vec![Enemy::new([3.0, 1.0], 3.0, 3.0, 0.0),
+                                Enemy::new([6.0, 1.0], 6.0, 6.0, 0.0),
+                                Entity::new([6.0, 1.0], Typed("f.spawn"))];
+        let outlined = |entities: &[Entity]| -> Vec<bool> {
+            entities.iter().map(|e| e.effect == Some(HOVER_OUTLINE)).collect()
+        };
+        outline_hovered(&mut entities, [3.1, 1.5]);
+        assert_eq!(outlined(&entities), [true, false, false]);
+        // Moves to the other enemy, the spawn point has no hitbox
+        outline_hovered(&mut entities, [6.0, 1.2]);
+        assert_eq!(outlined(&entities), [false, true, false]);
+        outline_hovered(&mut entities, [0.0, 5.0]);
+        assert_eq!(outlined(&entities), [false, false, false]);
+        // A flash isn't replaced or removed
+        let flash = DrawEffect::Flash([1.0, 0.0, 0.0, 1.0]);
+        entities[0].effect = Some(flash);
+        outline_hovered(&mut entities, [3.0, 1.5]);
+        assert_eq!(entities[0].effect, Some(flash));
+        outline_hovered(&mut entities, [0.0, 5.0]);
+        assert_eq!(entities[0].effect, Some(flash));
+    }
+
+    #[test]
+    fn test_triggers() {
+        let mut builder = room(9, 3);
+        let mut bell = TileTypeDefinition::default();
+        bell.trigger = Some(Trigger::PlaySound("bell".to_owned()));
+        let bell = builder.tile_type(bell);
+        let mut exit = TileTypeDefinition::default();
+        exit.trigger = Some(Trigger::CompleteLevel);
+        let exit = builder.tile_type(exit);
+        builder.rect(3, 1, 2, 1, bell).set_tile(7, 1, exit)
+            .entity("f.spawn", [1.5, 1.0]);
+        let mut game = headless_game(builder.build().unwrap());
+
+        // Walk right, across the bell tiles and onto the exit
+        let mut input = InputManager::new(1);
+        input.handle_event(&press(Key::Right));
+        let mut sounds = Vec::new();
+        for _ in 0..120 {
+            assert!(!game.world.level_complete);
+            game.step(1.0 / 60.0, &input, |name| sounds.push(name.to_owned()));
+            if game.world.level_complete {
+                break;
+            }
+        }
+        assert!(game.world.level_complete);
+        assert_eq!(sounds, vec!["bell".to_owned()]);
+    }
+
+    /// Start a game on a map without a window, with one character waiting
+    /// for a spawn point.
+    fn headless_game(map_factory: MapFactory) -> Game {
+        let mut world = map_factory.create_with(|_| unreachable!(), &entity_registry(), 1)
+            .unwrap();
+        world.spawnables.push(Box::new(SimpleSpawn::new(Box::new(Character::headless(0)))));
+        Game::with_world(world, 1, Rc::new(map_factory))
+    }
+
+    /// Step a game for a sequence of (steps, keys held) inputs.
+    fn run_inputs(game: &mut Game, inputs: &[(usize, &[Key])], dt: f64) {
+        for &(steps, keys) in inputs {
+            let mut input = InputManager::new(1);
+            for &key in keys {
+                input.handle_event(&press(key));
+            }
+            for _ in 0..steps {
+                game.step_headless(dt, &input);
+                input.update(dt);
+            }
+        }
+    }
+
+    /// The event of a key being pressed.
+    fn press(key: Key) -> Event {
+        Event::Input(Input::Button(ButtonArgs {
+            state: ButtonState::Press,
+            button: Button::Keyboard(key),
+            scancode: None,
+        }))
+    }
+
+    /// The character of the game, if it is spawned and alive.
+    fn character(game: &Game) -> Option<&Entity> {
+        game.world.entities.iter().find(|e| e.logic.type_id() == Some("f.character"))
+    }
+
+    /// A map with a floor and walls on the sides, to add to.
+    fn room(width: usize, height: usize) -> MapFactoryBuilder {
+        let mut builder = MapFactoryBuilder::new();
+        builder.dimensions(width, height);
+        builder.tile_type(TileTypeDefinition::default());
+        let mut wall = TileTypeDefinition::default();
+        wall.collide = true;
+        let wall = builder.tile_type(wall);
+        builder.rect(0, 0, width, 1, wall).rect(0, 0, 1, height, wall)
+            .rect(width - 1, 0, 1, height, wall);
+        builder
+    }
+
+    #[test]
+    fn test_run_inputs() {
+        let mut builder = room(10, 4);
+        builder.entity("f.spawn", [1.5, 1.0]);
+        let mut game = headless_game(builder.build().unwrap());
+        run_inputs(&mut game, &[(1, &[])], 1.0 / 120.0);
+        let start = character(&game).expect("Character wasn't spawned").physics.pos;
+
+        // Run right for a second, then stand still
+        run_inputs(&mut game, &[(120, &[Key::Right]), (60, &[])], 1.0 / 120.0);
+        let pos = character(&game).unwrap().physics.pos;
+        assert!((pos.x() - (start.x() + Physics::default().run_speed)).abs() < 1e-6,
+                "{:?} -> {:?}", start, pos);
+        assert!((pos.y() - (1.0 + CHAR_H / 2.0)).abs() < 1e-6, "{:?}", pos);
+    }
+
+    #[test]
+    fn test_shoot() {
+        let mut builder = room(30, 4);
+        builder.entity("f.spawn", [1.5, 1.0]);
+        let mut game = headless_game(builder.build().unwrap());
+        let bullets = |game: &Game| {
+            game.world.entities.iter().filter(|e| e.logic.type_id().is_none()).count()
+        };
+        run_inputs(&mut game, &[(2, &[])], 1.0 / 60.0);
+
+        // One shot per press
+        run_inputs(&mut game, &[(2, &[Key::RCtrl])], 1.0 / 60.0);
+        assert_eq!(bullets(&game), 1);
+        // Pressing again too soon doesn't fire
+        run_inputs(&mut game, &[(1, &[]), (2, &[Key::RCtrl])], 1.0 / 60.0);
+        assert_eq!(bullets(&game), 1);
+        // Holding the button doesn't fire once the cooldown is over
+        run_inputs(&mut game, &[(20, &[Key::RCtrl])], 1.0 / 60.0);
+        assert_eq!(bullets(&game), 1);
+        // Pressing again does
+        run_inputs(&mut game, &[(1, &[]), (1, &[Key::RCtrl])], 1.0 / 60.0);
+        assert_eq!(bullets(&game), 2);
+    }
+
+    #[test]
+    fn test_particles() {
+        let burst = || {
+            let mut game = headless_game(room(10, 6).build().unwrap());
+            game.world.add_entity(ParticleEmitter::new([5.0, 3.0], 8, (1.0, 2.0), 0.5, None,
+                                                       [1.0, 0.0, 0.0, 1.0]));
+            run_inputs(&mut game, &[(1, &[])], 1.0 / 60.0);
+            game
+        };
+        // The emitter is replaced by its particles, going in random
+        // directions, the same ones for the same seed
+        let mut game = burst();
+        assert_eq!(game.world.entities.len(), 8);
+        let speeds: Vec<Vector2> = game.world.entities.iter().map(|p| {
+            assert_eq!(p.physics.pos, [5.0, 3.0]);
+            assert_eq!(p.ttl, Some(0.5));
+            assert!(p.effect.is_some());
+            let speed = vec2_len(p.physics.speed);
+            assert!(speed >= 1.0 - 1e-9 && speed <= 2.0 + 1e-9);
+            p.physics.speed
+        }).collect();
+        assert!(speeds.iter().any(|s| s != &speeds[0]));
+        let again: Vec<Vector2> = burst().world.entities.iter().map(|p| p.physics.speed)
+            .collect();
+        assert_eq!(speeds, again);
+
+        // They go away after their lifetime
+        run_inputs(&mut game, &[(29, &[])], 1.0 / 60.0);
+        assert_eq!(game.world.entities.len(), 8);
+        run_inputs(&mut game, &[(2, &[])], 1.0 / 60.0);
+        assert!(game.world.entities.is_empty());
+    }
+
+    #[test]
+    fn test_lava() {
+        let mut builder = room(5, 4);
+        let mut lava = TileTypeDefinition::default();
+        lava.damage = 1.0;
+        let lava = builder.tile_type(lava);
+        builder.rect(1, 1, 3, 1, lava).entity("f.spawn", [2.5, 1.0]);
+        let mut game = headless_game(builder.build().unwrap());
+
+        // Standing in the lava wears the character's health down
+        run_inputs(&mut game, &[(1, &[])], 1.0 / 60.0);
+        let start = character(&game).expect("Character wasn't spawned").physics.health;
+        run_inputs(&mut game, &[(30, &[])], 1.0 / 60.0);
+        let end = character(&game).unwrap().physics.health;
+        assert!((start - end - 0.5).abs() < 1e-6, "{} -> {}", start, end);
+
+        // Until it dies
+        run_inputs(&mut game, &[(60, &[])], 1.0 / 60.0);
+        assert!(character(&game).is_none());
+    }
+
+    #[test]
+    fn test_map_gravity() {
+        let fall = |gravity: f64| {
+            let mut builder = room(5, 12);
+            let mut physics = Physics::default();
+            physics.gravity = gravity;
+            builder.physics(physics).entity("f.spawn", [2.5, 10.0]);
+            let mut game = headless_game(builder.build().unwrap());
+
+            run_inputs(&mut game, &[(1, &[])], 1.0 / 60.0);
+            let start = character(&game).expect("Character wasn't spawned").physics.pos.y();
+            run_inputs(&mut game, &[(20, &[])], 1.0 / 60.0);
+            start - character(&game).unwrap().physics.pos.y()
+        };
+        let normal = fall(Physics::default().gravity);
+        let heavy = fall(Physics::default().gravity * 2.0);
+        assert!(normal > 0.0);
+        assert!((heavy - normal * 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_enemy_contact() {
+        let mut builder = room(7, 4);
+        builder.entity("f.spawn", [3.0, 1.0]);
+        let mut game = headless_game(builder.build().unwrap());
+        game.world.add_entity(Enemy::new([3.0, 1.0], 3.0, 3.0, 0.0));
+
+        // Standing on the enemy hurts, for as long as they touch
+        run_inputs(&mut game, &[(60, &[])], 1.0 / 60.0);
+        let health = character(&game).expect("Character wasn't spawned").physics.health;
+        assert!(health > 0.4 && health < 0.6, "{}", health);
+    }
+
+    #[test]
+    fn test_enemy_sight() {
+        let enemy_x = |wall: bool| {
+            let mut builder = room(12, 4);
+            builder.entity("f.spawn", [2.5, 1.0]);
+            if wall {
+                let mut wall = TileTypeDefinition::default();
+                wall.collide = true;
+                let wall = builder.tile_type(wall);
+                builder.rect(5, 1, 1, 3, wall);
+            }
+            let mut game = headless_game(builder.build().unwrap());
+            game.world.add_entity(Enemy::new([8.0, 1.0], 8.0, 8.0, 6.0));
+            run_inputs(&mut game, &[(60, &[])], 1.0 / 60.0);
+            game.world.entities.iter().find(|e| e.logic.type_id() == Some("f.enemy"))
+                .unwrap().physics.pos.x()
+        };
+        // Chases the character it sees, stays put if it's behind a wall
+        assert!(enemy_x(false) < 6.0);
+        assert!((enemy_x(true) - 8.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_bullet_hit() {
+        // No spawn point, the character never comes in
+        let mut game = headless_game(room(10, 4).build().unwrap());
+        game.world.add_entity(Enemy::new([6.0, 1.0], 6.0, 6.0, 0.0));
+        game.world.add_entity(Bullet::fire([2.0, 1.4], false));
+
+        // The bullet hurts the enemy, and is gone
+        run_inputs(&mut game, &[(30, &[])], 1.0 / 60.0);
+        assert_eq!(game.world.entities.len(), 1);
+        let enemy = &game.world.entities[0];
+        assert_eq!(enemy.logic.type_id(), Some("f.enemy"));
+        assert_eq!(enemy.physics.health, 0.5);
+    }
+
+    #[test]
+    fn test_heal_pickup() {
+        let mut builder = room(9, 4);
+        builder.entity("f.spawn", [2.5, 1.0])
+            .entity("f.heal", [2.5, 1.5])
+            .entity("f.heal", [6.5, 1.5]);
+        let mut game = headless_game(builder.build().unwrap());
+        let items = |game: &Game| {
+            game.world.entities.iter()
+                .filter(|e| e.logic.type_id() == Some("f.heal"))
+                .count()
+        };
+
+        // Full health: the item is left there
+        run_inputs(&mut game, &[(10, &[])], 1.0 / 60.0);
+        assert_eq!(items(&game), 2);
+
+        // Hurt, the character takes the item it's on, not the far one
+        {
+            let character = game.world.entities.iter_mut()
+                .find(|e| e.logic.type_id() == Some("f.character"))
+                .expect("Character wasn't spawned");
+            character.physics.health = 0.25;
+        }
+        run_inputs(&mut game, &[(1, &[])], 1.0 / 60.0);
+        assert_eq!(items(&game), 1);
+        let character = character(&game).unwrap();
+        assert_eq!(character.physics.health, 0.75);
+        assert!(character.physics.pos.x() < 4.0);
+    }
+
+    #[test]
+    fn test_step_headless() {
+        let mut game = headless_game(grid_map_factory(&[
+            "#.......#",
+            "#S......#",
+            "#########",
+        ]));
+
+        // Hold right
+        run_inputs(&mut game, &[(1, &[Key::Right])], 1.0 / 120.0);
+        let start = character(&game).expect("Character wasn't spawned").physics.pos;
+        run_inputs(&mut game, &[(360, &[Key::Right])], 1.0 / 120.0);
+
+        // It walked to the wall on the right, and stopped there
+        let pos = character(&game).unwrap().physics.pos;
+        assert!(pos.x() > start.x() + 4.0, "{:?} -> {:?}", start, pos);
+        assert!(pos.x() + CHAR_W / 2.0 <= 8.0, "{:?}", pos);
+        assert!((pos.y() - (1.0 + CHAR_H / 2.0)).abs() < 1e-6, "{:?}", pos);
+
+        // Nothing moves while paused
+        game.paused = true;
+        let clock = game.world.clock;
+        let positions: Vec<Vector2> = game.world.entities.iter().map(|e| e.physics.pos).collect();
+        run_inputs(&mut game, &[(120, &[Key::Left])], 1.0 / 120.0);
+        assert_eq!(game.world.clock, clock);
+        assert_eq!(game.world.entities.iter().map(|e| e.physics.pos).collect::<Vec<_>>(),
+                   positions);
+    }
+
+    #[test]
+    fn test_free_camera() {
+        let mut game = headless_game(grid_map_factory(&[
+            "#...................#",
+            "#S..................#",
+            "#####################",
+        ]));
+        game.camera.pos = [30.0, 30.0];
+
+        // The character is ignored
+        game.camera_mode = CameraMode::Free;
+        run_inputs(&mut game, &[(1, &[])], 0.1);
+        assert_eq!(game.camera.pos, [30.0, 30.0]);
+
+        // Pan right
+        game.free_camera.handle_key(Key::D, true);
+        run_inputs(&mut game, &[(1, &[])], 0.1);
+        assert!((game.camera.pos.x() - 31.0).abs() < 1e-9, "{:?}", game.camera.pos);
+        assert_eq!(game.camera.pos.y(), 30.0);
+        game.free_camera.handle_key(Key::D, false);
+
+        // Zoom in, around the center
+        let (center, _) = game.camera.view_rect();
+        game.free_camera.scroll = 1.0;
+        run_inputs(&mut game, &[(1, &[])], 0.1);
+        assert!((game.camera.size - 9.0).abs() < 1e-9);
+        let (new_center, _) = game.camera.view_rect();
+        assert!(vec2_len(vec2_sub(new_center, center)) < 1e-9);
+
+        // Following again, the camera goes back to the character
+        game.camera_mode = CameraMode::Follow;
+        run_inputs(&mut game, &[(1, &[])], 0.1);
+        assert!(game.camera.pos.y() < 30.0, "{:?}", game.camera.pos);
+
+        // The keys panning the camera don't move the player bound to them
+        let mut players = InputManager::new(2);
+        game.camera_mode = CameraMode::Free;
+        game.route_input(&press(Key::D), &mut players);
+        assert!(game.free_camera.right);
+        assert_eq!(players.player_input(1).unwrap().x(), 0.0);
+        game.route_input(&press(Key::Right), &mut players);
+        assert_eq!(players.player_input(0).unwrap().x(), 1.0);
+        game.camera_mode = CameraMode::Follow;
+        game.free_camera = FreeCameraInput::default();
+        game.route_input(&press(Key::D), &mut players);
+        assert!(!game.free_camera.right);
+        assert_eq!(players.player_input(1).unwrap().x(), 1.0);
+    }
+
+    #[test]
+    fn test_draw_passes() {
+        let mut map = grid_map(&["#.", "##"]);
+        assert_eq!(draw_passes(&map), vec![DrawPass::Layer(0), DrawPass::Entities]);
+
+        // Foreground tiles are drawn after the entities
+        assert!(map.add_layer(TileLayer::new(RenderOrder::Foreground, vec![0, 0, 1, 0])));
+        assert!(map.add_layer(TileLayer::new(RenderOrder::Background, vec![0; 4])));
+        assert_eq!(draw_passes(&map), vec![DrawPass::Layer(0), DrawPass::Layer(1),
+                                           DrawPass::Entities, DrawPass::Layer(2)]);
+        assert_eq!(map.layers()[2].render_order, RenderOrder::Foreground);
+
+        // Collisions only use the main layer
+        assert!(map.tile(1, 1).map_or(false, |t| !t.collide));
+        assert!(!map.add_layer(TileLayer::new(RenderOrder::Main, vec![1; 4])));
+        assert!(!map.add_layer(TileLayer::new(RenderOrder::Foreground, vec![1])));
     }
 }
@@ -0,0 +1,116 @@
+//! Common setup for the states using conrod for their interface (menus).
+
+use std::path::Path;
+
+use conrod;
+use piston;
+use piston::window::Window;
+use piston_window::{self, Context, G2d, G2dTexture};
+use piston_window::texture::UpdateTexture;
+
+use ::Resources;
+
+/// A conrod `Ui` with what's needed to feed it events and draw it.
+pub struct Gui {
+    pub ui: conrod::Ui,
+    image_map: conrod::image::Map<G2dTexture>,
+    glyph_cache: conrod::text::GlyphCache,
+    text_texture_cache: G2dTexture,
+    text_vertex_data: Vec<u8>,
+}
+
+impl Gui {
+    pub fn new(resources: &mut Resources) -> Gui {
+        let window_size = resources.window.size();
+
+        // Construct our `Ui`.
+        let mut ui = conrod::UiBuilder::new([window_size.width as f64,
+                                             window_size.height as f64])
+            .build();
+
+        // Add a `Font` to the `Ui`'s `font::Map` from file.
+        let font_path = Path::new("assets/NotoSans-Regular.ttf");
+        assert!(font_path.exists());
+        ui.fonts.insert_from_file(font_path).unwrap();
+
+        // Create a texture to use for efficiently caching text on the GPU.
+        let (glyph_cache, text_texture_cache) = {
+            let cache =
+                conrod::text::GlyphCache::new(window_size.width,
+                                              window_size.height,
+                                              0.1, 0.1);
+            let buffer_len = window_size.width as usize * window_size.height as usize;
+            let init = vec![128; buffer_len];
+            let settings = piston_window::TextureSettings::new();
+            let factory = &mut resources.window.factory;
+            let texture = G2dTexture::from_memory_alpha(
+                factory, &init, window_size.width, window_size.height, &settings).unwrap();
+            (cache, texture)
+        };
+
+        Gui {
+            ui: ui,
+            // The image map describing each of our widget->image mappings
+            // (in our case, none).
+            image_map: conrod::image::Map::new(),
+            glyph_cache: glyph_cache,
+            text_texture_cache: text_texture_cache,
+            text_vertex_data: Vec::new(),
+        }
+    }
+
+    /// Convert the piston event to a conrod event and give it to the `Ui`.
+    pub fn handle_event(&mut self, event: &piston::input::Event, resources: &Resources) {
+        let window_size = resources.window.size();
+        if let Some(ce) = conrod::backend::piston::event::convert(
+            event.clone(), window_size.width as f64, window_size.height as f64)
+        {
+            self.ui.handle_event(ce);
+        }
+    }
+
+    /// Lay out the interface for the current window size.
+    ///
+    /// Resize events are handled by `handle_event()`, this is for states that
+    /// were paused while the window changed size.
+    pub fn fit_window(&mut self, resources: &Resources) {
+        let window_size = resources.window.size();
+        if window_size.width as f64 != self.ui.win_w ||
+            window_size.height as f64 != self.ui.win_h
+        {
+            self.ui.handle_event(conrod::event::Input::Resize(window_size.width,
+                                                              window_size.height));
+        }
+    }
+
+    pub fn draw(&mut self, c: Context, g: &mut G2d) {
+        let primitives = self.ui.draw();
+
+        let text_vertex_data = &mut self.text_vertex_data;
+        let cache_queued_glyphs = |graphics: &mut G2d,
+                                   cache: &mut G2dTexture,
+                                   rect: conrod::text::rt::Rect<u32>,
+                                   data: &[u8]|
+        {
+            let offset = [rect.min.x, rect.min.y];
+            let size = [rect.width(), rect.height()];
+            let format = piston_window::texture::Format::Rgba8;
+            let encoder = &mut graphics.encoder;
+            text_vertex_data.clear();
+            text_vertex_data.extend(
+                data.iter().flat_map(|&b| vec![255, 255, 255, b]));
+            UpdateTexture::update(cache, encoder, format,
+                                  &text_vertex_data[..], offset, size)
+                .expect("failed to update texture")
+        };
+
+        fn texture_from_image<T>(img: &T) -> &T { img };
+
+        conrod::backend::piston::draw::primitives(primitives, c, g,
+                                                  &mut self.text_texture_cache,
+                                                  &mut self.glyph_cache,
+                                                  &self.image_map,
+                                                  cache_queued_glyphs,
+                                                  texture_from_image);
+    }
+}
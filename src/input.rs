@@ -1,7 +1,52 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
 use piston;
 use piston::input::*;
+use sdl2;
+use sdl2::HapticSubsystem;
+use sdl2::haptic::Haptic;
+use serde_json;
+
+use vecmath::*;
+
+/// Default analog value past which the stick acts like a button (up jumps).
+pub const INPUT_THRESHOLD: f64 = 0.8;
+/// Analog values closer to zero than this are ignored (resting sticks).
+const DEADZONE: f64 = 0.15;
+/// The controller button used to shoot, the others jump.
+const CONTROLLER_SHOOT: u8 = 2;
+
+/// An action that can be bound to a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Left,
+    Right,
+    Up,
+    Down,
+    Jump,
+    Shoot,
+}
 
-const INPUT_THRESHOLD: f64 = 0.8;
+/// All the actions, in the order they are shown to the player.
+pub const ACTIONS: [Action; 6] = [
+    Action::Left,
+    Action::Right,
+    Action::Up,
+    Action::Down,
+    Action::Jump,
+    Action::Shoot,
+];
+
+/// The default keys for each player, as (left, right, up, down, shoot).
+const DEFAULT_BINDINGS: [(Key, Key, Key, Key, Key); 4] = [
+    (Key::Left, Key::Right, Key::Up, Key::Down, Key::RCtrl),
+    (Key::A, Key::D, Key::W, Key::S, Key::E),
+    (Key::J, Key::L, Key::I, Key::K, Key::O),
+    (Key::NumPad4, Key::NumPad6, Key::NumPad8, Key::NumPad5, Key::NumPad0),
+];
 
 pub struct PlayerInput {
     d_right: bool,
@@ -9,9 +54,24 @@ pub struct PlayerInput {
     d_up: bool,
     d_down: bool,
     d_jump: bool,
+    d_shoot: bool,
 
     a_x: f64,
     a_y: f64,
+
+    /// Analog value past which the stick acts like a button, see
+    /// `InputManager::set_threshold()`.
+    threshold: f64,
+
+    /// Whether jump was held on the previous frame, see
+    /// `InputManager::update()`.
+    prev_jump: bool,
+    /// Whether the stick moved since the last frame, analog values only
+    /// decay when it didn't.
+    analog_moved: bool,
+
+    /// Joystick used by this player, if not on the keyboard.
+    device: Option<i32>,
 }
 
 impl PlayerInput {
@@ -22,8 +82,13 @@ impl PlayerInput {
             d_up: false,
             d_down: false,
             d_jump: false,
+            d_shoot: false,
             a_x: 0.0,
             a_y: 0.0,
+            threshold: INPUT_THRESHOLD,
+            prev_jump: false,
+            analog_moved: false,
+            device: None,
         }
     }
 
@@ -48,49 +113,535 @@ impl PlayerInput {
         PlayerInput::da_input(self.a_y, self.d_down, self.d_up)
     }
 
+    pub fn down(&self) -> bool {
+        self.d_down || self.a_y < -self.threshold
+    }
+
     pub fn jump(&self) -> bool {
-        self.d_up || self.a_y > INPUT_THRESHOLD
+        self.d_jump || self.d_up || self.a_y > self.threshold
+    }
+
+    /// Whether jump started being held this frame.
+    pub fn jump_pressed(&self) -> bool {
+        self.jump() && !self.prev_jump
+    }
+
+    /// Whether jump stopped being held this frame.
+    pub fn jump_released(&self) -> bool {
+        !self.jump() && self.prev_jump
+    }
+
+    pub fn shoot(&self) -> bool {
+        self.d_shoot
+    }
+
+    fn set_action(&mut self, action: Action, pressed: bool) {
+        match action {
+            Action::Left => self.d_left = pressed,
+            Action::Right => self.d_right = pressed,
+            Action::Up => self.d_up = pressed,
+            Action::Down => self.d_down = pressed,
+            Action::Jump => self.d_jump = pressed,
+            Action::Shoot => self.d_shoot = pressed,
+        }
+    }
+}
+
+/// Move a value toward zero by `amount`, without crossing it.
+fn decay_toward_zero(value: f64, amount: f64) -> f64 {
+    if value.abs() <= amount {
+        0.0
+    } else {
+        value - amount * value.signum()
+    }
+}
+
+/// Something that can make controllers rumble.
+pub trait Haptics {
+    /// Rumble a joystick, with strength from 0 to 1, for a duration in
+    /// seconds.
+    fn rumble(&mut self, device: i32, strength: f32, duration: f64);
+}
+
+/// Force feedback through SDL2's haptic subsystem.
+pub struct SdlHaptics {
+    subsystem: HapticSubsystem,
+    /// Haptic devices, opened as needed.
+    devices: HashMap<i32, Haptic>,
+}
+
+impl SdlHaptics {
+    pub fn new(sdl: &sdl2::Sdl) -> Result<SdlHaptics, String> {
+        Ok(SdlHaptics {
+            subsystem: try!(sdl.haptic()),
+            devices: HashMap::new(),
+        })
+    }
+}
+
+impl Haptics for SdlHaptics {
+    fn rumble(&mut self, device: i32, strength: f32, duration: f64) {
+        if !self.devices.contains_key(&device) {
+            match self.subsystem.open_from_joystick_id(device) {
+                Ok(haptic) => {
+                    self.devices.insert(device, haptic);
+                }
+                Err(e) => {
+                    warn!("Can't open haptic device {}: {}", device, e);
+                    return;
+                }
+            }
+        }
+        let haptic = self.devices.get_mut(&device).unwrap();
+        haptic.rumble_play(strength, (duration * 1000.0) as u32);
     }
 }
 
 /// The input manager, mapping input events into player actions.
 pub struct InputManager {
     players: Vec<PlayerInput>,
+    /// Which player and action each key controls.
+    bindings: HashMap<Key, (usize, Action)>,
+    haptics: RefCell<Option<Box<Haptics>>>,
+    /// Analog value past which the sticks act like buttons.
+    threshold: f64,
+    /// How fast analog values go back to zero without input, per second.
+    analog_decay: f64,
+    /// Position of the mouse cursor in the window, once it moved.
+    cursor: Option<Vector2>,
+    /// Position of the mouse cursor in the world, see `set_aim_target()`.
+    aim_target: Option<Vector2>,
 }
 
 impl InputManager {
-    pub fn new() -> InputManager {
-        InputManager {
-            players: vec![PlayerInput::new()],
+    pub fn new(nb_players: usize) -> InputManager {
+        let mut bindings = HashMap::new();
+        for (player, keys) in DEFAULT_BINDINGS.iter().enumerate() {
+            bindings.insert(keys.0, (player, Action::Left));
+            bindings.insert(keys.1, (player, Action::Right));
+            bindings.insert(keys.2, (player, Action::Up));
+            bindings.insert(keys.3, (player, Action::Down));
+            bindings.insert(keys.4, (player, Action::Shoot));
+        }
+        let mut input_manager = InputManager {
+            players: Vec::new(),
+            bindings: bindings,
+            haptics: RefCell::new(None),
+            threshold: INPUT_THRESHOLD,
+            analog_decay: 0.0,
+            cursor: None,
+            aim_target: None,
+        };
+        input_manager.set_nb_players(nb_players);
+        input_manager
+    }
+
+    /// Change the number of players, keeping the state of existing ones.
+    pub fn set_nb_players(&mut self, nb_players: usize) {
+        self.players.truncate(nb_players);
+        while self.players.len() < nb_players {
+            let mut input = PlayerInput::new();
+            input.threshold = self.threshold;
+            self.players.push(input);
+        }
+    }
+
+    /// Set how far the sticks need to be pushed up or down to jump or drop,
+    /// from 0 to 1.
+    pub fn set_threshold(&mut self, threshold: f64) {
+        self.threshold = threshold;
+        for input in self.players.iter_mut() {
+            input.threshold = threshold;
+        }
+    }
+
+    /// Bind a key to a player's action, replacing the previous key for it.
+    pub fn set_binding(&mut self, player: usize, action: Action, key: Key) {
+        self.bindings.retain(|_, &mut binding| binding != (player, action));
+        self.bindings.insert(key, (player, action));
+    }
+
+    /// The key bound to a player's action, if any.
+    pub fn binding(&self, player: usize, action: Action) -> Option<Key> {
+        self.bindings.iter()
+            .find(|&(_, &binding)| binding == (player, action))
+            .map(|(&key, _)| key)
+    }
+
+    /// Write the key bindings as JSON.
+    pub fn save_bindings<W: Write>(&self, writer: W) -> serde_json::Result<()> {
+        let mut bindings: Vec<(Key, usize, Action)> = self.bindings.iter()
+            .map(|(&key, &(player, action))| (key, player, action))
+            .collect();
+        bindings.sort_by_key(|&(key, player, action)| {
+            (player, ACTIONS.iter().position(|&a| a == action), u32::from(key))
+        });
+        serde_json::to_writer_pretty(writer, &bindings)
+    }
+
+    /// Replace the key bindings with ones read from JSON.
+    pub fn load_bindings<R: Read>(&mut self, reader: R) -> serde_json::Result<()> {
+        let bindings: Vec<(Key, usize, Action)> = try!(serde_json::from_reader(reader));
+        self.bindings = bindings.into_iter()
+            .map(|(key, player, action)| (key, (player, action)))
+            .collect();
+        Ok(())
+    }
+
+    /// Make analog values go back to zero when the sticks don't move, by
+    /// this much per second, 0 to disable (the default).
+    ///
+    /// This is meant for devices that don't report going back to the center,
+    /// or to smooth out jitter; sticks held in place decay too.
+    pub fn set_analog_decay(&mut self, rate: f64) {
+        self.analog_decay = rate;
+    }
+
+    /// Set the backend used to make controllers rumble.
+    pub fn set_haptics(&mut self, haptics: Box<Haptics>) {
+        self.haptics = RefCell::new(Some(haptics));
+    }
+
+    /// The joystick a player is using, `None` for the keyboard.
+    pub fn device(&self, player: usize) -> Option<i32> {
+        self.players.get(player).and_then(|p| p.device)
+    }
+
+    /// Set the joystick a player is using, `None` for the keyboard.
+    pub fn set_device(&mut self, player: usize, device: Option<i32>) {
+        if let Some(input) = self.players.get_mut(player) {
+            input.device = device;
+        }
+    }
+
+    /// Make a player's controller rumble, if they use one.
+    pub fn rumble(&self, player: usize, strength: f32, duration: f64) {
+        let device = match self.players.get(player).and_then(|p| p.device) {
+            Some(device) => device,
+            None => return,
+        };
+        if let Some(ref mut haptics) = *self.haptics.borrow_mut() {
+            haptics.rumble(device, strength, duration);
         }
     }
 
     pub fn handle_event(&mut self, event: &piston::input::Event) {
-        if let Some((key, pressed)) = if let Some(Button::Keyboard(key)) = event.press_args() {
-            info!("Pressed key '{:?}'", key);
-            Some((key, true))
-        } else if let Some(Button::Keyboard(key)) = event.release_args() {
-            info!("Released key '{:?}", key);
-            Some((key, false))
+        let (button, pressed) = if let Some(button) = event.press_args() {
+            (button, true)
+        } else if let Some(button) = event.release_args() {
+            (button, false)
         } else {
-            None
-        } {
-            if key == Key::Right {
-                self.players[0].d_right = pressed;
-            } else if key == Key::Left {
-                self.players[0].d_left = pressed;
-            } else if key == Key::Up {
-                self.players[0].d_up = pressed;
-            } else if key == Key::Down {
-                self.players[0].d_down = pressed;
+            if let Some(args) = event.controller_axis_args() {
+                self.handle_axis(args.id, args.axis, args.position);
             }
+            if let Some(pos) = event.mouse_cursor_args() {
+                self.cursor = Some(pos);
+            }
+            return;
+        };
+        match button {
+            Button::Keyboard(key) => {
+                if pressed {
+                    info!("Pressed key '{:?}'", key);
+                } else {
+                    info!("Released key '{:?}'", key);
+                }
+                self.handle_key(key, pressed);
+            }
+            Button::Controller(button) => {
+                if let Some(player) = self.controller_player(button.id) {
+                    let action = if button.button == CONTROLLER_SHOOT {
+                        Action::Shoot
+                    } else {
+                        Action::Jump
+                    };
+                    self.players[player].set_action(action, pressed);
+                }
+            }
+            _ => {}
         }
     }
 
+    /// Find the player using a controller.
+    ///
+    /// A controller that wasn't seen before goes to the first player not
+    /// already using one.
+    fn controller_player(&mut self, id: i32) -> Option<usize> {
+        if let Some(player) = self.players.iter().position(|p| p.device == Some(id)) {
+            return Some(player);
+        }
+        let player = self.players.iter().position(|p| p.device.is_none());
+        if let Some(player) = player {
+            info!("Controller {} is used by player {}", id, player);
+            self.players[player].device = Some(id);
+        }
+        player
+    }
+
+    fn handle_axis(&mut self, id: i32, axis: u8, position: f64) {
+        let value = if position.abs() < DEADZONE { 0.0 } else { position };
+        if let Some(player) = self.controller_player(id) {
+            self.players[player].analog_moved = true;
+            match axis {
+                0 => self.players[player].a_x = value,
+                // Controllers have Y pointing down
+                1 => self.players[player].a_y = -value,
+                _ => {}
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: Key, pressed: bool) {
+        if let Some(&(player, action)) = self.bindings.get(&key) {
+            if let Some(input) = self.players.get_mut(player) {
+                input.set_action(action, pressed);
+            }
+        }
+    }
+
+    /// Advance to the next frame, after the inputs were read.
+    ///
+    /// This is what makes `jump_pressed()` and `jump_released()` only true on
+    /// the frame where the state changed, and decays the analog values, see
+    /// `set_analog_decay()`.
     pub fn update(&mut self, dt: f64) {
+        let decay = self.analog_decay * dt;
+        for input in self.players.iter_mut() {
+            input.prev_jump = input.jump();
+            if decay > 0.0 && !input.analog_moved {
+                input.a_x = decay_toward_zero(input.a_x, decay);
+                input.a_y = decay_toward_zero(input.a_y, decay);
+            }
+            input.analog_moved = false;
+        }
+    }
+
+    /// Position of the mouse cursor in the window, if it moved.
+    pub fn cursor(&self) -> Option<Vector2> {
+        self.cursor
+    }
+
+    /// Set the point in the world that the mouse cursor is over.
+    ///
+    /// This depends on the camera, so the game converts `cursor()` itself.
+    pub fn set_aim_target(&mut self, target: Option<Vector2>) {
+        self.aim_target = target;
+    }
+
+    /// Direction from a point in the world toward the mouse cursor, that
+    /// player one aims at, or `[0, 0]` if the mouse isn't used.
+    pub fn aim_direction(&self, from: Vector2) -> Vector2 {
+        match self.aim_target {
+            Some(target) => vec2_sub(target, from).normalized(),
+            None => [0.0, 0.0],
+        }
     }
 
     pub fn player_input(&self, player: usize) -> Option<&PlayerInput> {
         self.players.get(player)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use piston::input::{Button, ButtonArgs, ButtonState, ControllerAxisArgs, ControllerButton,
+                        Event, Input, Key, Motion};
+
+    use super::{Action, Haptics, InputManager};
+
+    struct FakeHaptics {
+        calls: Rc<RefCell<Vec<(i32, f32, f64)>>>,
+    }
+
+    impl Haptics for FakeHaptics {
+        fn rumble(&mut self, device: i32, strength: f32, duration: f64) {
+            self.calls.borrow_mut().push((device, strength, duration));
+        }
+    }
+
+    #[test]
+    fn test_rumble_dispatch() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut input_manager = InputManager::new(1);
+        input_manager.set_haptics(Box::new(FakeHaptics { calls: calls.clone() }));
+
+        // Keyboard player: nothing to rumble
+        input_manager.rumble(0, 0.5, 0.2);
+        assert!(calls.borrow().is_empty());
+
+        input_manager.set_device(0, Some(3));
+        input_manager.rumble(0, 0.5, 0.2);
+        input_manager.rumble(1, 1.0, 0.2);
+        assert_eq!(*calls.borrow(), vec![(3, 0.5, 0.2)]);
+    }
+
+    #[test]
+    fn test_player_bindings() {
+        let mut input_manager = InputManager::new(2);
+        input_manager.handle_key(Key::D, true);
+        assert_eq!(input_manager.player_input(0).unwrap().x(), 0.0);
+        assert_eq!(input_manager.player_input(1).unwrap().x(), 1.0);
+        input_manager.handle_key(Key::Left, true);
+        assert_eq!(input_manager.player_input(0).unwrap().x(), -1.0);
+        assert_eq!(input_manager.player_input(1).unwrap().x(), 1.0);
+        // No player 3 in this game
+        input_manager.handle_key(Key::I, true);
+        assert!(input_manager.player_input(2).is_none());
+
+        // Remapping
+        input_manager.set_binding(1, Action::Jump, Key::Space);
+        input_manager.set_binding(1, Action::Jump, Key::Q);
+        input_manager.handle_key(Key::Space, true);
+        assert!(!input_manager.player_input(1).unwrap().jump());
+        input_manager.handle_key(Key::Q, true);
+        assert!(input_manager.player_input(1).unwrap().jump());
+
+        input_manager.handle_key(Key::RCtrl, true);
+        assert!(input_manager.player_input(0).unwrap().shoot());
+        assert!(!input_manager.player_input(1).unwrap().shoot());
+
+        assert!(!input_manager.player_input(0).unwrap().down());
+        input_manager.handle_key(Key::Down, true);
+        assert!(input_manager.player_input(0).unwrap().down());
+    }
+
+    #[test]
+    fn test_save_bindings() {
+        let mut input_manager = InputManager::new(1);
+        assert_eq!(input_manager.binding(0, Action::Left), Some(Key::Left));
+        assert_eq!(input_manager.binding(0, Action::Jump), None);
+        input_manager.set_binding(0, Action::Jump, Key::Space);
+
+        let mut saved = Vec::new();
+        input_manager.save_bindings(&mut saved).unwrap();
+        let mut loaded = InputManager::new(1);
+        loaded.set_binding(0, Action::Left, Key::Q);
+        loaded.load_bindings(&saved[..]).unwrap();
+        assert_eq!(loaded.binding(0, Action::Left), Some(Key::Left));
+        assert_eq!(loaded.binding(0, Action::Jump), Some(Key::Space));
+        assert_eq!(loaded.binding(1, Action::Shoot), Some(Key::E));
+    }
+
+    fn axis(id: i32, axis: u8, position: f64) -> Event {
+        Event::Input(Input::Move(ControllerAxisArgs::new(id, axis, position).into()))
+    }
+
+    #[test]
+    fn test_controllers() {
+        let mut input_manager = InputManager::new(2);
+        input_manager.handle_event(&axis(7, 0, 0.9));
+        input_manager.handle_event(&axis(4, 0, -0.5));
+        assert_eq!(input_manager.player_input(0).unwrap().x(), 0.9);
+        assert_eq!(input_manager.player_input(1).unwrap().x(), -0.5);
+
+        // Deadzone
+        input_manager.handle_event(&axis(7, 0, 0.1));
+        assert_eq!(input_manager.player_input(0).unwrap().x(), 0.0);
+
+        // Up on the stick or any button jumps
+        input_manager.handle_event(&axis(4, 1, -0.9));
+        assert!(input_manager.player_input(1).unwrap().jump());
+        input_manager.handle_event(&axis(4, 1, 0.0));
+        assert!(!input_manager.player_input(1).unwrap().jump());
+        let button = Button::Controller(ControllerButton::new(7, 0));
+        input_manager.handle_event(&Event::Input(Input::Button(ButtonArgs {
+            state: ButtonState::Press,
+            button: button,
+            scancode: None,
+        })));
+        assert!(input_manager.player_input(0).unwrap().jump());
+        assert!(!input_manager.player_input(0).unwrap().shoot());
+        let button = Button::Controller(ControllerButton::new(4, 2));
+        input_manager.handle_event(&Event::Input(Input::Button(ButtonArgs {
+            state: ButtonState::Press,
+            button: button,
+            scancode: None,
+        })));
+        assert!(input_manager.player_input(1).unwrap().shoot());
+        assert!(!input_manager.player_input(1).unwrap().jump());
+
+        // No player left for a third controller
+        input_manager.handle_event(&axis(9, 0, 1.0));
+        assert_eq!(input_manager.player_input(0).unwrap().x(), 0.0);
+        assert_eq!(input_manager.player_input(1).unwrap().x(), -0.5);
+    }
+
+    #[test]
+    fn test_jump_edges() {
+        let mut input_manager = InputManager::new(1);
+        input_manager.handle_key(Key::Up, true);
+        assert!(input_manager.player_input(0).unwrap().jump_pressed());
+
+        // Held
+        for _ in 0..3 {
+            input_manager.update(1.0 / 60.0);
+            assert!(input_manager.player_input(0).unwrap().jump());
+            assert!(!input_manager.player_input(0).unwrap().jump_pressed());
+            assert!(!input_manager.player_input(0).unwrap().jump_released());
+        }
+
+        input_manager.handle_key(Key::Up, false);
+        assert!(input_manager.player_input(0).unwrap().jump_released());
+        input_manager.update(1.0 / 60.0);
+        assert!(!input_manager.player_input(0).unwrap().jump_released());
+    }
+
+    #[test]
+    fn test_analog_decay() {
+        let mut input_manager = InputManager::new(1);
+        input_manager.handle_event(&axis(2, 0, -0.9));
+        input_manager.handle_event(&axis(2, 1, 0.5));
+        // Disabled by default
+        input_manager.update(0.1);
+        input_manager.update(0.1);
+        assert_eq!(input_manager.player_input(0).unwrap().x(), -0.9);
+
+        input_manager.set_analog_decay(2.0);
+        input_manager.handle_event(&axis(2, 0, -0.9));
+        // Not on the frame the stick moved
+        input_manager.update(0.1);
+        assert_eq!(input_manager.player_input(0).unwrap().x(), -0.9);
+        let mut previous = -0.9;
+        for _ in 0..10 {
+            input_manager.update(0.1);
+            let input = input_manager.player_input(0).unwrap();
+            assert!(input.x() <= 0.0 && input.x() >= previous);
+            previous = input.x();
+        }
+        assert_eq!(input_manager.player_input(0).unwrap().x(), 0.0);
+        assert_eq!(input_manager.player_input(0).unwrap().y(), 0.0);
+    }
+
+    #[test]
+    fn test_aim_direction() {
+        let mut input_manager = InputManager::new(1);
+        assert_eq!(input_manager.aim_direction([1.0, 2.0]), [0.0, 0.0]);
+        input_manager.handle_event(&Event::Input(Input::Move(Motion::MouseCursor(30.0, 40.0))));
+        assert_eq!(input_manager.cursor(), Some([30.0, 40.0]));
+
+        input_manager.set_aim_target(Some([4.0, 6.0]));
+        let dir = input_manager.aim_direction([1.0, 2.0]);
+        assert!((dir[0] - 0.6).abs() < 1e-9 && (dir[1] - 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_threshold() {
+        let mut input_manager = InputManager::new(1);
+        input_manager.handle_event(&axis(2, 1, -0.5));
+        assert!(!input_manager.player_input(0).unwrap().jump());
+
+        input_manager.set_threshold(0.4);
+        assert!(input_manager.player_input(0).unwrap().jump());
+        input_manager.handle_event(&axis(2, 1, 0.5));
+        assert!(!input_manager.player_input(0).unwrap().jump());
+        assert!(input_manager.player_input(0).unwrap().down());
+
+        // Kept for players that join later
+        input_manager.set_nb_players(2);
+        input_manager.handle_event(&axis(3, 1, -0.5));
+        assert!(input_manager.player_input(1).unwrap().jump());
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt::{self, Debug, Formatter};
+
+use graphics::{self, Rectangle};
+use piston_window::{Context, G2d};
+
+use ::{GameState, Resources, StateTransition};
+
+/// How far along a loading job is.
+pub enum LoadProgress {
+    /// Still working, with the fraction done so far (from 0 to 1).
+    Working(f64),
+    /// Done, this state replaces the loading screen and the state below it.
+    Done(Box<GameState>),
+    /// Loading failed, going back to the previous state.
+    Failed(String),
+}
+
+/// Loading work, done a bit each time it is called.
+pub type LoadJob = Box<FnMut(&mut Resources) -> LoadProgress>;
+
+/// Run a step of a loading job, keeping track of its progress.
+fn advance<C>(job: &mut FnMut(&mut C) -> LoadProgress, context: &mut C,
+              progress: &mut f64) -> StateTransition {
+    match job(context) {
+        LoadProgress::Working(p) => {
+            *progress = p.max(0.0).min(1.0);
+            StateTransition::Continue
+        }
+        LoadProgress::Done(state) => {
+            *progress = 1.0;
+            StateTransition::Batch(vec![StateTransition::End, StateTransition::Replace(state)])
+        }
+        LoadProgress::Failed(e) => {
+            error!("Loading failed: {}", e);
+            StateTransition::End
+        }
+    }
+}
+
+/// Screen showing a progress bar while a job loads the next state, across
+/// frames so the window stays responsive.
+///
+/// It is pushed over the state starting the job, that it returns to if the
+/// job fails.
+pub struct Loading {
+    job: LoadJob,
+    /// Fraction of the job done, from 0 to 1.
+    progress: f64,
+}
+
+impl Debug for Loading {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Loading({:.0}%)", self.progress * 100.0)
+    }
+}
+
+impl Loading {
+    pub fn new(job: LoadJob) -> Loading {
+        Loading {
+            job: job,
+            progress: 0.0,
+        }
+    }
+}
+
+impl GameState for Loading {
+    fn update(&mut self, dt: f64, resources: &mut Resources) -> StateTransition {
+        advance(&mut *self.job, resources, &mut self.progress)
+    }
+
+    fn draw(&mut self, c: Context, g: &mut G2d, alpha: f64) {
+        graphics::clear([0.0, 0.0, 0.0, 1.0], g);
+        let size = c.get_view_size();
+        let bar = [size[0] * 0.25, size[1] * 0.5 - 10.0, size[0] * 0.5, 20.0];
+        Rectangle::new([1.0, 1.0, 1.0, 1.0])
+            .draw([bar[0], bar[1], bar[2] * self.progress, bar[3]],
+                  &c.draw_state, c.transform, g);
+        Rectangle::new_border([1.0, 1.0, 1.0, 1.0], 1.0)
+            .draw(bar, &c.draw_state, c.transform, g);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use piston_window::{Context, G2d};
+
+    use ::{GameState, Resources, StateTransition};
+    use super::{LoadProgress, advance};
+
+    #[derive(Debug)]
+    struct Loaded;
+
+    impl GameState for Loaded {
+        fn update(&mut self, dt: f64, resources: &mut Resources) -> StateTransition {
+            StateTransition::Continue
+        }
+
+        fn draw(&mut self, c: Context, g: &mut G2d, alpha: f64) {}
+    }
+
+    #[test]
+    fn test_loading() {
+        // Loads one item per call
+        let mut job = |items: &mut Vec<u32>| {
+            if items.len() < 4 {
+                items.push(items.len() as u32);
+                LoadProgress::Working(items.len() as f64 / 4.0)
+            } else {
+                LoadProgress::Done(Box::new(Loaded))
+            }
+        };
+        let mut items = Vec::new();
+        let mut progress = 0.0;
+        let mut calls = 0;
+        loop {
+            calls += 1;
+            assert!(calls < 10);
+            match advance(&mut job, &mut items, &mut progress) {
+                StateTransition::Continue => assert_eq!(progress, items.len() as f64 / 4.0),
+                // The loading screen and the menu below are replaced
+                StateTransition::Batch(transitions) => {
+                    assert_eq!(transitions.len(), 2);
+                    match (&transitions[0], &transitions[1]) {
+                        (&StateTransition::End, &StateTransition::Replace(_)) => break,
+                        _ => panic!("Unexpected transitions"),
+                    }
+                }
+                _ => panic!("Unexpected transition"),
+            }
+        }
+        assert_eq!(calls, 5);
+        assert_eq!(progress, 1.0);
+
+        let mut job = |_: &mut ()| LoadProgress::Failed("no such map".to_owned());
+        match advance(&mut job, &mut (), &mut progress) {
+            StateTransition::End => {}
+            _ => panic!("Unexpected transition"),
+        }
+    }
+}
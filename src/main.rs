@@ -2,13 +2,22 @@
 extern crate env_logger;
 extern crate gfx_core;
 extern crate graphics;
+extern crate image;
 #[macro_use] extern crate log;
 extern crate piston;
 extern crate piston_window;
+extern crate rand;
+extern crate rodio;
+extern crate sdl2;
 extern crate sdl2_window;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate serde_json;
 extern crate vecmath as vecmath_lib;
+extern crate xml;
 
 use std::fmt::Debug;
+use std::path::PathBuf;
 use std::rc::Rc;
 
 use gfx_core::Device;
@@ -17,19 +26,40 @@ use piston_window::{Context, G2d, OpenGL, PistonWindow};
 use piston::input::*;
 use sdl2_window::Sdl2Window;
 
+mod audio;
+mod bundle;
+mod confirm;
+mod controls;
 mod entities;
 mod game;
+mod gui;
 mod input;
+mod loading;
+mod menu;
+mod options;
+mod spatial;
 mod sprites;
 mod utils;
 mod vecmath;
 mod world;
 
-use input::InputManager;
-use sprites::{SpriteManager, SpriteSheet};
+use audio::SoundManager;
+use input::{InputManager, SdlHaptics};
+use sprites::{FilterMode, SpriteError, SpriteHandle, SpriteManager, SpriteSheet};
+use utils::{FixedStep, clamp_dt};
+use world::EntityRegistry;
 
 type Window = PistonWindow<Sdl2Window>;
 
+/// Duration of a simulation step, independent of the frame rate.
+const UPDATE_DT: f64 = 1.0 / 120.0;
+/// Most simulation steps to run per frame, slowing the game down rather than
+/// lagging further behind.
+const MAX_UPDATES_PER_FRAME: u32 = 8;
+/// Most time a frame can advance the simulation, longer frames (window moved
+/// or unfocused) being skipped.
+const MAX_FRAME_DT: f64 = 0.1;
+
 /// A transition requested by a game state.
 pub enum StateTransition {
     /// Stay on this state.
@@ -42,6 +72,9 @@ pub enum StateTransition {
     Push(Box<GameState>),
     /// Quit the game completely.
     Quit,
+    /// Several transitions, applied in order, for example ending this state
+    /// and pushing two others. They can't be `Continue`.
+    Batch(Vec<StateTransition>),
 }
 
 /// A state the game can be in.
@@ -58,20 +91,63 @@ pub trait GameState: Debug {
         StateTransition::Continue
     }
     fn update(&mut self, dt: f64, resources: &mut Resources) -> StateTransition;
-    fn draw(&mut self, c: Context, g: &mut G2d);
+    /// Draw the state, `alpha` being how far along the next update we are
+    /// (from 0 to 1), to interpolate positions.
+    fn draw(&mut self, c: Context, g: &mut G2d, alpha: f64);
     fn pause(&mut self, resources: &mut Resources) {}
     fn resume(&mut self, resources: &mut Resources) {}
 }
 
+/// User preferences, kept across game states.
+pub struct Settings {
+    /// Master volume, between 0 and 1.
+    pub master_volume: f32,
+    /// Size of the window, in pixels.
+    pub resolution: (u32, u32),
+    /// How far the sticks need to be pushed to jump or drop, from 0 to 1.
+    pub stick_threshold: f64,
+    /// How fast the sticks go back to the center without input, per second,
+    /// 0 to disable.
+    pub analog_decay: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            master_volume: 1.0,
+            resolution: (800, 600),
+            stick_threshold: input::INPUT_THRESHOLD,
+            analog_decay: 0.0,
+        }
+    }
+}
+
 pub struct Resources {
     window: Window,
     input_manager: InputManager,
     sprite_manager: SpriteManager,
+    sound_manager: SoundManager,
+    settings: Settings,
+    /// The entity types maps can use.
+    entity_registry: Rc<EntityRegistry>,
+    /// Seed to use for new games, instead of a random one.
+    forced_seed: Option<u32>,
 }
 
 impl Resources {
-    pub fn load_spritesheet(&mut self, name: &str) -> Rc<SpriteSheet> {
-        self.sprite_manager.load(&mut self.window, name)
+    /// Load a sprite sheet, with crisp pixels when it is scaled up.
+    pub fn load_spritesheet(&mut self, name: &str) -> Result<Rc<SpriteSheet>, SpriteError> {
+        self.load_spritesheet_filtered(name, FilterMode::default())
+    }
+
+    pub fn load_spritesheet_filtered(&mut self, name: &str,
+                                     filter: FilterMode) -> Result<Rc<SpriteSheet>, SpriteError> {
+        self.sprite_manager.load(&mut self.window, name, filter)
+    }
+
+    /// Load a sprite sheet in the background, see `SpriteManager::load_async()`.
+    pub fn load_spritesheet_async(&mut self, name: &str) -> SpriteHandle {
+        self.sprite_manager.load_async(name, FilterMode::default())
     }
 }
 
@@ -81,9 +157,9 @@ struct App {
 }
 
 impl App {
-    fn new() -> App {
-        let width = 800;
-        let height = 600;
+    fn new(forced_seed: Option<u32>, levels: Vec<PathBuf>) -> App {
+        let settings = Settings::default();
+        let (width, height) = settings.resolution;
 
         // Change this to OpenGL::V2_1 if not working.
         let opengl = OpenGL::V3_2;
@@ -94,21 +170,38 @@ impl App {
                 [width, height],
             )
             .opengl(opengl)
+            .controllers(true)
             .build()
             .unwrap();
         info!("Window created");
 
+        let mut input_manager = InputManager::new(1);
+        controls::load_controls(&mut input_manager);
+        input_manager.set_threshold(settings.stick_threshold);
+        input_manager.set_analog_decay(settings.analog_decay);
+        match SdlHaptics::new(&window.window.sdl_context) {
+            Ok(haptics) => input_manager.set_haptics(Box::new(haptics)),
+            Err(e) => warn!("Can't initialize haptics: {}", e),
+        }
+
+        let mut sound_manager = SoundManager::new();
+        sound_manager.set_volume(settings.master_volume);
+
         let mut app = App {
             states: Vec::new(),
             resources: Resources {
                 window: window,
-                input_manager: InputManager::new(),
+                input_manager: input_manager,
                 sprite_manager: SpriteManager::new(),
+                sound_manager: sound_manager,
+                settings: settings,
+                entity_registry: Rc::new(entities::entity_registry()),
+                forced_seed: forced_seed,
             },
         };
-        let game = game::Game::new(world::MapFactory::example(), &mut app.resources);
-        app.states.push(Box::new(game));
-        info!("Game state created");
+        let menu = menu::MainMenu::new(&mut app.resources, levels);
+        app.states.push(Box::new(menu));
+        info!("Main menu created");
         app
     }
 
@@ -119,26 +212,8 @@ impl App {
             } else {
                 break
             };
-            match transition {
-                StateTransition::Continue => panic!("App::run() got Transition::Continue"),
-                StateTransition::End => {
-                    let previous = self.states.pop().expect("Transition::End with no states");
-                    info!("Dropped {:?}", previous);
-                }
-                StateTransition::Replace(state) => {
-                    let previous = self.states.pop().expect("Transition::Replace with no states");
-                    info!("Dropped {:?}", previous);
-                    info!("Created {:?}", state);
-                    self.states.push(state);
-                }
-                StateTransition::Push(state) => {
-                    info!("Created {:?}", state);
-                    self.states.push(state);
-                }
-                StateTransition::Quit => {
-                    info!("Exiting...");
-                    break
-                },
+            if !apply_transition(&mut self.states, transition) {
+                break
             }
         }
     }
@@ -147,6 +222,7 @@ impl App {
         info!("Executing {:?}", state);
         state.resume(resources);
 
+        let mut fixed_step = FixedStep::new(UPDATE_DT, MAX_UPDATES_PER_FRAME);
         while let Some(event) = resources.window.next() {
             // Handle generic event
             let transition = state.handle_event(&event, resources);
@@ -158,21 +234,27 @@ impl App {
                 }
             }
 
-            // Call update method
+            // Call update method, in fixed steps
             if let Some(u) = event.update_args() {
-                let transition = state.update(u.dt, resources);
-                match transition {
-                    StateTransition::Continue => {},
-                    t => {
-                        state.pause(resources);
-                        return t;
+                // Finish loading the sprite sheets read in the background
+                resources.sprite_manager.poll(&mut resources.window);
+
+                for _ in 0..fixed_step.advance(clamp_dt(u.dt, MAX_FRAME_DT)) {
+                    let transition = state.update(fixed_step.dt, resources);
+                    match transition {
+                        StateTransition::Continue => {},
+                        t => {
+                            state.pause(resources);
+                            return t;
+                        }
                     }
                 }
             }
 
             // Call draw method
             if let Some(r) = event.render_args() {
-                resources.window.draw_2d(&event, |c, g| state.draw(c, g));
+                let alpha = fixed_step.alpha();
+                resources.window.draw_2d(&event, |c, g| state.draw(c, g, alpha));
                 resources.window.device.cleanup();
             }
         }
@@ -182,10 +264,123 @@ impl App {
     }
 }
 
+/// Change the stack of states as requested by the top one, returns false to
+/// quit.
+fn apply_transition(states: &mut Vec<Box<GameState>>, transition: StateTransition) -> bool {
+    match transition {
+        StateTransition::Continue => panic!("App::run() got Transition::Continue"),
+        StateTransition::End => {
+            let previous = states.pop().expect("Transition::End with no states");
+            info!("Dropped {:?}", previous);
+        }
+        StateTransition::Replace(state) => {
+            let previous = states.pop().expect("Transition::Replace with no states");
+            info!("Dropped {:?}", previous);
+            info!("Created {:?}", state);
+            states.push(state);
+        }
+        StateTransition::Push(state) => {
+            info!("Created {:?}", state);
+            states.push(state);
+        }
+        StateTransition::Quit => {
+            info!("Exiting...");
+            return false;
+        }
+        StateTransition::Batch(transitions) => {
+            for transition in transitions {
+                if let StateTransition::Continue = transition {
+                    panic!("Transition::Batch contains Transition::Continue");
+                }
+                if !apply_transition(states, transition) {
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Read the command-line: `[--seed <number>] [level.tmx...]`.
+fn parse_args() -> (Option<u32>, Vec<PathBuf>) {
+    let mut seed = None;
+    let mut levels = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            match args.next().map(|s| s.parse()) {
+                Some(Ok(s)) => seed = Some(s),
+                _ => warn!("--seed expects a number"),
+            }
+        } else {
+            levels.push(PathBuf::from(arg));
+        }
+    }
+    (seed, levels)
+}
+
 fn main() {
     env_logger::init().unwrap();
     info!("Starting up...");
-    let mut app = App::new();
+    let (forced_seed, levels) = parse_args();
+    if let Some(seed) = forced_seed {
+        info!("Forcing seed {}", seed);
+    }
+    let mut app = App::new(forced_seed, levels);
     info!("Running application");
     app.run();
 }
+
+#[cfg(test)]
+mod tests {
+    use piston_window::{Context, G2d};
+
+    use super::{GameState, Resources, StateTransition, apply_transition};
+
+    #[derive(Debug)]
+    struct Named(&'static str);
+
+    impl GameState for Named {
+        fn update(&mut self, dt: f64, resources: &mut Resources) -> StateTransition {
+            StateTransition::Continue
+        }
+
+        fn draw(&mut self, c: Context, g: &mut G2d, alpha: f64) {}
+    }
+
+    fn names(states: &[Box<GameState>]) -> Vec<String> {
+        states.iter().map(|s| format!("{:?}", s)).collect()
+    }
+
+    #[test]
+    fn test_batch_transition() {
+        let mut states: Vec<Box<GameState>> = vec![Box::new(Named("menu")),
+                                                   Box::new(Named("game"))];
+        assert!(apply_transition(&mut states, StateTransition::Batch(vec![
+            StateTransition::End,
+            StateTransition::Push(Box::new(Named("options"))),
+            StateTransition::Push(Box::new(Named("dialog"))),
+        ])));
+        assert_eq!(names(&states), vec!["Named(\"menu\")", "Named(\"options\")",
+                                        "Named(\"dialog\")"]);
+
+        // Single transitions work as before
+        assert!(apply_transition(&mut states, StateTransition::Replace(Box::new(Named("game")))));
+        assert_eq!(names(&states).last().unwrap(), "Named(\"game\")");
+        assert_eq!(states.len(), 3);
+
+        // Quitting stops the rest of the batch
+        assert!(!apply_transition(&mut states, StateTransition::Batch(vec![
+            StateTransition::Quit,
+            StateTransition::End,
+        ])));
+        assert_eq!(states.len(), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_batch_continue() {
+        let mut states: Vec<Box<GameState>> = vec![Box::new(Named("menu"))];
+        apply_transition(&mut states, StateTransition::Batch(vec![StateTransition::Continue]));
+    }
+}
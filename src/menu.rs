@@ -0,0 +1,98 @@
+use std::fmt::{self, Debug, Formatter};
+use std::path::PathBuf;
+
+use conrod::{self, Labelable, Positionable, Sizeable, Widget};
+use piston;
+use piston_window::{Context, G2d};
+
+use ::{GameState, Resources, StateTransition};
+use game::Game;
+use gui::Gui;
+use loading::Loading;
+use options::OptionsMenu;
+
+widget_ids!(struct MenuWidgetIds { canvas, play, options, quit });
+
+/// The menu shown when starting the game.
+pub struct MainMenu {
+    gui: Gui,
+    widget_ids: MenuWidgetIds,
+    /// The levels to play, from the command-line.
+    levels: Vec<PathBuf>,
+}
+
+impl Debug for MainMenu {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "MainMenu")
+    }
+}
+
+impl MainMenu {
+    pub fn new(resources: &mut Resources, levels: Vec<PathBuf>) -> MainMenu {
+        let mut gui = Gui::new(resources);
+        let ids = MenuWidgetIds::new(gui.ui.widget_id_generator());
+
+        MainMenu {
+            gui: gui,
+            widget_ids: ids,
+            levels: levels,
+        }
+    }
+}
+
+impl GameState for MainMenu {
+    fn handle_event(&mut self, event: &piston::input::Event,
+                    resources: &mut Resources) -> StateTransition
+    {
+        self.gui.handle_event(event, resources);
+
+        let (play, options, quit) = {
+            let ui = &mut self.gui.ui.set_widgets();
+
+            conrod::widget::Canvas::new().floating(true).w_h(100.0, 125.0).pad(10.0).middle()
+                .set(self.widget_ids.canvas, ui);
+
+            let play = conrod::widget::Button::new()
+                .mid_top_of(self.widget_ids.canvas)
+                .w_h(80.0, 25.0)
+                .label("Play")
+                .set(self.widget_ids.play, ui)
+                .was_clicked();
+            let options = conrod::widget::Button::new()
+                .down(15.0)
+                .w_h(80.0, 25.0)
+                .label("Options")
+                .set(self.widget_ids.options, ui)
+                .was_clicked();
+            let quit = conrod::widget::Button::new()
+                .down(15.0)
+                .w_h(80.0, 25.0)
+                .label("Quit")
+                .set(self.widget_ids.quit, ui)
+                .was_clicked();
+            (play, options, quit)
+        };
+
+        if play {
+            StateTransition::Push(Box::new(Loading::new(Game::loader(self.levels.clone()))))
+        } else if options {
+            StateTransition::Push(Box::new(OptionsMenu::new(resources)))
+        } else if quit {
+            StateTransition::Quit
+        } else {
+            StateTransition::Continue
+        }
+    }
+
+    fn update(&mut self, dt: f64, resources: &mut Resources) -> StateTransition {
+        StateTransition::Continue
+    }
+
+    fn draw(&mut self, c: Context, g: &mut G2d, alpha: f64) {
+        self.gui.draw(c, g);
+    }
+
+    fn resume(&mut self, resources: &mut Resources) {
+        self.gui.fit_window(resources);
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt::{self, Debug, Formatter};
+
+use conrod::{self, Labelable, Positionable, Sizeable, Widget};
+use piston;
+use piston::input::{Button, Key, PressEvent};
+use piston_window::{Context, G2d};
+
+use ::{GameState, Resources, StateTransition};
+use controls::ControlsMenu;
+use gui::Gui;
+
+widget_ids!(struct OptionsWidgetIds { canvas, volume, resolution, threshold, decay,
+                                       controls, back });
+
+/// The window sizes that can be picked.
+const RESOLUTIONS: &'static [(u32, u32)] = &[
+    (800, 600),
+    (1024, 768),
+    (1280, 720),
+    (1600, 900),
+    (1920, 1080),
+];
+
+/// Index of a resolution in `RESOLUTIONS`, if it is one of them.
+fn resolution_index(resolution: (u32, u32)) -> Option<usize> {
+    RESOLUTIONS.iter().position(|&r| r == resolution)
+}
+
+/// Screen changing the settings, reachable from the main and pause menus.
+pub struct OptionsMenu {
+    gui: Gui,
+    widget_ids: OptionsWidgetIds,
+    resolution_labels: Vec<String>,
+}
+
+impl Debug for OptionsMenu {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "OptionsMenu")
+    }
+}
+
+impl OptionsMenu {
+    pub fn new(resources: &mut Resources) -> OptionsMenu {
+        let mut gui = Gui::new(resources);
+        let ids = OptionsWidgetIds::new(gui.ui.widget_id_generator());
+
+        OptionsMenu {
+            gui: gui,
+            widget_ids: ids,
+            resolution_labels: RESOLUTIONS.iter()
+                .map(|&(w, h)| format!("{}x{}", w, h))
+                .collect(),
+        }
+    }
+}
+
+impl GameState for OptionsMenu {
+    fn handle_event(&mut self, event: &piston::input::Event,
+                    resources: &mut Resources) -> StateTransition
+    {
+        if let Some(Button::Keyboard(Key::Escape)) = event.press_args() {
+            return StateTransition::End;
+        }
+
+        self.gui.handle_event(event, resources);
+
+        let ui = &mut self.gui.ui.set_widgets();
+
+        conrod::widget::Canvas::new().floating(true).w_h(220.0, 245.0).pad(10.0).middle()
+            .set(self.widget_ids.canvas, ui);
+
+        let volume = resources.settings.master_volume;
+        if let Some(volume) = conrod::widget::Slider::new(volume, 0.0, 1.0)
+            .mid_top_of(self.widget_ids.canvas)
+            .w_h(200.0, 25.0)
+            .label(&format!("Volume: {}%", (volume * 100.0).round()))
+            .set(self.widget_ids.volume, ui)
+        {
+            resources.settings.master_volume = volume;
+            resources.sound_manager.set_volume(volume);
+        }
+
+        let selected = resolution_index(resources.settings.resolution);
+        if let Some(index) = conrod::widget::DropDownList::new(&self.resolution_labels,
+                                                               selected)
+            .down(15.0)
+            .w_h(200.0, 25.0)
+            .set(self.widget_ids.resolution, ui)
+        {
+            let (width, height) = RESOLUTIONS[index];
+            info!("Changing resolution to {}x{}", width, height);
+            resources.settings.resolution = (width, height);
+            // Piston can't resize, go through the SDL window it wraps
+            if let Err(e) = resources.window.window.window.set_size(width, height) {
+                error!("Can't change resolution: {}", e);
+            }
+        }
+
+        let threshold = resources.settings.stick_threshold;
+        if let Some(threshold) = conrod::widget::Slider::new(threshold, 0.1, 1.0)
+            .down(15.0)
+            .w_h(200.0, 25.0)
+            .label(&format!("Stick threshold: {}%", (threshold * 100.0).round()))
+            .set(self.widget_ids.threshold, ui)
+        {
+            resources.settings.stick_threshold = threshold;
+            resources.input_manager.set_threshold(threshold);
+        }
+
+        let decay = resources.settings.analog_decay;
+        if let Some(decay) = conrod::widget::Slider::new(decay, 0.0, 5.0)
+            .down(15.0)
+            .w_h(200.0, 25.0)
+            .label(&if decay > 0.0 {
+                format!("Stick decay: {:.1}/s", decay)
+            } else {
+                "Stick decay: off".to_owned()
+            })
+            .set(self.widget_ids.decay, ui)
+        {
+            resources.settings.analog_decay = decay;
+            resources.input_manager.set_analog_decay(decay);
+        }
+
+        if conrod::widget::Button::new()
+            .down(15.0)
+            .w_h(200.0, 25.0)
+            .label("Controls")
+            .set(self.widget_ids.controls, ui)
+            .was_clicked()
+        {
+            return StateTransition::Push(Box::new(ControlsMenu::new(resources)));
+        }
+
+        if conrod::widget::Button::new()
+            .down(15.0)
+            .w_h(80.0, 25.0)
+            .label("Back")
+            .set(self.widget_ids.back, ui)
+            .was_clicked()
+        {
+            StateTransition::End
+        } else {
+            StateTransition::Continue
+        }
+    }
+
+    fn update(&mut self, dt: f64, resources: &mut Resources) -> StateTransition {
+        StateTransition::Continue
+    }
+
+    fn draw(&mut self, c: Context, g: &mut G2d, alpha: f64) {
+        self.gui.draw(c, g);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolution_index;
+
+    #[test]
+    fn test_resolution_index() {
+        assert_eq!(resolution_index((800, 600)), Some(0));
+        assert_eq!(resolution_index((1920, 1080)), Some(4));
+        assert_eq!(resolution_index((640, 480)), None);
+    }
+}
@@ -0,0 +1,94 @@
+//! Bucketing of positions in a uniform grid, to find what's near a point.
+
+use std::collections::HashMap;
+
+use vecmath::*;
+
+/// A uniform grid of cells, each holding the indices of the items in it.
+pub struct SpatialGrid {
+    /// Width and height of a cell, in tiles.
+    cell_size: f64,
+    cells: HashMap<(i32, i32), Vec<(usize, Vector2)>>,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f64) -> SpatialGrid {
+        SpatialGrid {
+            cell_size: cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell(&self, pos: Vector2) -> (i32, i32) {
+        ((pos.x() / self.cell_size).floor() as i32,
+         (pos.y() / self.cell_size).floor() as i32)
+    }
+
+    /// Remove all the items.
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    /// Add an item at a position.
+    pub fn insert(&mut self, index: usize, pos: Vector2) {
+        let cell = self.cell(pos);
+        self.cells.entry(cell).or_insert_with(Vec::new).push((index, pos));
+    }
+
+    /// Find the items within a distance of a position, in increasing order.
+    pub fn query_radius(&self, pos: Vector2, radius: f64) -> Vec<usize> {
+        let (x1, y1) = self.cell([pos.x() - radius, pos.y() - radius]);
+        let (x2, y2) = self.cell([pos.x() + radius, pos.y() + radius]);
+        let mut result = Vec::new();
+        for y in y1..y2 + 1 {
+            for x in x1..x2 + 1 {
+                if let Some(items) = self.cells.get(&(x, y)) {
+                    for &(index, item_pos) in items {
+                        if item_pos.distance(pos) <= radius {
+                            result.push(index);
+                        }
+                    }
+                }
+            }
+        }
+        result.sort();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpatialGrid;
+
+    #[test]
+    fn test_empty() {
+        let grid = SpatialGrid::new(4.0);
+        assert!(grid.query_radius([0.0, 0.0], 10.0).is_empty());
+    }
+
+    #[test]
+    fn test_single_cell() {
+        let mut grid = SpatialGrid::new(4.0);
+        grid.insert(0, [1.0, 1.0]);
+        grid.insert(1, [3.0, 1.0]);
+        assert_eq!(grid.query_radius([1.0, 1.5], 1.0), vec![0]);
+        assert_eq!(grid.query_radius([2.0, 1.0], 1.0), vec![0, 1]);
+        assert!(grid.query_radius([2.0, 3.5], 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_query_radius() {
+        let mut grid = SpatialGrid::new(4.0);
+        grid.insert(2, [-1.0, 0.5]);
+        grid.insert(0, [7.5, 0.0]);
+        grid.insert(1, [3.5, 0.0]);
+        grid.insert(3, [0.5, 9.0]);
+        // Spans several cells, including negative ones
+        assert_eq!(grid.query_radius([3.0, 0.0], 4.5), vec![0, 1, 2]);
+        assert_eq!(grid.query_radius([3.0, 0.0], 4.1), vec![1, 2]);
+        assert_eq!(grid.query_radius([0.0, 5.0], 4.7), vec![2, 3]);
+
+        grid.clear();
+        assert!(grid.query_radius([3.0, 0.0], 100.0).is_empty());
+    }
+}
@@ -1,65 +1,692 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
-use std::path::Path;
-use std::rc::{Rc, Weak};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
 
+use graphics::{DrawState, Graphics};
+use graphics::math::Matrix2d;
+use graphics::triangulation::rect_tri_list_xy;
+use image::{self, RgbaImage};
 use piston_window;
-use piston_window::texture::ImageSize;
+use piston_window::texture::{Filter, ImageSize};
+use serde_json;
 
 use ::Window;
+use bundle;
+use utils::WeakCache;
+use vecmath::Vector2;
+
+/// How a texture is sampled when it is scaled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Nearest texel, keeping pixel art crisp when the camera zooms in.
+    Nearest,
+    /// Linear blending, for smooth pictures.
+    Linear,
+}
+
+impl Default for FilterMode {
+    fn default() -> FilterMode {
+        FilterMode::Nearest
+    }
+}
+
+impl FilterMode {
+    pub fn texture_settings(&self) -> piston_window::TextureSettings {
+        let filter = match *self {
+            FilterMode::Nearest => Filter::Nearest,
+            FilterMode::Linear => Filter::Linear,
+        };
+        // The texture is uploaded as sRGB and sampled as linear colors by
+        // the GPU, so the pixels are given as-is; converting them here would
+        // apply the gamma twice, and to the alpha channel too
+        piston_window::TextureSettings::new().filter(filter).convert_gamma(false)
+    }
+
+    /// Name of a sheet in the cache, loading it with another filter gives
+    /// another texture.
+    fn cache_key(&self, name: &str) -> String {
+        match *self {
+            FilterMode::Nearest => name.to_owned(),
+            FilterMode::Linear => format!("{}#linear", name),
+        }
+    }
+}
 
 pub struct SpriteSheet {
     pub texture: piston_window::G2dTexture,
-    width: usize,
-    height: usize,
+    pub width: usize,
+    pub height: usize,
+    /// The filter the texture was created with.
+    pub filter: FilterMode,
+    /// Named parts of the image, from the atlas file next to it.
+    regions: HashMap<String, SpriteRegion>,
+}
+
+/// A named part of a sprite sheet, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct SpriteRegion {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+}
+
+impl SpriteRegion {
+    /// Source rectangle, as used in `Sprite::coords`.
+    pub fn coords(&self) -> [f64; 4] {
+        [self.x, self.y, self.w, self.h]
+    }
+}
+
+/// An atlas file, in the JSON (hash) format of TexturePacker.
+#[derive(Deserialize)]
+struct AtlasJson {
+    frames: HashMap<String, AtlasFrameJson>,
+}
+
+#[derive(Deserialize)]
+struct AtlasFrameJson {
+    frame: SpriteRegion,
+}
+
+/// Error loading a sprite sheet, with the path of the file.
+#[derive(Debug)]
+pub enum SpriteError {
+    /// The image couldn't be read or decoded.
+    Image(PathBuf, image::ImageError),
+    /// The texture couldn't be created from the image.
+    Texture(PathBuf, String),
+    /// The atlas file next to the image couldn't be read.
+    Atlas(PathBuf, serde_json::Error),
+}
+
+impl fmt::Display for SpriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SpriteError::Image(ref path, ref e) => {
+                write!(f, "Can't load image {}: {}", path.display(), e)
+            }
+            SpriteError::Texture(ref path, ref msg) => {
+                write!(f, "Can't create texture for {}: {}", path.display(), msg)
+            }
+            SpriteError::Atlas(ref path, ref e) => {
+                write!(f, "Can't load atlas {}: {}", path.display(), e)
+            }
+        }
+    }
+}
+
+impl Error for SpriteError {
+    fn description(&self) -> &str {
+        match *self {
+            SpriteError::Image(_, _) => "Can't load image",
+            SpriteError::Texture(_, _) => "Can't create texture",
+            SpriteError::Atlas(_, _) => "Can't load atlas",
+        }
+    }
+}
+
+/// Read a sprite sheet's image, from the bundle or the assets directory.
+fn load_image(name: &str) -> Result<RgbaImage, SpriteError> {
+    let path = Path::new("assets").join(name);
+    let image = if let Some(bytes) = bundle::get(name) {
+        image::load_from_memory(bytes)
+    } else {
+        image::open(&path)
+    };
+    match image {
+        Ok(image) => Ok(image.to_rgba()),
+        Err(e) => Err(SpriteError::Image(path, e)),
+    }
+}
+
+/// Read the regions of an atlas file.
+fn parse_atlas<R: Read>(reader: R) -> Result<HashMap<String, SpriteRegion>, serde_json::Error> {
+    let atlas: AtlasJson = try!(serde_json::from_reader(reader));
+    Ok(atlas.frames.into_iter().map(|(name, frame)| (name, frame.frame)).collect())
+}
+
+/// Read the atlas file of a sprite sheet's image, if there is one.
+///
+/// It has the name of the image with a `.json` extension.
+fn load_atlas(name: &str) -> Result<HashMap<String, SpriteRegion>, SpriteError> {
+    let atlas_name = Path::new(name).with_extension("json").to_string_lossy().into_owned();
+    let path = Path::new("assets").join(&atlas_name);
+    let result = if let Some(bytes) = bundle::get(&atlas_name) {
+        parse_atlas(bytes)
+    } else {
+        match File::open(&path) {
+            Ok(file) => parse_atlas(io::BufReader::new(file)),
+            Err(_) => return Ok(HashMap::new()),
+        }
+    };
+    result.map_err(|e| SpriteError::Atlas(path, e))
+}
+
+/// A sprite sheet read from disk, that only needs its texture created.
+struct DecodedSheet {
+    image: RgbaImage,
+    regions: HashMap<String, SpriteRegion>,
+}
+
+impl DecodedSheet {
+    /// Read and decode the files of a sprite sheet, can be done on any thread.
+    fn read(name: &str) -> Result<DecodedSheet, SpriteError> {
+        Ok(DecodedSheet {
+            image: try!(load_image(name)),
+            regions: try!(load_atlas(name)),
+        })
+    }
+}
+
+/// A sheet read by the loading thread: its name, filter, and result.
+type LoadedSheet = (String, FilterMode, Result<DecodedSheet, SpriteError>);
+
+/// Reads sprite sheets on a worker thread, so decoding doesn't block frames.
+struct SheetLoader {
+    requests: mpsc::Sender<(String, FilterMode)>,
+    results: mpsc::Receiver<LoadedSheet>,
+}
+
+impl SheetLoader {
+    fn new() -> SheetLoader {
+        let (requests, requests_rx) = mpsc::channel::<(String, FilterMode)>();
+        let (results_tx, results) = mpsc::channel();
+        thread::Builder::new()
+            .name("sprite-loader".to_owned())
+            .spawn(move || {
+                // Stops once the SheetLoader is dropped
+                for (name, filter) in requests_rx {
+                    let result = DecodedSheet::read(&name);
+                    if results_tx.send((name, filter, result)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Can't start sprite loading thread");
+        SheetLoader {
+            requests: requests,
+            results: results,
+        }
+    }
+
+    fn queue(&self, name: &str, filter: FilterMode) {
+        self.requests.send((name.to_owned(), filter))
+            .expect("Sprite loading thread stopped");
+    }
+
+    /// Get a sheet that is done reading, if any.
+    fn try_recv(&self) -> Option<LoadedSheet> {
+        self.results.try_recv().ok()
+    }
 }
 
 impl SpriteSheet {
-    fn from_file(window: &mut Window, name: &str) -> Result<SpriteSheet, String> {
-        let texture = try!(
-            piston_window::Texture::from_path(
-                &mut window.factory,
-                &Path::new("assets").join(name),
-                piston_window::Flip::None,
-                &piston_window::TextureSettings::new()));
+    fn from_texture(texture: piston_window::G2dTexture, filter: FilterMode,
+                    regions: HashMap<String, SpriteRegion>) -> SpriteSheet {
         let width = texture.get_size().0 as usize;
         let height = texture.get_size().1 as usize;
-        Ok(SpriteSheet {
+        SpriteSheet {
             texture: texture,
             width: width,
             height: height,
-        })
+            filter: filter,
+            regions: regions,
+        }
+    }
+
+    fn load(window: &mut Window, name: &str,
+            filter: FilterMode) -> Result<SpriteSheet, SpriteError> {
+        let decoded = try!(DecodedSheet::read(name));
+        SpriteSheet::from_decoded(window, name, filter, decoded)
+    }
+
+    /// Create the texture, this has to happen on the main thread.
+    fn from_decoded(window: &mut Window, name: &str, filter: FilterMode,
+                    decoded: DecodedSheet) -> Result<SpriteSheet, SpriteError> {
+        let texture = try!(
+            piston_window::Texture::from_image(
+                &mut window.factory,
+                &decoded.image,
+                &filter.texture_settings())
+            .map_err(|e| SpriteError::Texture(Path::new("assets").join(name),
+                                              format!("{:?}", e))));
+        Ok(SpriteSheet::from_texture(texture, filter, decoded.regions))
+    }
+
+    /// Find a named part of the image, from its atlas file.
+    pub fn region(&self, name: &str) -> Option<SpriteRegion> {
+        self.regions.get(name).cloned()
     }
 }
 
+#[derive(Clone)]
 pub struct Sprite {
     pub sheet: Rc<SpriteSheet>,
     pub coords: [f64; 4],
     pub size: [f64; 2],
+    /// Mirror the image horizontally.
+    pub flip_h: bool,
+    /// Mirror the image vertically.
+    pub flip_v: bool,
+}
+
+/// Destination rectangle of an image centered on a position.
+///
+/// The world's Y axis goes up, so the height is negative unless flipped
+/// vertically; a flipped axis gets drawn from the opposite edge.
+pub fn image_rect(center: Vector2, size: Vector2, flip_h: bool, flip_v: bool) -> [f64; 4] {
+    let w = if flip_h { -size[0] } else { size[0] };
+    let h = if flip_v { size[1] } else { -size[1] };
+    [center[0] - w / 2.0, center[1] - h / 2.0, w, h]
+}
+
+impl Sprite {
+    /// Destination rectangle of this sprite centered on a position.
+    pub fn rect(&self, center: Vector2) -> [f64; 4] {
+        image_rect(center, self.size, self.flip_h, self.flip_v)
+    }
+}
+
+/// Most vertices given to the back-end at once, a whole number of quads.
+const BATCH_CHUNK: usize = 6 * 170;
+
+/// Parts of a texture to draw with a single call, instead of an `Image` each.
+///
+/// The triangles are the same that `Image` would draw, in the same order.
+pub struct SpriteBatch {
+    vertices: Vec<[f32; 2]>,
+    uvs: Vec<[f32; 2]>,
+}
+
+impl SpriteBatch {
+    pub fn new() -> SpriteBatch {
+        SpriteBatch {
+            vertices: Vec::new(),
+            uvs: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    /// Add the part `coords` of a texture of the given size, drawn in `rect`.
+    pub fn push(&mut self, transform: Matrix2d, rect: [f64; 4], coords: [f64; 4],
+                size: (u32, u32)) {
+        self.vertices.extend_from_slice(&rect_tri_list_xy(transform, rect));
+        let (w, h) = (size.0 as f32, size.1 as f32);
+        let x1 = coords[0] as f32 / w;
+        let y1 = coords[1] as f32 / h;
+        let x2 = (coords[2] + coords[0]) as f32 / w;
+        let y2 = (coords[3] + coords[1]) as f32 / h;
+        self.uvs.extend_from_slice(&[[x1, y1], [x2, y1], [x1, y2],
+                                     [x2, y1], [x2, y2], [x1, y2]]);
+    }
+
+    /// Draw everything that was added, with the given color, and clear.
+    pub fn draw<G: Graphics>(&mut self, texture: &G::Texture, color: [f32; 4],
+                             draw_state: &DrawState, g: &mut G) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        {
+            let (vertices, uvs) = (&self.vertices, &self.uvs);
+            g.tri_list_uv(draw_state, &color, texture, |f| {
+                for (v, uv) in vertices.chunks(BATCH_CHUNK).zip(uvs.chunks(BATCH_CHUNK)) {
+                    f(v, uv);
+                }
+            });
+        }
+        self.vertices.clear();
+        self.uvs.clear();
+    }
+}
+
+/// A transient effect applied when drawing an entity's sprite.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DrawEffect {
+    /// Draw the sprite tinted with this color.
+    Flash([f32; 4]),
+    /// Draw an outline of this color and thickness (in world units) around
+    /// the sprite.
+    Outline([f32; 4], f64),
+}
+
+pub const NO_TINT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// Multiply two colors, component-wise.
+pub fn modulate(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    [a[0] * b[0], a[1] * b[1], a[2] * b[2], a[3] * b[3]]
+}
+
+impl DrawEffect {
+    /// The additional passes drawn behind the sprite, as (offset, color).
+    ///
+    /// An outline is obtained by drawing the sprite offset in the 4
+    /// directions in a solid color before drawing it normally.
+    pub fn underlays(&self) -> Vec<(Vector2, [f32; 4])> {
+        match *self {
+            DrawEffect::Flash(_) => Vec::new(),
+            DrawEffect::Outline(color, t) => vec![
+                ([-t, 0.0], color),
+                ([t, 0.0], color),
+                ([0.0, -t], color),
+                ([0.0, t], color),
+            ],
+        }
+    }
+
+    /// The color the sprite itself is multiplied with.
+    pub fn tint(&self) -> [f32; 4] {
+        match *self {
+            DrawEffect::Flash(color) => color,
+            DrawEffect::Outline(_, _) => NO_TINT,
+        }
+    }
+}
+
+/// A sprite sheet being loaded in the background.
+///
+/// It is empty until the sheet is ready; drawing code should skip the sprite
+/// meanwhile. It stays empty if the sheet can't be loaded, which `is_done()`
+/// tells apart from still loading.
+#[derive(Clone)]
+pub struct SpriteHandle {
+    sheet: Rc<RefCell<Option<Rc<SpriteSheet>>>>,
+    /// Whether the sheet couldn't be loaded, so it never will be.
+    failed: Rc<Cell<bool>>,
+}
+
+impl SpriteHandle {
+    fn new(sheet: Option<Rc<SpriteSheet>>) -> SpriteHandle {
+        SpriteHandle {
+            sheet: Rc::new(RefCell::new(sheet)),
+            failed: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// The sheet, if it is loaded.
+    pub fn get(&self) -> Option<Rc<SpriteSheet>> {
+        self.sheet.borrow().clone()
+    }
+
+    /// Whether loading is over, whether it worked or not.
+    pub fn is_done(&self) -> bool {
+        self.failed.get() || self.sheet.borrow().is_some()
+    }
 }
 
 pub struct SpriteManager {
-    sprites: RefCell<HashMap<String, Weak<SpriteSheet>>>,
+    sprites: WeakCache<SpriteSheet>,
+    loader: SheetLoader,
+    /// Handles waiting for a sheet from the loader, by cache key.
+    pending: RefCell<HashMap<String, Vec<SpriteHandle>>>,
 }
 
 impl SpriteManager {
     pub fn new() -> SpriteManager {
         SpriteManager {
-            sprites: RefCell::new(HashMap::new()),
+            sprites: WeakCache::new(),
+            loader: SheetLoader::new(),
+            pending: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Load a sprite sheet, reusing it if it is still loaded with the same
+    /// filter.
+    pub fn load(&self, window: &mut Window, name: &str,
+                filter: FilterMode) -> Result<Rc<SpriteSheet>, SpriteError> {
+        self.sprites.get_or_load(&filter.cache_key(name),
+                                 || SpriteSheet::load(window, name, filter))
+    }
+
+    /// Load a sprite sheet in the background.
+    ///
+    /// The files are read on a worker thread, and the texture is created by
+    /// `poll()`. If the sheet is still loaded, the handle is ready right away.
+    pub fn load_async(&self, name: &str, filter: FilterMode) -> SpriteHandle {
+        let key = filter.cache_key(name);
+        if let Some(sheet) = self.sprites.get(&key) {
+            return SpriteHandle::new(Some(sheet));
+        }
+        let handle = SpriteHandle::new(None);
+        let mut pending = self.pending.borrow_mut();
+        let waiting = pending.entry(key).or_insert_with(Vec::new);
+        if waiting.is_empty() {
+            self.loader.queue(name, filter);
         }
+        waiting.push(handle.clone());
+        handle
     }
 
-    pub fn load(&self, window: &mut Window, name: &str) -> Rc<SpriteSheet> {
-        if let Some(sheet) = self.sprites.borrow().get(name) {
-            if let Some(sheet) = sheet.upgrade() {
-                return sheet;
+    /// Create the textures of the sheets read in the background since the
+    /// last call, and fill their handles. Called from the main loop.
+    pub fn poll(&self, window: &mut Window) {
+        while let Some((name, filter, result)) = self.loader.try_recv() {
+            let key = filter.cache_key(&name);
+            let handles = self.pending.borrow_mut().remove(&key).unwrap_or_else(Vec::new);
+            let sheet = self.sprites.get_or_load(&key, || {
+                result.and_then(|decoded| {
+                    SpriteSheet::from_decoded(window, &name, filter, decoded)
+                })
+            });
+            match sheet {
+                Ok(sheet) => {
+                    for handle in handles {
+                        *handle.sheet.borrow_mut() = Some(sheet.clone());
+                    }
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    for handle in handles {
+                        handle.failed.set(true);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use graphics::{DrawState, Graphics, Image, ImageSize, Transformed};
+    use graphics::math::identity;
+
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{DrawEffect, FilterMode, SheetLoader, SpriteBatch, SpriteRegion, image_rect,
+                load_atlas, load_image, parse_atlas};
+
+    /// A texture that is only a size and a name.
+    struct FakeTexture(&'static str, u32, u32);
+
+    impl ImageSize for FakeTexture {
+        fn get_size(&self) -> (u32, u32) {
+            (self.1, self.2)
+        }
+    }
+
+    /// Graphics back-end recording the textured draw calls, with their
+    /// texture, color and vertices.
+    struct Recorder {
+        calls: Vec<(&'static str, [f32; 4], Vec<[f32; 2]>, Vec<[f32; 2]>)>,
+    }
+
+    impl Graphics for Recorder {
+        type Texture = FakeTexture;
+
+        fn clear_color(&mut self, _color: [f32; 4]) {}
+
+        fn clear_stencil(&mut self, _value: u8) {}
+
+        fn tri_list<F>(&mut self, _draw_state: &DrawState, _color: &[f32; 4], _f: F)
+            where F: FnMut(&mut FnMut(&[[f32; 2]]))
+        {
+            panic!("Untextured triangles");
+        }
+
+        fn tri_list_uv<F>(&mut self, _draw_state: &DrawState, color: &[f32; 4],
+                          texture: &FakeTexture, mut f: F)
+            where F: FnMut(&mut FnMut(&[[f32; 2]], &[[f32; 2]]))
+        {
+            let mut vertices = Vec::new();
+            let mut uvs = Vec::new();
+            f(&mut |v, uv| {
+                vertices.extend_from_slice(v);
+                uvs.extend_from_slice(uv);
+            });
+            self.calls.push((texture.0, *color, vertices, uvs));
+        }
+    }
+
+    #[test]
+    fn test_load_image() {
+        let image = load_image("map/liquidLava.png").unwrap();
+        assert_eq!(image.dimensions(), (70, 70));
+        let error = load_image("map/missing.png").unwrap_err().to_string();
+        assert!(error.contains("assets/map/missing.png"), "{}", error);
+    }
+
+    #[test]
+    fn test_sheet_loader() {
+        let loader = SheetLoader::new();
+        loader.queue("map/liquidLava.png", FilterMode::Nearest);
+        loader.queue("map/missing.png", FilterMode::Linear);
+        let mut loaded = Vec::new();
+        for _ in 0..500 {
+            if let Some(sheet) = loader.try_recv() {
+                loaded.push(sheet);
+                if loaded.len() == 2 {
+                    break;
+                }
             } else {
-                self.sprites.borrow_mut().remove(name);
+                thread::sleep(Duration::from_millis(10));
             }
         }
+        assert_eq!(loaded.len(), 2);
+
+        // Results come back in order
+        let (ref name, filter, ref result) = loaded[0];
+        assert_eq!((name.as_str(), filter), ("map/liquidLava.png", FilterMode::Nearest));
+        assert_eq!(result.as_ref().ok().unwrap().image.dimensions(), (70, 70));
+        let (ref name, filter, ref result) = loaded[1];
+        assert_eq!((name.as_str(), filter), ("map/missing.png", FilterMode::Linear));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_atlas() {
+        let atlas = r#"{"frames": {
+            "idle_1": {"frame": {"x": 0, "y": 0, "w": 213, "h": 428}, "rotated": false},
+            "jump": {"frame": {"x": 213, "y": 10, "w": 200, "h": 400}}
+        }, "meta": {"size": {"w": 413, "h": 428}}}"#;
+        let regions = parse_atlas(atlas.as_bytes()).unwrap();
+        assert_eq!(regions.len(), 2);
+        let jump = regions["jump"];
+        assert_eq!(jump, SpriteRegion { x: 213.0, y: 10.0, w: 200.0, h: 400.0 });
+        assert_eq!(jump.coords(), [213.0, 10.0, 200.0, 400.0]);
+        assert!(parse_atlas("{}".as_bytes()).is_err());
+
+        // Sidecar of an image, or nothing
+        let regions = load_atlas("alien/green__0000_idle_1.png").unwrap();
+        assert_eq!(regions["idle_1"].coords(), [0.0, 0.0, 213.0, 428.0]);
+        assert!(load_atlas("map/liquidLava.png").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_image_rect() {
+        assert_eq!(image_rect([1.0, 2.0], [0.5, 2.0], false, false),
+                   [0.75, 3.0, 0.5, -2.0]);
+        assert_eq!(image_rect([1.0, 2.0], [0.5, 2.0], true, false),
+                   [1.25, 3.0, -0.5, -2.0]);
+        assert_eq!(image_rect([1.0, 2.0], [0.5, 2.0], false, true),
+                   [0.75, 1.0, 0.5, 2.0]);
+    }
+
+    #[test]
+    fn test_filter_mode() {
+        assert_eq!(FilterMode::default(), FilterMode::Nearest);
+        // Filter isn't PartialEq
+        assert_eq!(format!("{:?}", FilterMode::Nearest.texture_settings().get_filter()),
+                   "(Nearest, Nearest)");
+        assert_eq!(format!("{:?}", FilterMode::Linear.texture_settings().get_filter()),
+                   "(Linear, Linear)");
+
+        // Pixels are uploaded unchanged, alpha included
+        assert!(!FilterMode::Nearest.texture_settings().get_convert_gamma());
+        assert!(!FilterMode::Linear.texture_settings().get_convert_gamma());
+
+        // The same sheet with different filters is cached separately
+        assert_eq!(FilterMode::Nearest.cache_key("map/tiles.png"), "map/tiles.png");
+        assert_ne!(FilterMode::Linear.cache_key("map/tiles.png"), "map/tiles.png");
+    }
+
+    #[test]
+    fn test_sprite_batch() {
+        let texture = FakeTexture("tiles", 256, 128);
+        let transform = identity().scale(16.0, -16.0);
+        let color = [0.5, 0.5, 0.5, 1.0];
+        let tiles = [([0.0, 1.0, 1.0, -1.0], [0.0, 0.0, 64.0, 64.0]),
+                     ([1.0, 1.0, 1.0, -1.0], [64.0, 0.0, 64.0, 64.0]),
+                     ([1.5, 2.5, -1.0, -1.0], [128.0, 64.0, 64.0, 64.0])];
+
+        // One call per tile
+        let mut expected = Recorder { calls: Vec::new() };
+        for &(rect, coords) in tiles.iter() {
+            Image::new().src_rect(coords).rect(rect).color(color)
+                .draw(&texture, &DrawState::default(), transform, &mut expected);
+        }
+        assert_eq!(expected.calls.len(), 3);
+
+        // A single call, drawing the same triangles
+        let mut batched = Recorder { calls: Vec::new() };
+        let mut batch = SpriteBatch::new();
+        for &(rect, coords) in tiles.iter() {
+            batch.push(transform, rect, coords, texture.get_size());
+        }
+        batch.draw(&texture, color, &DrawState::default(), &mut batched);
+        assert!(batch.is_empty());
+        assert_eq!(batched.calls.len(), 1);
+        let (name, batch_color, ref vertices, ref uvs) = batched.calls[0];
+        assert_eq!((name, batch_color), ("tiles", color));
+        let mut all_vertices = Vec::new();
+        let mut all_uvs = Vec::new();
+        for &(_, _, ref v, ref uv) in expected.calls.iter() {
+            all_vertices.extend_from_slice(v);
+            all_uvs.extend_from_slice(uv);
+        }
+        assert_eq!(*vertices, all_vertices);
+        assert_eq!(*uvs, all_uvs);
+
+        // Nothing to draw, no call
+        batch.draw(&texture, color, &DrawState::default(), &mut batched);
+        assert_eq!(batched.calls.len(), 1);
+    }
+
+    #[test]
+    fn test_outline_passes() {
+        let white = [1.0, 1.0, 1.0, 1.0];
+        let outline = DrawEffect::Outline(white, 0.1);
+        let passes = outline.underlays();
+        assert_eq!(passes.len(), 4);
+        for &(offset, color) in &passes {
+            assert_eq!(offset[0].abs() + offset[1].abs(), 0.1);
+            assert_eq!(color, white);
+        }
+        assert_eq!(outline.tint(), white);
 
-        let sheet = Rc::new(SpriteSheet::from_file(window, name).unwrap());
-        self.sprites.borrow_mut().insert(name.to_owned(), Rc::downgrade(&sheet));
-        sheet
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let flash = DrawEffect::Flash(red);
+        assert!(flash.underlays().is_empty());
+        assert_eq!(flash.tint(), red);
     }
 }
@@ -1,7 +1,15 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
 use std::mem::swap;
+use std::path::PathBuf;
+use std::rc::{Rc, Weak};
 
 /// Iterate on (one element, rest of collection) pairs.
 pub fn one_rest_split_iter<T, F>(mut vec: &mut Vec<T>, mut f: F) where F: FnMut(&mut T, &mut Vec<T>) {
+    if vec.is_empty() {
+        return;
+    }
     let mut kept = vec.remove(0);
     f(&mut kept, &mut vec);
     for i in 0..vec.len() {
@@ -11,13 +19,215 @@ pub fn one_rest_split_iter<T, F>(mut vec: &mut Vec<T>, mut f: F) where F: FnMut(
     vec.push(kept);
 }
 
+/// Iterate on (one element, rest of collection) pairs, only keeping the
+/// elements for which the function returns true.
+///
+/// The order of the kept elements is preserved. Elements added to the rest
+/// of the collection by the function are kept and not iterated on. Returns
+/// the removed elements.
+pub fn one_rest_split_retain<T, F>(vec: &mut Vec<T>, mut f: F) -> Vec<T>
+    where F: FnMut(&mut T, &mut Vec<T>) -> bool
+{
+    let mut removed = Vec::new();
+    let mut i = 0;
+    let mut remaining = vec.len();
+    while remaining > 0 {
+        let mut one = vec.remove(i);
+        if f(&mut one, vec) {
+            vec.insert(i, one);
+            i += 1;
+        } else {
+            removed.push(one);
+        }
+        remaining -= 1;
+    }
+    removed
+}
+
+/// Turns the variable time between frames into a number of fixed-size steps.
+pub struct FixedStep {
+    /// Duration of a step.
+    pub dt: f64,
+    /// Most steps to run in one frame.
+    max_steps: u32,
+    /// Time elapsed that wasn't used by a step yet.
+    accumulator: f64,
+}
+
+impl FixedStep {
+    pub fn new(dt: f64, max_steps: u32) -> FixedStep {
+        FixedStep {
+            dt: dt,
+            max_steps: max_steps,
+            accumulator: 0.0,
+        }
+    }
+
+    /// Add the time elapsed this frame, returns the number of steps to run.
+    ///
+    /// If more than `max_steps` are due, the extra time is dropped, so that
+    /// a slow frame doesn't make the next ones slower.
+    pub fn advance(&mut self, elapsed: f64) -> u32 {
+        self.accumulator += elapsed;
+        let mut steps = 0;
+        while self.accumulator >= self.dt {
+            if steps == self.max_steps {
+                self.accumulator = 0.0;
+                break;
+            }
+            self.accumulator -= self.dt;
+            steps += 1;
+        }
+        steps
+    }
+
+    /// How far along the next step we are, from 0 to 1.
+    pub fn alpha(&self) -> f64 {
+        self.accumulator / self.dt
+    }
+}
+
+/// Limit the time elapsed in a frame, so a hitch doesn't cause a jump.
+///
+/// The time lost that way is simply skipped, with a warning.
+pub fn clamp_dt(dt: f64, max_dt: f64) -> f64 {
+    if dt > max_dt {
+        warn!("Frame took {:.3}s, only simulating {:.3}s", dt, max_dt);
+        max_dt
+    } else {
+        dt.max(0.0)
+    }
+}
+
+/// Shared values by name, kept only while something else holds them.
+pub struct WeakCache<T> {
+    entries: RefCell<HashMap<String, Weak<T>>>,
+}
+
+impl<T> WeakCache<T> {
+    pub fn new() -> WeakCache<T> {
+        WeakCache {
+            entries: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Get a value if it is still alive.
+    pub fn get(&self, name: &str) -> Option<Rc<T>> {
+        self.entries.borrow().get(name).and_then(|value| value.upgrade())
+    }
+
+    /// Get a value if it is still alive, or load it with the function.
+    pub fn get_or_load<E, F>(&self, name: &str, load: F) -> Result<Rc<T>, E>
+        where F: FnOnce() -> Result<T, E>
+    {
+        if let Some(value) = self.get(name) {
+            return Ok(value);
+        }
+
+        let value = Rc::new(try!(load()));
+        self.entries.borrow_mut().insert(name.to_owned(), Rc::downgrade(&value));
+        Ok(value)
+    }
+
+    /// Whether a value is loaded and still alive.
+    pub fn is_loaded(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+}
+
+/// The directory where the game's configuration is stored, for this user.
+///
+/// This is `$XDG_CONFIG_HOME/fluffy-fiesta`, `~/.config/fluffy-fiesta`, or
+/// `%APPDATA%\fluffy-fiesta` on Windows.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from));
+    base.map(|base| base.join("fluffy-fiesta"))
+}
+
+/// Measures the frame rate, averaged over a period of time.
+pub struct FpsCounter {
+    /// Duration over which frames are counted.
+    period: f64,
+    /// Time elapsed in the current period.
+    time: f64,
+    /// Frames counted in the current period.
+    frames: u32,
+    /// Frame rate measured over the last period.
+    pub fps: f64,
+}
+
+impl FpsCounter {
+    pub fn new(period: f64) -> FpsCounter {
+        FpsCounter {
+            period: period,
+            time: 0.0,
+            frames: 0,
+            fps: 0.0,
+        }
+    }
 
+    /// Count a frame that was drawn.
+    pub fn frame(&mut self) {
+        self.frames += 1;
+    }
+
+    /// Add elapsed time, updating the frame rate at the end of each period.
+    pub fn advance(&mut self, elapsed: f64) {
+        self.time += elapsed;
+        if self.time >= self.period {
+            self.fps = self.frames as f64 / self.time;
+            self.time = 0.0;
+            self.frames = 0;
+        }
+    }
+}
+
+/// Something that can only be done again after some time (rate limits).
+pub struct Cooldown {
+    /// Time to wait after each use.
+    pub duration: f64,
+    /// Time left before it is ready.
+    remaining: f64,
+}
+
+impl Cooldown {
+    /// A new cooldown, ready to be used.
+    pub fn new(duration: f64) -> Cooldown {
+        Cooldown {
+            duration: duration,
+            remaining: 0.0,
+        }
+    }
+
+    /// Add elapsed time.
+    pub fn tick(&mut self, dt: f64) {
+        self.remaining = (self.remaining - dt).max(0.0);
+    }
+
+    pub fn ready(&self) -> bool {
+        self.remaining <= 0.0
+    }
+
+    /// Use it if it is ready, returns whether it was.
+    pub fn trigger(&mut self) -> bool {
+        if self.ready() {
+            self.remaining = self.duration;
+            true
+        } else {
+            false
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use std::fmt;
+    use std::rc::Rc;
 
-    use super::one_rest_split_iter;
+    use super::{Cooldown, FixedStep, FpsCounter, WeakCache, clamp_dt,
+                one_rest_split_iter, one_rest_split_retain};
 
     struct NonTrivialThing {
         i: i32,
@@ -49,4 +259,128 @@ mod tests {
             });
         }
     }
+
+    #[test]
+    fn test_empty() {
+        let mut v: Vec<i32> = Vec::new();
+        let mut calls = 0;
+        one_rest_split_iter(&mut v, |_, _| calls += 1);
+        assert_eq!(calls, 0);
+        assert!(v.is_empty());
+    }
+
+    #[test]
+    fn test_single() {
+        let mut v = vec![42];
+        let mut calls = Vec::new();
+        one_rest_split_iter(&mut v, |i, r| calls.push((*i, r.len())));
+        assert_eq!(calls, vec![(42, 0)]);
+        assert_eq!(v, vec![42]);
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut v = vec![1, 2, 3, 4, 5];
+        let mut seen = Vec::new();
+        let removed = one_rest_split_retain(&mut v, |i, r| {
+            seen.push((*i, r.len()));
+            if *i == 2 {
+                // Added elements are kept but not visited
+                r.push(10);
+            }
+            *i % 2 == 0
+        });
+        assert_eq!(seen, vec![(1, 4), (2, 3), (3, 4), (4, 3), (5, 3)]);
+        assert_eq!(removed, vec![1, 3, 5]);
+        assert_eq!(v, vec![2, 4, 10]);
+
+        let mut v: Vec<i32> = Vec::new();
+        assert!(one_rest_split_retain(&mut v, |_, _| false).is_empty());
+    }
+
+    #[test]
+    fn test_fixed_step() {
+        let mut step = FixedStep::new(1.0 / 120.0, 8);
+        assert_eq!(step.advance(0.05), 6);
+        // Leftover time carries to the next frames
+        assert_eq!(step.advance(0.005), 0);
+        assert_eq!(step.advance(0.005), 1);
+        // Lag spike: capped, and the rest is dropped
+        assert_eq!(step.advance(1.0), 8);
+        assert_eq!(step.advance(0.0), 0);
+        assert_eq!(step.alpha(), 0.0);
+
+        let mut step = FixedStep::new(0.25, 8);
+        assert_eq!(step.advance(0.625), 2);
+        assert_eq!(step.alpha(), 0.5);
+    }
+
+    #[test]
+    fn test_clamp_dt() {
+        assert_eq!(clamp_dt(0.016, 0.1), 0.016);
+        assert_eq!(clamp_dt(0.1, 0.1), 0.1);
+        assert_eq!(clamp_dt(5.0, 0.1), 0.1);
+        assert_eq!(clamp_dt(-1.0, 0.1), 0.0);
+    }
+
+    #[test]
+    fn test_weak_cache() {
+        let cache = WeakCache::new();
+        let mut loads = 0;
+        let a = cache.get_or_load("a", || -> Result<_, ()> { loads += 1; Ok(1) }).unwrap();
+        let again = cache.get_or_load("a", || -> Result<_, ()> { loads += 1; Ok(2) }).unwrap();
+        assert!(Rc::ptr_eq(&a, &again));
+        assert_eq!(*again, 1);
+        assert_eq!(loads, 1);
+        assert!(cache.is_loaded("a"));
+        assert!(Rc::ptr_eq(&cache.get("a").unwrap(), &a));
+
+        // Loaded again once dropped
+        drop(a);
+        drop(again);
+        assert!(!cache.is_loaded("a"));
+        let a = cache.get_or_load("a", || -> Result<_, ()> { loads += 1; Ok(3) }).unwrap();
+        assert_eq!(*a, 3);
+        assert_eq!(loads, 2);
+
+        // Errors are not cached
+        assert_eq!(cache.get_or_load("b", || Err("nope")), Err("nope"));
+        assert!(!cache.is_loaded("b"));
+    }
+
+    #[test]
+    fn test_fps_counter() {
+        let mut counter = FpsCounter::new(0.5);
+        for _ in 0..7 {
+            counter.frame();
+            counter.advance(0.0625);
+        }
+        // Not updated until the period is over
+        assert_eq!(counter.fps, 0.0);
+        counter.frame();
+        counter.advance(0.0625);
+        assert_eq!(counter.fps, 16.0);
+        // Slower frames
+        for _ in 0..4 {
+            counter.frame();
+            counter.advance(0.125);
+        }
+        assert_eq!(counter.fps, 8.0);
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let mut cooldown = Cooldown::new(0.5);
+        assert!(cooldown.ready());
+        assert!(cooldown.trigger());
+        assert!(!cooldown.trigger());
+        cooldown.tick(0.25);
+        assert!(!cooldown.ready());
+        cooldown.tick(0.25);
+        assert!(cooldown.trigger());
+        // Waiting longer doesn't bank uses
+        cooldown.tick(2.0);
+        assert!(cooldown.trigger());
+        assert!(!cooldown.trigger());
+    }
 }
@@ -1,12 +1,54 @@
 use vecmath_lib;
 
-pub use vecmath_lib::{vec2_add, vec2_sub, vec2_mul, vec2_scale};
+pub use vecmath_lib::{vec2_add, vec2_sub, vec2_mul, vec2_scale, vec2_len};
 
 pub type Vector2 = vecmath_lib::Vector2<f64>;
 
 pub trait Vector2Ext {
     fn x(&self) -> f64;
     fn y(&self) -> f64;
+    fn length(&self) -> f64;
+    /// The vector scaled to a length of 1, or `[0, 0]` for the zero vector.
+    fn normalized(&self) -> Vector2;
+    fn dot(&self, other: Vector2) -> f64;
+    fn distance(&self, other: Vector2) -> f64;
+    /// The point between this one (alpha 0) and the other one (alpha 1).
+    fn lerp(&self, other: Vector2, alpha: f64) -> Vector2;
+}
+
+/// An axis-aligned box, from its bottom-left and top-right corners.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector2,
+    pub max: Vector2,
+}
+
+impl Aabb {
+    pub fn new(min: Vector2, max: Vector2) -> Aabb {
+        Aabb {
+            min: min,
+            max: max,
+        }
+    }
+
+    pub fn from_center_size(center: Vector2, size: Vector2) -> Aabb {
+        Aabb {
+            min: [center.x() - size.x() / 2.0, center.y() - size.y() / 2.0],
+            max: [center.x() + size.x() / 2.0, center.y() + size.y() / 2.0],
+        }
+    }
+
+    /// Whether the boxes overlap. Boxes only touching by an edge don't.
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x() < other.max.x() && other.min.x() < self.max.x() &&
+            self.min.y() < other.max.y() && other.min.y() < self.max.y()
+    }
+
+    /// Whether a point is in the box, edges included.
+    pub fn contains(&self, point: Vector2) -> bool {
+        point.x() >= self.min.x() && point.x() <= self.max.x() &&
+            point.y() >= self.min.y() && point.y() <= self.max.y()
+    }
 }
 
 impl Vector2Ext for Vector2 {
@@ -17,4 +59,102 @@ impl Vector2Ext for Vector2 {
     fn y(&self) -> f64 {
         self[1]
     }
+
+    fn length(&self) -> f64 {
+        vec2_len(*self)
+    }
+
+    fn normalized(&self) -> Vector2 {
+        let len = self.length();
+        if len == 0.0 {
+            [0.0, 0.0]
+        } else {
+            vec2_scale(*self, 1.0 / len)
+        }
+    }
+
+    fn dot(&self, other: Vector2) -> f64 {
+        self.x() * other.x() + self.y() * other.y()
+    }
+
+    fn distance(&self, other: Vector2) -> f64 {
+        vec2_sub(*self, other).length()
+    }
+
+    fn lerp(&self, other: Vector2, alpha: f64) -> Vector2 {
+        vec2_add(*self, vec2_scale(vec2_sub(other, *self), alpha))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Aabb, Vector2, Vector2Ext};
+
+    const ZERO: Vector2 = [0.0, 0.0];
+
+    #[test]
+    fn test_length() {
+        let v: Vector2 = [3.0, -4.0];
+        assert_eq!(v.length(), 5.0);
+        assert_eq!(ZERO.length(), 0.0);
+    }
+
+    #[test]
+    fn test_normalized() {
+        let v: Vector2 = [3.0, -4.0];
+        let n = v.normalized();
+        assert!((n.x() - 0.6).abs() < 1e-12);
+        assert!((n.y() + 0.8).abs() < 1e-12);
+        let v: Vector2 = [0.0, 2.0];
+        assert_eq!(v.normalized(), [0.0, 1.0]);
+        assert_eq!(ZERO.normalized(), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_dot() {
+        let v: Vector2 = [1.0, 2.0];
+        assert_eq!(v.dot([3.0, -4.0]), -5.0);
+        assert_eq!(v.dot([-2.0, 1.0]), 0.0);
+        assert_eq!(ZERO.dot([3.0, 4.0]), 0.0);
+    }
+
+    #[test]
+    fn test_distance() {
+        let a: Vector2 = [1.0, 1.0];
+        let b: Vector2 = [4.0, 5.0];
+        assert_eq!(a.distance(b), 5.0);
+        assert_eq!(b.distance(a), 5.0);
+        assert_eq!(ZERO.distance(ZERO), 0.0);
+    }
+
+    #[test]
+    fn test_lerp() {
+        let a: Vector2 = [1.0, -2.0];
+        let b: Vector2 = [3.0, 2.0];
+        assert_eq!(a.lerp(b, 0.0), a);
+        assert_eq!(a.lerp(b, 0.5), [2.0, 0.0]);
+        assert_eq!(a.lerp(b, 1.0), b);
+    }
+
+    #[test]
+    fn test_aabb() {
+        let a = Aabb::from_center_size([1.0, 1.0], [2.0, 4.0]);
+        assert_eq!(a, Aabb::new([0.0, -1.0], [2.0, 3.0]));
+
+        // Overlapping, either way
+        let b = Aabb::new([1.5, 2.5], [4.0, 4.0]);
+        assert!(a.intersects(&b) && b.intersects(&a));
+        assert!(a.intersects(&Aabb::new([0.5, 0.0], [1.0, 1.0])));
+        // Touching by an edge or a corner
+        assert!(!a.intersects(&Aabb::new([2.0, 0.0], [3.0, 1.0])));
+        assert!(!a.intersects(&Aabb::new([2.0, 3.0], [3.0, 4.0])));
+        // Disjoint
+        assert!(!a.intersects(&Aabb::new([3.0, 0.0], [4.0, 1.0])));
+        assert!(!a.intersects(&Aabb::new([0.0, 3.5], [2.0, 4.0])));
+
+        assert!(a.contains([1.0, 1.0]));
+        assert!(a.contains([2.0, -1.0]));
+        assert!(!a.contains([2.1, 1.0]));
+        assert!(!a.contains([1.0, -1.5]));
+    }
 }
@@ -0,0 +1,172 @@
+//! Building maps in code, for tests and procedural generation.
+
+use vecmath::Vector2;
+use super::{CameraConfig, EntityDefinition, MapFactory, MapLoadError, Physics, RenderOrder, Tile,
+            TileLayer, TileTypeDefinition};
+
+/// Index of a tile type, as returned by `MapFactoryBuilder::tile_type()`.
+pub type TileTypeId = Tile;
+
+/// Builds a `MapFactory` step by step.
+///
+/// Tiles start out as the first tile type. Everything is checked by
+/// `build()`, so placing tiles can be chained.
+pub struct MapFactoryBuilder {
+    width: usize,
+    height: usize,
+    nb_players: usize,
+    physics: Physics,
+    tiletypes: Vec<TileTypeDefinition>,
+    tiles: Vec<Tile>,
+    entities: Vec<EntityDefinition>,
+    /// First tile that was set outside the map, reported by `build()`.
+    outside: Option<(usize, usize)>,
+}
+
+impl MapFactoryBuilder {
+    pub fn new() -> MapFactoryBuilder {
+        MapFactoryBuilder {
+            width: 0,
+            height: 0,
+            nb_players: 1,
+            physics: Physics::default(),
+            tiletypes: Vec::new(),
+            tiles: Vec::new(),
+            entities: Vec::new(),
+            outside: None,
+        }
+    }
+
+    /// Set the size of the map, in tiles. This clears the tiles.
+    pub fn dimensions(&mut self, width: usize, height: usize) -> &mut MapFactoryBuilder {
+        self.width = width;
+        self.height = height;
+        self.tiles = vec![0; width * height];
+        self
+    }
+
+    pub fn nb_players(&mut self, nb_players: usize) -> &mut MapFactoryBuilder {
+        self.nb_players = nb_players;
+        self
+    }
+
+    /// Set the physical constants of the map.
+    pub fn physics(&mut self, physics: Physics) -> &mut MapFactoryBuilder {
+        self.physics = physics;
+        self
+    }
+
+    /// Add a tile type, returning the index to place it with.
+    pub fn tile_type(&mut self, definition: TileTypeDefinition) -> TileTypeId {
+        self.tiletypes.push(definition);
+        (self.tiletypes.len() - 1) as TileTypeId
+    }
+
+    /// Set all the tiles of the map.
+    pub fn fill(&mut self, id: TileTypeId) -> &mut MapFactoryBuilder {
+        for tile in self.tiles.iter_mut() {
+            *tile = id;
+        }
+        self
+    }
+
+    /// Set a tile, `(0, 0)` being the bottom-left corner.
+    ///
+    /// A tile outside the map makes `build()` fail.
+    pub fn set_tile(&mut self, x: usize, y: usize, id: TileTypeId) -> &mut MapFactoryBuilder {
+        if x < self.width && y < self.height {
+            self.tiles[y * self.width + x] = id;
+        } else if self.outside.is_none() {
+            self.outside = Some((x, y));
+        }
+        self
+    }
+
+    /// Set a rectangle of tiles, from its bottom-left corner.
+    ///
+    /// The part of the rectangle outside the map is ignored.
+    pub fn rect(&mut self, x: usize, y: usize, w: usize, h: usize,
+                id: TileTypeId) -> &mut MapFactoryBuilder {
+        for ty in y..(y + h).min(self.height) {
+            for tx in x..(x + w).min(self.width) {
+                self.tiles[ty * self.width + tx] = id;
+            }
+        }
+        self
+    }
+
+    /// Add an entity, created by the `EntityRegistry` when the map starts.
+    pub fn entity(&mut self, type_id: &str, pos: Vector2) -> &mut MapFactoryBuilder {
+        self.entities.push(EntityDefinition {
+            type_id: type_id.to_owned(),
+            position: pos,
+        });
+        self
+    }
+
+    /// Make the map, checking that the tiles are valid.
+    pub fn build(self) -> Result<MapFactory, MapLoadError> {
+        if let Some((x, y)) = self.outside {
+            return Err(MapLoadError::Invalid(format!(
+                "Tile ({}, {}) is outside the {}x{} map", x, y, self.width, self.height)));
+        }
+        let map = MapFactory {
+            width: self.width,
+            height: self.height,
+            nb_players: self.nb_players,
+            physics: self.physics,
+            camera: CameraConfig::default(),
+            tile_size: 1.0,
+            tiletypes: self.tiletypes,
+            layers: vec![TileLayer::new(RenderOrder::Main, self.tiles)],
+            entities: self.entities,
+            backgrounds: Vec::new(),
+            music: None,
+        };
+        try!(map.validate());
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use world::{MapLoadError, TileTypeDefinition};
+    use super::MapFactoryBuilder;
+
+    fn small_map() -> MapFactoryBuilder {
+        let mut builder = MapFactoryBuilder::new();
+        builder.dimensions(4, 3);
+        builder.tile_type(TileTypeDefinition::default());
+        builder.tile_type(TileTypeDefinition { collide: true, ..TileTypeDefinition::default() });
+        builder
+    }
+
+    #[test]
+    fn test_rect() {
+        let mut builder = small_map();
+        // Clipped to the map
+        builder.rect(2, 1, 5, 5, 1).rect(0, 0, 1, 1, 1);
+        let map = builder.build().unwrap();
+        assert_eq!(map.main_layer().tiles, vec![1, 0, 0, 0,
+                                                0, 0, 1, 1,
+                                                0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut builder = small_map();
+        builder.set_tile(3, 2, 1).set_tile(4, 0, 1);
+        match builder.build() {
+            Err(MapLoadError::Invalid(e)) => assert!(e.contains("(4, 0)"), "{}", e),
+            r => panic!("Unexpected result {:?}", r.map(|_| ())),
+        }
+
+        // Tile type that wasn't added
+        let mut builder = small_map();
+        builder.set_tile(1, 1, 2);
+        match builder.build() {
+            Err(MapLoadError::Invalid(_)) => {}
+            r => panic!("Unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Procedural generation of platformer levels.
+
+use rand::Rng;
+
+use super::{MapFactory, MapFactoryBuilder, TileTypeDefinition, seeded_rng};
+
+/// Vertical distance between rows of platforms, less than a double jump.
+const PLATFORM_SPACING: usize = 2;
+/// Smoothed noise above which a tile is part of a platform.
+const PLATFORM_THRESHOLD: f64 = 0.55;
+/// Smoothed noise above which a tile of the floor is lava.
+const LAVA_THRESHOLD: f64 = 0.7;
+/// Columns on each side of a spawn point kept clear of platforms and lava.
+const SPAWN_CLEARANCE: usize = 1;
+
+/// Random values in `[0, 1)`, averaged with their neighbors so they form runs
+/// once thresholded.
+fn smooth_noise<R: Rng>(rng: &mut R, len: usize) -> Vec<f64> {
+    let noise: Vec<f64> = (0..len).map(|_| rng.gen::<f64>()).collect();
+    (0..len).map(|i| {
+        let from = if i == 0 { 0 } else { i - 1 };
+        let to = (i + 2).min(len);
+        noise[from..to].iter().sum::<f64>() / (to - from) as f64
+    }).collect()
+}
+
+impl MapFactory {
+    /// Generate a level: walls all around, a floor with pools of lava, and
+    /// one-way platforms to jump on, with spawn points spread on the floor.
+    ///
+    /// The same seed gives the same level. The map is at least 3x5 tiles.
+    pub fn generate(width: usize, height: usize, nb_players: usize, seed: u32) -> MapFactory {
+        let (width, height) = (width.max(3), height.max(5));
+        let mut rng = seeded_rng(seed);
+
+        let mut builder = MapFactoryBuilder::new();
+        builder.dimensions(width, height).nb_players(nb_players);
+        let empty = builder.tile_type(TileTypeDefinition {
+            sprite_sheet: Some("map/bg_castle.png".to_owned()),
+            sprite_coords: [0., 0., 256., 256.],
+            ..TileTypeDefinition::default()
+        });
+        let wall = builder.tile_type(TileTypeDefinition {
+            sprite_sheet: Some("map/castleCenter.png".to_owned()),
+            sprite_coords: [0., 0., 256., 256.],
+            collide: true,
+            ..TileTypeDefinition::default()
+        });
+        let platform = builder.tile_type(TileTypeDefinition {
+            sprite_sheet: Some("map/castleCenter.png".to_owned()),
+            sprite_coords: [0., 0., 256., 256.],
+            collide: true,
+            one_way: true,
+            ..TileTypeDefinition::default()
+        });
+        let lava = builder.tile_type(TileTypeDefinition {
+            sprite_sheet: Some("map/liquidLava.png".to_owned()),
+            sprite_coords: [0., 0., 256., 256.],
+            damage: 1.0,
+            damage_falloff: 0.5,
+            ..TileTypeDefinition::default()
+        });
+
+        // Spawn points spread evenly on the floor, with room around them
+        let spawns: Vec<usize> = (0..nb_players).map(|i| {
+            1 + ((i as f64 + 0.5) * (width - 2) as f64 / nb_players as f64) as usize
+        }).collect();
+        let near_spawn = |x: usize| {
+            spawns.iter().any(|&s| x + SPAWN_CLEARANCE >= s && x <= s + SPAWN_CLEARANCE)
+        };
+
+        // Walls all around
+        builder.fill(empty)
+            .rect(0, 0, width, 1, wall)
+            .rect(0, height - 1, width, 1, wall)
+            .rect(0, 0, 1, height, wall)
+            .rect(width - 1, 0, 1, height, wall);
+
+        // Lava in the floor, never under a spawn point
+        let noise = smooth_noise(&mut rng, width);
+        for x in 1..width - 1 {
+            if noise[x] > LAVA_THRESHOLD && !near_spawn(x) {
+                builder.set_tile(x, 0, lava);
+            }
+        }
+
+        // Rows of platforms, leaving room to stand on the top ones
+        let mut y = PLATFORM_SPACING;
+        while y + 3 < height {
+            let noise = smooth_noise(&mut rng, width);
+            for x in 1..width - 1 {
+                if noise[x] > PLATFORM_THRESHOLD && !(y == PLATFORM_SPACING && near_spawn(x)) {
+                    builder.set_tile(x, y, platform);
+                }
+            }
+            y += PLATFORM_SPACING;
+        }
+
+        for &x in spawns.iter() {
+            builder.entity("f.spawn", [x as f64 + 0.5, 1.0]);
+        }
+        builder.build().expect("Generated map is invalid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use world::MapFactory;
+
+    #[test]
+    fn test_generate() {
+        for seed in 0..20 {
+            for &(width, height, nb_players) in [(40, 20, 4), (12, 8, 1), (3, 3, 2)].iter() {
+                let map = MapFactory::generate(width, height, nb_players, seed);
+                map.validate().unwrap();
+                let (width, height) = (map.width, map.height);
+                let tiles = &map.main_layer().tiles;
+                let tile = |x: usize, y: usize| &map.tiletypes[tiles[y * width + x] as usize];
+
+                // Intact borders, lava only replacing the floor
+                for x in 0..width {
+                    assert!(tile(x, height - 1).collide);
+                    assert!(tile(x, 0).collide || tile(x, 0).damage > 0.0);
+                }
+                for y in 0..height {
+                    assert!(tile(0, y).collide && tile(width - 1, y).collide);
+                }
+
+                // Spawns standing on solid ground, with room for a character
+                let spawns: Vec<_> = map.entities.iter()
+                    .filter(|e| e.type_id == "f.spawn")
+                    .collect();
+                assert_eq!(spawns.len(), nb_players);
+                for spawn in spawns {
+                    let (x, y) = (spawn.position[0] as usize, spawn.position[1] as usize);
+                    assert!(tile(x, y - 1).collide && tile(x, y - 1).damage == 0.0,
+                            "seed {}: spawn at {:?}", seed, spawn.position);
+                    assert!(!tile(x, y).collide && !tile(x, y + 1).collide,
+                            "seed {}: spawn at {:?}", seed, spawn.position);
+                }
+            }
+        }
+    }
+}
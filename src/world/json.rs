@@ -0,0 +1,293 @@
+//! Reading and writing maps in JSON format.
+//!
+//! This is a readable text format for maps, mirroring `MapFactory`. Fields
+//! that were added to tile types later can be omitted, and get their default
+//! value. Entity positions are in world units, `tile_size` being the size of
+//! a tile in those units (1 if omitted).
+
+use std::io::{Read, Write};
+
+use serde_json;
+
+use vecmath::Vector2;
+use super::{BackgroundDefinition, CameraConfig, EntityDefinition, MapFactory, Physics,
+            RenderOrder, Tile, TileLayer, TileShape, TileTypeDefinition, Trigger};
+
+#[derive(Serialize, Deserialize)]
+struct TileTypeJson {
+    sprite_sheet: Option<String>,
+    sprite_coords: [f64; 4],
+    damage: f64,
+    #[serde(default)]
+    damage_falloff: f64,
+    collide: bool,
+    #[serde(default)]
+    surface_velocity: Vector2,
+    #[serde(default)]
+    render_offset: Vector2,
+    #[serde(default)]
+    shape: TileShape,
+    #[serde(default)]
+    one_way: bool,
+    #[serde(default)]
+    climbable: bool,
+    #[serde(default)]
+    notify_enter: bool,
+    #[serde(default)]
+    trigger: Option<Trigger>,
+    #[serde(default)]
+    open_tile: Option<Tile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EntityJson {
+    type_id: String,
+    position: Vector2,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackgroundJson {
+    sprite_sheet: String,
+    parallax: f64,
+    #[serde(default)]
+    scroll: Vector2,
+}
+
+/// A decorative layer, drawn in front or behind the main one.
+#[derive(Serialize, Deserialize)]
+struct LayerJson {
+    render_order: RenderOrder,
+    tiles: Vec<Tile>,
+}
+
+fn default_tile_size() -> f64 {
+    1.0
+}
+
+#[derive(Serialize, Deserialize)]
+struct MapJson {
+    width: usize,
+    height: usize,
+    nb_players: usize,
+    #[serde(default)]
+    physics: Physics,
+    #[serde(default)]
+    camera: CameraConfig,
+    #[serde(default = "default_tile_size")]
+    tile_size: f64,
+    tiletypes: Vec<TileTypeJson>,
+    /// The main layer.
+    tiles: Vec<Tile>,
+    #[serde(default)]
+    layers: Vec<LayerJson>,
+    entities: Vec<EntityJson>,
+    #[serde(default)]
+    backgrounds: Vec<BackgroundJson>,
+    #[serde(default)]
+    music: Option<String>,
+}
+
+pub fn read<R: Read>(reader: R) -> Result<MapFactory, serde_json::Error> {
+    let map: MapJson = try!(serde_json::from_reader(reader));
+    let mut layers = vec![TileLayer::new(RenderOrder::Main, map.tiles)];
+    layers.extend(map.layers.into_iter().map(|l| TileLayer::new(l.render_order, l.tiles)));
+    Ok(MapFactory {
+        width: map.width,
+        height: map.height,
+        nb_players: map.nb_players,
+        physics: map.physics,
+        camera: map.camera,
+        tile_size: map.tile_size,
+        tiletypes: map.tiletypes.into_iter().map(|t| {
+            TileTypeDefinition {
+                sprite_sheet: t.sprite_sheet,
+                sprite_coords: t.sprite_coords,
+                damage: t.damage,
+                damage_falloff: t.damage_falloff,
+                collide: t.collide,
+                surface_velocity: t.surface_velocity,
+                render_offset: t.render_offset,
+                shape: t.shape,
+                one_way: t.one_way,
+                climbable: t.climbable,
+                notify_enter: t.notify_enter,
+                trigger: t.trigger,
+                open_tile: t.open_tile,
+                tile_entity: None,
+            }
+        }).collect(),
+        layers: layers,
+        entities: map.entities.into_iter().map(|e| {
+            EntityDefinition {
+                type_id: e.type_id,
+                position: e.position,
+            }
+        }).collect(),
+        backgrounds: map.backgrounds.into_iter().map(|b| {
+            BackgroundDefinition {
+                sprite_sheet: b.sprite_sheet,
+                parallax: b.parallax,
+                scroll: b.scroll,
+            }
+        }).collect(),
+        music: map.music,
+    })
+}
+
+pub fn write<W: Write>(map: &MapFactory, writer: W) -> Result<(), serde_json::Error> {
+    let map = MapJson {
+        width: map.width,
+        height: map.height,
+        nb_players: map.nb_players,
+        physics: map.physics,
+        camera: map.camera,
+        tile_size: map.tile_size,
+        tiletypes: map.tiletypes.iter().map(|t| {
+            TileTypeJson {
+                sprite_sheet: t.sprite_sheet.clone(),
+                sprite_coords: t.sprite_coords,
+                damage: t.damage,
+                damage_falloff: t.damage_falloff,
+                collide: t.collide,
+                surface_velocity: t.surface_velocity,
+                render_offset: t.render_offset,
+                shape: t.shape,
+                one_way: t.one_way,
+                climbable: t.climbable,
+                notify_enter: t.notify_enter,
+                trigger: t.trigger.clone(),
+                open_tile: t.open_tile,
+            }
+        }).collect(),
+        tiles: map.main_layer().tiles.clone(),
+        layers: map.layers.iter().filter(|l| l.render_order != RenderOrder::Main).map(|l| {
+            LayerJson {
+                render_order: l.render_order,
+                tiles: l.tiles.clone(),
+            }
+        }).collect(),
+        entities: map.entities.iter().map(|e| {
+            EntityJson {
+                type_id: e.type_id.clone(),
+                position: e.position,
+            }
+        }).collect(),
+        backgrounds: map.backgrounds.iter().map(|b| {
+            BackgroundJson {
+                sprite_sheet: b.sprite_sheet.clone(),
+                parallax: b.parallax,
+                scroll: b.scroll,
+            }
+        }).collect(),
+        music: map.music.clone(),
+    };
+    serde_json::to_writer(writer, &map)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use world::{BackgroundDefinition, MapFactory, MapLoadError};
+
+    #[test]
+    fn test_roundtrip() {
+        let mut example = MapFactory::example();
+        example.backgrounds.push(BackgroundDefinition {
+            sprite_sheet: "map/bg.png".to_owned(),
+            parallax: 0.5,
+            scroll: [1.0, 0.0],
+        });
+        example.tile_size = 0.5;
+        example.music = Some("level1".to_owned());
+        let mut json = Vec::new();
+        example.to_json(&mut json).unwrap();
+        let map = MapFactory::from_json(&json[..]).unwrap();
+
+        assert_eq!((map.width, map.height, map.nb_players),
+                   (example.width, example.height, example.nb_players));
+        assert_eq!(map.physics, example.physics);
+        assert_eq!(map.camera, example.camera);
+        assert_eq!(map.tile_size, 0.5);
+        assert_eq!(map.music, Some("level1".to_owned()));
+        assert_eq!(map.layers, example.layers);
+        assert_eq!(map.tiletypes.len(), example.tiletypes.len());
+        for (a, b) in map.tiletypes.iter().zip(example.tiletypes.iter()) {
+            assert_eq!(a.sprite_sheet, b.sprite_sheet);
+            assert_eq!(a.sprite_coords, b.sprite_coords);
+            assert_eq!(a.damage, b.damage);
+            assert_eq!(a.damage_falloff, b.damage_falloff);
+            assert_eq!(a.collide, b.collide);
+            assert_eq!(a.surface_velocity, b.surface_velocity);
+            assert_eq!(a.render_offset, b.render_offset);
+            assert_eq!(a.shape, b.shape);
+            assert_eq!(a.one_way, b.one_way);
+            assert_eq!(a.climbable, b.climbable);
+            assert_eq!(a.notify_enter, b.notify_enter);
+            assert_eq!(a.trigger, b.trigger);
+            assert_eq!(a.open_tile, b.open_tile);
+        }
+        assert_eq!(map.entities.len(), example.entities.len());
+        for (a, b) in map.entities.iter().zip(example.entities.iter()) {
+            assert_eq!(a.type_id, b.type_id);
+            assert_eq!(a.position, b.position);
+        }
+        assert_eq!(map.backgrounds.len(), example.backgrounds.len());
+        for (a, b) in map.backgrounds.iter().zip(example.backgrounds.iter()) {
+            assert_eq!(a.sprite_sheet, b.sprite_sheet);
+            assert_eq!(a.parallax, b.parallax);
+            assert_eq!(a.scroll, b.scroll);
+        }
+    }
+
+    #[test]
+    fn test_from_file() {
+        let map = MapFactory::from_file(Path::new("assets/maps/test.json")).unwrap();
+        assert_eq!((map.width, map.height, map.nb_players), (4, 3, 1));
+        assert_eq!(map.tile_size, 1.0);
+        assert_eq!(map.tiletypes.len(), 2);
+        assert!(map.tiletypes[1].collide);
+        assert_eq!(map.layers[0].tiles[..4], [1, 1, 1, 1]);
+        assert_eq!(map.entities.len(), 1);
+        assert_eq!(map.entities[0].type_id, "f.spawn");
+        assert_eq!(map.entities[0].position, [1.5, 1.0]);
+    }
+
+    #[test]
+    fn test_validation() {
+        let wrong_size = r#"{"width": 2, "height": 2, "nb_players": 1,
+            "tiletypes": [{"sprite_sheet": null, "sprite_coords": [0, 0, 1, 1],
+                           "damage": 0, "collide": false}],
+            "tiles": [0, 0, 0], "entities": []}"#;
+        match MapFactory::from_json(wrong_size.as_bytes()) {
+            Err(MapLoadError::Invalid(_)) => {}
+            r => panic!("Unexpected result {:?}", r.map(|_| ())),
+        }
+
+        let wrong_tile = r#"{"width": 2, "height": 1, "nb_players": 1,
+            "tiletypes": [{"sprite_sheet": null, "sprite_coords": [0, 0, 1, 1],
+                           "damage": 0, "collide": false}],
+            "tiles": [0, 1], "entities": []}"#;
+        match MapFactory::from_json(wrong_tile.as_bytes()) {
+            Err(MapLoadError::Invalid(msg)) => assert!(msg.contains("(1, 0)")),
+            r => panic!("Unexpected result {:?}", r.map(|_| ())),
+        }
+
+        // Tiles are 1 unit wide unless set, and can't be empty
+        let tile_size = |field: &str| format!(r#"{{"width": 1, "height": 1, "nb_players": 1,
+            "tiletypes": [{{"sprite_sheet": null, "sprite_coords": [0, 0, 1, 1],
+                            "damage": 0, "collide": false}}],
+            {}"tiles": [0], "entities": []}}"#, field);
+        let map = MapFactory::from_json(tile_size("").as_bytes()).unwrap();
+        assert_eq!(map.tile_size, 1.0);
+        match MapFactory::from_json(tile_size(r#""tile_size": 0, "#).as_bytes()) {
+            Err(MapLoadError::Invalid(_)) => {}
+            r => panic!("Unexpected result {:?}", r.map(|_| ())),
+        }
+
+        match MapFactory::from_json("{".as_bytes()) {
+            Err(MapLoadError::Json(_)) => {}
+            r => panic!("Unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+}
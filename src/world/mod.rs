@@ -0,0 +1,3016 @@
+//! This module has the base structures for the game elements.
+//!
+//! The map is made of tiles, where each tile references an entry in the tile
+//! type array. Tiles are ordered row first (Y goes from bottom to top) then
+//! column (X goes from left to right).
+//!
+//! Apart from the static grid of tiles, entities represent everything else:
+//! characters, items, bullets. They can occupy any decimal position, and move.
+//!
+//! Some tiles are active in some way, meaning that they have logic attached to
+//! them. These are represented by tile entities, which means the tile types has
+//! `has_entity` set to `true`, and an entity exists for each tile of that type.
+
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::error::Error;
+use std::f64;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use rand::{SeedableRng, StdRng};
+use serde_json;
+use xml;
+
+use ::Resources;
+use input::InputManager;
+use spatial::SpatialGrid;
+use sprites::{DrawEffect, NO_TINT, Sprite, SpriteError, SpriteSheet};
+use utils::one_rest_split_retain;
+use vecmath::*;
+
+mod builder;
+mod generate;
+mod json;
+mod tmx;
+
+pub use self::builder::{MapFactoryBuilder, TileTypeId};
+
+/// Size of the cells of the grid used to find neighbors, in tiles.
+const GRID_CELL_SIZE: f64 = 4.0;
+
+/// Create the random generator for a game, the same for the same seed.
+pub fn seeded_rng(seed: u32) -> StdRng {
+    let seed: &[usize] = &[seed as usize];
+    StdRng::from_seed(seed)
+}
+
+/// What entities see of the game while updating: the players' input, and
+/// the time.
+///
+/// This doesn't need a window, so that the world can be stepped without one,
+/// see `Game::step_headless()`.
+pub struct SimContext<'a> {
+    pub input: &'a InputManager,
+    /// Time since the level started, in seconds.
+    pub time: f64,
+}
+
+/// This represents the logic for a type of entity.
+///
+/// Each frame, entities are updated in phases: every entity gets its `update`
+/// call, in which it moves, before any entity gets its `react` call. This way
+/// reactions to other entities (collisions, AI) don't depend on whether the
+/// other entity came before or after in the update order.
+pub trait EntityLogic: fmt::Debug {
+    /// Movement phase: update the entity's own state and position.
+    ///
+    /// Returning false removes the entity from the world, after which its
+    /// `on_death` method gets called.
+    fn update(&mut self, entity: &mut EntityPhysics, dt: f64,
+              world: &mut WorldView, ctx: &SimContext, sprite: &mut Option<Sprite>) -> bool;
+
+    /// Reaction phase, once all the entities have moved for this frame.
+    fn react(&mut self, entity: &mut EntityPhysics, dt: f64,
+             world: &mut WorldView, ctx: &SimContext) {
+    }
+
+    /// Called when the entity enters a tile whose type has `notify_enter`.
+    ///
+    /// This is only called once when stepping onto that tile type, not
+    /// every frame while staying on it.
+    fn on_tile_enter(&mut self, entity: &mut EntityPhysics, tile: Tile, tile_type: &TileType) {
+    }
+
+    /// Called when the entity dies and gets removed from the world.
+    ///
+    /// Entities can be added to the world from here, for instance to drop
+    /// loot or shrapnel.
+    fn on_death(&mut self, entity: &mut EntityPhysics, world: &mut WorldView) {
+    }
+
+    /// Name of the entity type in saved games, `None` if it isn't saved.
+    fn type_id(&self) -> Option<&str> {
+        None
+    }
+
+    /// State of the logic to put in saved games, given back when loading.
+    fn save_state(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    /// State of the logic to show in debug dumps, see `World::debug_dump()`.
+    fn debug_state(&self) -> serde_json::Value {
+        self.save_state()
+    }
+
+    /// Size of the box others can stand on, centered on the entity's
+    /// position horizontally and standing on it, if any.
+    fn platform_size(&self) -> Option<Vector2> {
+        None
+    }
+
+    /// The box the entity takes up at a position, for collisions with other
+    /// entities, if it has one.
+    fn hitbox(&self, pos: Vector2) -> Option<Aabb> {
+        None
+    }
+
+    /// Called when the entity touches an item, returns whether it takes it.
+    fn collect(&mut self, entity: &mut EntityPhysics, effect: &Effect) -> bool {
+        false
+    }
+
+    /// Entities with a higher value are drawn over those with a lower one.
+    fn z_order(&self) -> i32 {
+        0
+    }
+}
+
+/// Something an entity does to the world, applied after the entities are
+/// updated, see `World::apply_events()`.
+#[derive(Debug)]
+pub enum WorldEvent {
+    /// Hurt an entity, killing it if it has no health left.
+    Damage { target: EntityId, amount: f64 },
+    /// Add an entity to the world.
+    Spawn(Entity),
+    /// Play a sound effect, by name.
+    PlaySound(String),
+    /// A character entered a trigger zone.
+    Trigger(Trigger),
+}
+
+/// What an item does to the entity collecting it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    /// Restore some health, without going over full health.
+    Heal(f64),
+}
+
+/// This represents the physical attributes of an entity.
+pub struct EntityPhysics {
+    pub pos: Vector2,
+    pub speed: Vector2,
+    /// Multiplier on the gravity this entity is subject to (1 is normal).
+    pub gravity_scale: f64,
+    /// Remaining health, from 1 (full) down to 0 (dead).
+    pub health: f64,
+}
+
+impl EntityPhysics {
+    /// Accelerate the entity under the given gravity.
+    pub fn apply_gravity(&mut self, gravity: f64, dt: f64) {
+        self.speed[1] += gravity * self.gravity_scale * dt;
+    }
+
+    /// Lose some health, returns whether the entity is still alive.
+    pub fn hurt(&mut self, damage: f64) -> bool {
+        self.health -= damage;
+        self.health > 0.0
+    }
+
+    /// Regain some health, up to full health.
+    pub fn heal(&mut self, amount: f64) {
+        self.health = (self.health + amount).min(1.0);
+    }
+}
+
+/// Physical constants of a map, for themed levels (moon, underwater).
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Physics {
+    /// Vertical acceleration, negative to fall down.
+    pub gravity: f64,
+    /// Horizontal speed of characters running on the ground.
+    pub run_speed: f64,
+    /// Vertical speed given to characters by a jump.
+    pub jump_speed: f64,
+    /// Horizontal acceleration of characters in the air.
+    pub air_accel: f64,
+}
+
+impl Default for Physics {
+    fn default() -> Physics {
+        Physics {
+            gravity: -10.0,
+            run_speed: 5.0,
+            jump_speed: 5.0,
+            air_accel: 20.0,
+        }
+    }
+}
+
+/// How the camera follows the focused entities, can be tuned by the map.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    /// Fraction of the distance to its target the camera covers at each
+    /// update, horizontally and vertically.
+    pub smoothing: [f64; 2],
+    /// Half extents of the area around the center of the view in which the
+    /// target can move without the camera following.
+    pub deadzone: [f64; 2],
+    /// Fastest the center of the view moves, along each axis.
+    pub max_speed: f64,
+}
+
+impl Default for CameraConfig {
+    fn default() -> CameraConfig {
+        CameraConfig {
+            smoothing: [0.1, 0.1],
+            deadzone: [0.5, 0.5],
+            max_speed: 40.0,
+        }
+    }
+}
+
+/// Identifier of an entity, unique within a world.
+///
+/// Identifiers are handed out in increasing order as entities are added to
+/// the world; the default value 0 means the entity wasn't added yet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EntityId(u32);
+
+/// This is an entity in the world, with a position and pointer to the logic.
+pub struct Entity {
+    pub id: EntityId,
+    pub physics: EntityPhysics,
+    pub logic: Box<EntityLogic>,
+    pub sprite: Option<Sprite>,
+    /// Effect to apply when drawing the sprite (highlight, damage flash).
+    pub effect: Option<DrawEffect>,
+    /// Position where the sprite is drawn, see `smoothing`.
+    pub render_pos: Vector2,
+    /// `render_pos` before the last update, to interpolate between updates.
+    pub prev_render_pos: Vector2,
+    /// If set, the sprite lags behind the physics position, the remaining
+    /// distance decaying exponentially at this rate (per second).
+    pub smoothing: Option<f64>,
+    /// If set, the time left before the entity gets removed (in seconds),
+    /// whatever its logic does, see `World::expire_entities()`.
+    pub ttl: Option<f64>,
+    /// Type of the tile the entity was on, to notify when it changes.
+    tile: Option<Tile>,
+}
+
+impl Entity {
+    /// Create an entity at rest at the given position.
+    pub fn new<T: EntityLogic + 'static>(pos: Vector2, logic: T) -> Entity {
+        Entity::with_logic(pos, Box::new(logic))
+    }
+
+    /// Create an entity at rest, from already boxed logic.
+    pub fn with_logic(pos: Vector2, logic: Box<EntityLogic>) -> Entity {
+        Entity {
+            id: EntityId::default(),
+            physics: EntityPhysics {
+                pos: pos,
+                speed: [0.0, 0.0],
+                gravity_scale: 1.0,
+                health: 1.0,
+            },
+            logic: logic,
+            sprite: None,
+            effect: None,
+            render_pos: pos,
+            prev_render_pos: pos,
+            smoothing: None,
+            ttl: None,
+            tile: None,
+        }
+    }
+
+    /// Move the rendered position towards the physics position.
+    pub fn smooth_render_pos(&mut self, dt: f64) {
+        self.prev_render_pos = self.render_pos;
+        self.render_pos = match self.smoothing {
+            Some(rate) => {
+                let t = 1.0 - (-rate * dt).exp();
+                let delta = vec2_sub(self.physics.pos, self.render_pos);
+                vec2_add(self.render_pos, vec2_scale(delta, t))
+            }
+            None => self.physics.pos,
+        };
+    }
+
+    /// Where to draw the sprite, `alpha` of the way from the previous update.
+    pub fn draw_pos(&self, alpha: f64) -> Vector2 {
+        self.prev_render_pos.lerp(self.render_pos, alpha)
+    }
+}
+
+impl fmt::Debug for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Entity {:?} {:?} @ {:?}", self.id, self.logic, self.physics.pos)
+    }
+}
+
+pub type TileEntityFactory = &'static Fn(Tile, &TileType, (usize, usize)) -> Option<Entity>;
+
+/// Definition of a tile type, referenced by tiles.
+pub struct TileType {
+    /// Sprite for that tile.
+    pub sprite: Option<Sprite>,
+    /// Damage suffered from touching that tile.
+    pub damage: f64,
+    /// How much weaker the damage is at the edge of the tile than at its
+    /// center, from 0 (uniform) to 1 (no damage at the edge).
+    pub damage_falloff: f64,
+    /// Whether entities will collide with that tile, or pass through.
+    pub collide: bool,
+    /// Velocity given to entities standing on that tile (conveyor belts).
+    pub surface_velocity: Vector2,
+    /// Offset of the sprite from the center of the cell, in tiles.
+    pub render_offset: Vector2,
+    /// Shape of the ground, if the tile collides.
+    pub shape: TileShape,
+    /// Whether the tile only collides with what lands on it from above.
+    pub one_way: bool,
+    /// Whether characters can climb that tile (ladders), moving up and down
+    /// without gravity.
+    pub climbable: bool,
+    /// Whether entities get notified when they step on that tile.
+    pub notify_enter: bool,
+    /// What happens when a character enters the tile, see `TriggerState`.
+    pub trigger: Option<Trigger>,
+    /// For doors, the tile type they switch to when open.
+    pub open_tile: Option<Tile>,
+    /// Whether an entity is associated with this tile.
+    tile_entity: bool,
+}
+
+/// Shape of the solid part of a colliding tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TileShape {
+    Full,
+    /// Ramp going up towards the left.
+    SlopeUpLeft,
+    /// Ramp going up towards the right.
+    SlopeUpRight,
+    Empty,
+}
+
+impl Default for TileShape {
+    fn default() -> TileShape {
+        TileShape::Full
+    }
+}
+
+impl FromStr for TileShape {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<TileShape, ()> {
+        match s {
+            "full" => Ok(TileShape::Full),
+            "slope_up_left" => Ok(TileShape::SlopeUpLeft),
+            "slope_up_right" => Ok(TileShape::SlopeUpRight),
+            "empty" => Ok(TileShape::Empty),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What happens when a character enters an (invisible) trigger tile.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// The goal of the level was reached.
+    CompleteLevel,
+    /// Play a sound effect, by name.
+    PlaySound(String),
+}
+
+impl FromStr for Trigger {
+    type Err = ();
+
+    /// Parse `complete_level` or `play_sound:<name>`.
+    fn from_str(s: &str) -> Result<Trigger, ()> {
+        if s == "complete_level" {
+            Ok(Trigger::CompleteLevel)
+        } else if s.starts_with("play_sound:") {
+            Ok(Trigger::PlaySound(s["play_sound:".len()..].to_owned()))
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// Fires a trigger once when a character enters its zone.
+///
+/// Standing in the zone, or moving between tiles with the same trigger,
+/// doesn't fire it again; it has to be left first.
+#[derive(Debug, Default)]
+pub struct TriggerState {
+    current: Option<Trigger>,
+}
+
+impl TriggerState {
+    /// Update with the trigger of the tile the character is on, returns the
+    /// trigger to fire, if it was just entered.
+    pub fn update(&mut self, trigger: Option<&Trigger>) -> Option<Trigger> {
+        if self.current.as_ref() == trigger {
+            return None;
+        }
+        self.current = trigger.cloned();
+        self.current.clone()
+    }
+}
+
+impl TileShape {
+    /// Height of the ground in the tile, at a horizontal offset from 0 (left)
+    /// to 1 (right).
+    pub fn height_at(&self, x: f64) -> f64 {
+        let x = x.max(0.0).min(1.0);
+        match *self {
+            TileShape::Full => 1.0,
+            TileShape::SlopeUpLeft => 1.0 - x,
+            TileShape::SlopeUpRight => x,
+            TileShape::Empty => 0.0,
+        }
+    }
+}
+
+impl TileType {
+    /// Where the sprite of the tile at the given coordinates is centered.
+    ///
+    /// This only affects drawing, not collisions.
+    pub fn render_center(&self, x: i32, y: i32) -> Vector2 {
+        [x as f64 + 0.5 + self.render_offset.x(),
+         y as f64 + 0.5 + self.render_offset.y()]
+    }
+}
+
+/// A tile in the map, just references a TileType.
+pub type Tile = u16;
+
+pub trait Spawnable {
+    /// Create an entity at a spawn point, the feet of what is spawned.
+    ///
+    /// Returns whether to keep this spawnable for later, and the entity.
+    fn spawn(&mut self, pos: &Vector2) -> (bool, Option<Entity>);
+}
+
+/// What a ray ran into.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HitTarget {
+    /// A colliding tile, by its coordinates.
+    Tile(i32, i32),
+    Entity(EntityId),
+}
+
+/// Result of a raycast.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    /// Point where the ray entered the target.
+    pub pos: Vector2,
+    /// Distance from the origin of the ray to `pos`.
+    pub dist: f64,
+    pub target: HitTarget,
+}
+
+/// Distance along a ray (with normalized direction) to enter a box.
+fn ray_box(origin: Vector2, dir: Vector2, min: Vector2, max: Vector2) -> Option<f64> {
+    let mut enter = 0.0f64;
+    let mut exit = f64::INFINITY;
+    for i in 0..2 {
+        if dir[i] == 0.0 {
+            if origin[i] < min[i] || origin[i] > max[i] {
+                return None;
+            }
+        } else {
+            let t1 = (min[i] - origin[i]) / dir[i];
+            let t2 = (max[i] - origin[i]) / dir[i];
+            enter = enter.max(t1.min(t2));
+            exit = exit.min(t1.max(t2));
+        }
+    }
+    if enter <= exit { Some(enter) } else { None }
+}
+
+/// When a layer of tiles is drawn, relative to the entities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderOrder {
+    /// Decoration drawn behind the main layer.
+    Background,
+    /// The layer things collide with.
+    Main,
+    /// Decoration drawn over the entities.
+    Foreground,
+}
+
+impl FromStr for RenderOrder {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<RenderOrder, ()> {
+        match s {
+            "background" => Ok(RenderOrder::Background),
+            "main" => Ok(RenderOrder::Main),
+            "foreground" => Ok(RenderOrder::Foreground),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A layer of tiles, covering the whole map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileLayer {
+    pub render_order: RenderOrder,
+    /// The tiles, ordered Y first (bottom to top) then X (left to right).
+    pub tiles: Vec<Tile>,
+}
+
+impl TileLayer {
+    pub fn new(render_order: RenderOrder, tiles: Vec<Tile>) -> TileLayer {
+        TileLayer {
+            render_order: render_order,
+            tiles: tiles,
+        }
+    }
+}
+
+/// Iterator on the visible tiles of a layer, see `Map::visible_tiles()`.
+///
+/// Yields the coordinates and type of each tile, row by row.
+pub struct VisibleTiles<'a> {
+    map: &'a Map,
+    layer: usize,
+    x1: i32,
+    x2: i32,
+    y2: i32,
+    x: i32,
+    y: i32,
+}
+
+impl<'a> Iterator for VisibleTiles<'a> {
+    type Item = (i32, i32, &'a TileType);
+
+    fn next(&mut self) -> Option<(i32, i32, &'a TileType)> {
+        if self.x1 >= self.x2 {
+            return None;
+        }
+        if self.x >= self.x2 {
+            self.x = self.x1;
+            self.y += 1;
+        }
+        if self.y >= self.y2 {
+            return None;
+        }
+        let (x, y) = (self.x, self.y);
+        self.x += 1;
+        self.map.layer_tile(self.layer, x, y).map(|tile| (x, y, tile))
+    }
+}
+
+/// The map, representing the status of the world at a given instant.
+pub struct Map {
+    /// Width in number of tiles.
+    pub width: usize,
+    /// Height in number of tiles.
+    pub height: usize,
+    /// Size of a tile in world units, 1 by default.
+    pub tile_size: f64,
+    /// The tile types, referenced by the tile arrays.
+    tiletypes: Vec<TileType>,
+    /// The layers of tiles, from back to front.
+    layers: Vec<TileLayer>,
+    /// Index of the main layer, the only one used for collisions.
+    main: usize,
+}
+
+impl Map {
+    /// Create a map from its layers, exactly one of which is the main layer.
+    ///
+    /// The layers are put in drawing order, keeping the order of those with
+    /// the same `RenderOrder`.
+    pub fn new(width: usize, height: usize, tiletypes: Vec<TileType>,
+               mut layers: Vec<TileLayer>) -> Map {
+        layers.sort_by_key(|l| l.render_order);
+        let main = layers.iter().position(|l| l.render_order == RenderOrder::Main)
+            .expect("Map has no main layer");
+        Map {
+            width: width,
+            height: height,
+            tile_size: 1.0,
+            tiletypes: tiletypes,
+            layers: layers,
+            main: main,
+        }
+    }
+
+    /// The layers of tiles, from back to front.
+    pub fn layers(&self) -> &[TileLayer] {
+        &self.layers
+    }
+
+    /// Add a decorative layer, after the others with the same order.
+    ///
+    /// Returns false if it doesn't have the size of the map or is a main
+    /// layer, there can only be one.
+    pub fn add_layer(&mut self, layer: TileLayer) -> bool {
+        if layer.tiles.len() != self.width * self.height ||
+            layer.render_order == RenderOrder::Main
+        {
+            return false;
+        }
+        let pos = self.layers.iter()
+            .position(|l| l.render_order > layer.render_order)
+            .unwrap_or(self.layers.len());
+        if pos <= self.main {
+            self.main += 1;
+        }
+        self.layers.insert(pos, layer);
+        true
+    }
+
+    /// The tiles of the main layer.
+    pub fn tiles(&self) -> &[Tile] {
+        &self.layers[self.main].tiles
+    }
+
+    /// Type of a tile of the main layer.
+    pub fn tile(&self, x: i32, y: i32) -> Option<&TileType> {
+        self.layer_tile(self.main, x, y)
+    }
+
+    /// Type of a tile of any layer, by the index of the layer.
+    pub fn layer_tile(&self, layer: usize, x: i32, y: i32) -> Option<&TileType> {
+        if x >= 0 && self.width > x as usize &&
+            y >= 0 && self.height > y as usize
+        {
+            let tile = self.layers[layer].tiles[y as usize * self.width + x as usize];
+            Some(&self.tiletypes[tile as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Range of tiles seen by a camera, as `(x1, y1, x2, y2)` (exclusive).
+    ///
+    /// The view is given by its center and half extents. The range has a
+    /// margin of one tile, for tiles drawn with an offset.
+    pub fn visible_rect(&self, center: Vector2, half: Vector2) -> (i32, i32, i32, i32) {
+        let center = vec2_scale(center, 1.0 / self.tile_size);
+        let half = vec2_scale(half, 1.0 / self.tile_size);
+        let x1 = max((center.x() - half.x()) as i32 - 1, 0);
+        let y1 = max((center.y() - half.y()) as i32 - 1, 0);
+        let x2 = min((center.x() + half.x() + 1.0) as i32, self.width as i32);
+        let y2 = min((center.y() + half.y() + 1.0) as i32, self.height as i32);
+        (x1, y1, x2, y2)
+    }
+
+    /// Iterate on the tiles of a layer seen by a camera, see `visible_rect()`.
+    pub fn visible_tiles(&self, layer: usize, center: Vector2, half: Vector2) -> VisibleTiles {
+        let (x1, y1, x2, y2) = self.visible_rect(center, half);
+        VisibleTiles {
+            map: self,
+            layer: layer,
+            x1: x1,
+            x2: x2,
+            y2: y2,
+            x: x1,
+            y: y1,
+        }
+    }
+
+    /// The tile containing a world position, as `(x, y)` indices.
+    pub fn tile_index(&self, x: f64, y: f64) -> (i32, i32) {
+        ((x / self.tile_size).floor() as i32, (y / self.tile_size).floor() as i32)
+    }
+
+    /// Type of the tile of the main layer containing a world position.
+    pub fn tilef(&self, x: f64, y: f64) -> Option<&TileType> {
+        let (tx, ty) = self.tile_index(x, y);
+        self.tile(tx, ty)
+    }
+
+    /// Change the type of a tile of the main layer.
+    ///
+    /// Returns false if the position is outside the map or the tile type
+    /// doesn't exist.
+    pub fn set_tile(&mut self, x: usize, y: usize, tile: Tile) -> bool {
+        if x < self.width && y < self.height && (tile as usize) < self.tiletypes.len() {
+            self.layers[self.main].tiles[y * self.width + x] = tile;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Height of the ground under a point, if it is in a colliding tile.
+    ///
+    /// This follows the shape of slopes. A slope in the tile above also
+    /// counts, so that things at the foot of a ramp step onto it.
+    pub fn ground_at(&self, x: f64, y: f64) -> Option<(f64, &TileType)> {
+        let ts = self.tile_size;
+        let (x, y) = (x / ts, y / ts);
+        let (tx, ty) = (x.floor(), y.floor());
+        if let Some(above) = self.tile(tx as i32, ty as i32 + 1) {
+            if above.collide && above.shape != TileShape::Full {
+                return Some(((ty + 1.0 + above.shape.height_at(x - tx)) * ts, above));
+            }
+        }
+        match self.tile(tx as i32, ty as i32) {
+            Some(tile) if tile.collide => Some(((ty + tile.shape.height_at(x - tx)) * ts, tile)),
+            _ => None,
+        }
+    }
+
+    /// Height of the first ground found going straight down from a point,
+    /// if it is at most `max_dist` below.
+    ///
+    /// Any colliding tile counts, following the shape of slopes.
+    pub fn ground_below(&self, pos: Vector2, max_dist: f64) -> Option<f64> {
+        let ts = self.tile_size;
+        let (x, y, max_dist) = (pos.x() / ts, pos.y() / ts, max_dist / ts);
+        let tx = x.floor();
+        let mut ty = y.floor();
+        while ty >= 0.0 && y - (ty + 1.0) <= max_dist {
+            if let Some(tile) = self.tile(tx as i32, ty as i32) {
+                if tile.collide {
+                    let top = ty + tile.shape.height_at(x - tx);
+                    if top <= y {
+                        return if y - top <= max_dist { Some(top * ts) } else { None };
+                    }
+                }
+            }
+            ty -= 1.0;
+        }
+        None
+    }
+
+    /// Top of the first one-way platform crossed going down between heights.
+    pub fn platform_crossed(&self, x: f64, from_y: f64, to_y: f64) -> Option<f64> {
+        let ts = self.tile_size;
+        let tx = (x / ts).floor() as i32;
+        let mut top = (from_y / ts).floor();
+        while top > to_y / ts {
+            if let Some(tile) = self.tile(tx, top as i32 - 1) {
+                if tile.collide && tile.one_way {
+                    return Some(top * ts);
+                }
+            }
+            top -= 1.0;
+        }
+        None
+    }
+
+    /// Damage per second suffered at a position.
+    ///
+    /// This is strongest at the center of the tile, and lowers towards its
+    /// edges according to the tile type's `damage_falloff`.
+    pub fn damage_at(&self, x: f64, y: f64) -> f64 {
+        let (x, y) = (x / self.tile_size, y / self.tile_size);
+        let (tx, ty) = (x.floor(), y.floor());
+        if let Some(tile) = self.tile(tx as i32, ty as i32) {
+            let dist = vec2_len([x - tx - 0.5, y - ty - 0.5]) * 2.0;
+            tile.damage * (1.0 - tile.damage_falloff * dist.min(1.0))
+        } else {
+            0.0
+        }
+    }
+
+    /// Whether a position is in a tile that blocks from every side: colliding,
+    /// full, and not a one-way platform.
+    ///
+    /// The outside of the map isn't solid.
+    pub fn is_solid(&self, x: f64, y: f64) -> bool {
+        let (tx, ty) = self.tile_index(x, y);
+        match self.tile(tx, ty) {
+            Some(tile) => tile.collide && !tile.one_way && tile.shape == TileShape::Full,
+            None => false,
+        }
+    }
+
+    /// The tile containing a position, as its bottom-left and top-right
+    /// corners.
+    pub fn tile_rect(&self, x: f64, y: f64) -> (Vector2, Vector2) {
+        let ts = self.tile_size;
+        let (tx, ty) = self.tile_index(x, y);
+        let (x, y) = (tx as f64 * ts, ty as f64 * ts);
+        ([x, y], [x + ts, y + ts])
+    }
+
+    /// Whether no colliding tile overlaps the rectangle.
+    ///
+    /// The outside of the map counts as blocked.
+    pub fn area_clear(&self, min: Vector2, max: Vector2) -> bool {
+        let ts = self.tile_size;
+        let (x1, y1) = ((min.x() / ts).floor() as i32, (min.y() / ts).floor() as i32);
+        let (x2, y2) = ((max.x() / ts).ceil() as i32, (max.y() / ts).ceil() as i32);
+        for y in y1..y2 {
+            for x in x1..x2 {
+                match self.tile(x, y) {
+                    Some(tile) if !tile.collide => {}
+                    _ => return false,
+                }
+            }
+        }
+        true
+    }
+
+    /// Find the closest position where a box standing there is clear.
+    ///
+    /// The box is centered horizontally on the position and extends upwards
+    /// from it. Positions are tried by whole tiles from `pos`, up to
+    /// `max_dist` tiles away.
+    pub fn nearest_clear(&self, pos: Vector2, size: Vector2,
+                         max_dist: i32) -> Option<Vector2> {
+        let clear = |p: Vector2| {
+            self.area_clear([p.x() - size.x() / 2.0, p.y()],
+                            [p.x() + size.x() / 2.0, p.y() + size.y()])
+        };
+        for dist in 0..max_dist + 1 {
+            // Try the ring of tiles at that distance, keep the closest
+            let mut best: Option<(i32, Vector2)> = None;
+            for dy in -dist..dist + 1 {
+                for dx in -dist..dist + 1 {
+                    if dx.abs() != dist && dy.abs() != dist {
+                        continue;
+                    }
+                    let p = [pos.x() + dx as f64 * self.tile_size,
+                             pos.y() + dy as f64 * self.tile_size];
+                    let d = dx * dx + dy * dy;
+                    if best.map_or(true, |(best_d, _)| d < best_d) && clear(p) {
+                        best = Some((d, p));
+                    }
+                }
+            }
+            if let Some((_, p)) = best {
+                return Some(p);
+            }
+        }
+        None
+    }
+
+    /// Where a segment first runs into a colliding tile, if it does.
+    ///
+    /// Unlike only checking the end position, this doesn't skip over thin
+    /// walls when moving fast.
+    pub fn cast(&self, from: Vector2, to: Vector2) -> Option<Vector2> {
+        let motion = vec2_sub(to, from);
+        self.raycast(from, motion, vec2_len(motion)).map(|hit| hit.pos)
+    }
+
+    /// Find the first colliding tile along a ray.
+    ///
+    /// This walks the grid tile by tile (DDA). A ray leaving the map doesn't
+    /// hit anything. Only full tiles stop rays, not slopes or one-way
+    /// platforms.
+    pub fn raycast(&self, origin: Vector2, dir: Vector2, max_dist: f64) -> Option<Hit> {
+        let len = vec2_len(dir);
+        if len == 0.0 {
+            return None;
+        }
+        let dir = vec2_scale(dir, 1.0 / len);
+        // Walk the grid in tile units
+        let ts = self.tile_size;
+        let start = vec2_scale(origin, 1.0 / ts);
+        let max_dist = max_dist / ts;
+        let (mut x, mut y) = (start.x().floor() as i32, start.y().floor() as i32);
+        let step = |d: f64| if d > 0.0 { 1 } else { -1 };
+        let (step_x, step_y) = (step(dir.x()), step(dir.y()));
+        // Distance along the ray to cross a whole tile, and to cross the
+        // next tile boundary, on each axis
+        let delta = |d: f64| if d == 0.0 { f64::INFINITY } else { 1.0 / d.abs() };
+        let (delta_x, delta_y) = (delta(dir.x()), delta(dir.y()));
+        let first = |o: f64, d: f64, delta: f64| {
+            if d == 0.0 {
+                f64::INFINITY
+            } else if d > 0.0 {
+                (o.floor() + 1.0 - o) * delta
+            } else {
+                (o - o.floor()) * delta
+            }
+        };
+        let mut next_x = first(start.x(), dir.x(), delta_x);
+        let mut next_y = first(start.y(), dir.y(), delta_y);
+        let mut dist = 0.0;
+        loop {
+            match self.tile(x, y) {
+                None => return None,
+                Some(tile) if tile.collide && !tile.one_way &&
+                              tile.shape == TileShape::Full => {
+                    return Some(Hit {
+                        pos: vec2_add(origin, vec2_scale(dir, dist * ts)),
+                        dist: dist * ts,
+                        target: HitTarget::Tile(x, y),
+                    });
+                }
+                Some(_) => {}
+            }
+            if next_x < next_y {
+                dist = next_x;
+                next_x += delta_x;
+                x += step_x;
+            } else {
+                dist = next_y;
+                next_y += delta_y;
+                y += step_y;
+            }
+            if dist > max_dist {
+                return None;
+            }
+        }
+    }
+}
+
+/// Compute the offset of a background layer.
+///
+/// The layer follows the camera by the parallax factor: 0 means it stays
+/// fixed on screen, 1 that it moves along with the world.
+pub fn parallax_offset(camera_pos: Vector2, parallax: f64, scroll: Vector2) -> Vector2 {
+    vec2_add(vec2_scale(camera_pos, parallax), scroll)
+}
+
+/// Continuous scrolling of a background layer, independent of the camera.
+pub struct Scroll {
+    /// Scrolling speed, in world units per second.
+    pub velocity: Vector2,
+    /// Distance scrolled so far.
+    pub offset: Vector2,
+}
+
+impl Scroll {
+    pub fn new(velocity: Vector2) -> Scroll {
+        Scroll {
+            velocity: velocity,
+            offset: [0.0, 0.0],
+        }
+    }
+
+    pub fn advance(&mut self, dt: f64) {
+        self.offset = vec2_add(self.offset, vec2_scale(self.velocity, dt));
+    }
+}
+
+/// An image drawn behind the tiles, repeated to cover the screen.
+pub struct BackgroundLayer {
+    pub sheet: Rc<SpriteSheet>,
+    /// How much the layer follows the camera, see `parallax_offset()`.
+    pub parallax: f64,
+    /// Automatic scrolling (drifting clouds).
+    pub scroll: Scroll,
+}
+
+impl BackgroundLayer {
+    /// Offset of the layer, in world units, for the given camera position.
+    pub fn offset(&self, camera_pos: Vector2) -> Vector2 {
+        parallax_offset(camera_pos, self.parallax, self.scroll.offset)
+    }
+}
+
+/// A color cycling over time, modulating the whole scene (day and night).
+pub struct AmbientCycle {
+    /// Duration of a full cycle, in seconds.
+    pub length: f64,
+    /// Colors at points of the cycle, from 0 to 1, in increasing order.
+    pub keyframes: Vec<(f64, [f32; 4])>,
+}
+
+impl AmbientCycle {
+    /// The color at a given time, interpolated between keyframes.
+    pub fn color_at(&self, time: f64) -> [f32; 4] {
+        let n = self.keyframes.len();
+        if n == 0 {
+            return NO_TINT;
+        }
+        let phase = time / self.length - (time / self.length).floor();
+        // Keyframes around the phase, wrapping around the cycle
+        let next = self.keyframes.iter().position(|&(t, _)| t > phase).unwrap_or(n);
+        let (t1, c1) = if next == 0 {
+            (self.keyframes[n - 1].0 - 1.0, self.keyframes[n - 1].1)
+        } else {
+            self.keyframes[next - 1]
+        };
+        let (t2, c2) = if next == n {
+            (self.keyframes[0].0 + 1.0, self.keyframes[0].1)
+        } else {
+            self.keyframes[next]
+        };
+        let f = if t2 > t1 { ((phase - t1) / (t2 - t1)) as f32 } else { 0.0 };
+        [c1[0] + (c2[0] - c1[0]) * f,
+         c1[1] + (c2[1] - c1[1]) * f,
+         c1[2] + (c2[2] - c1[2]) * f,
+         c1[3] + (c2[3] - c1[3]) * f]
+    }
+}
+
+pub struct World {
+    /// The map, grid of terrain tiles.
+    pub map: Map,
+    /// The physical constants.
+    pub physics: Physics,
+    /// The background layers, drawn back to front.
+    pub backgrounds: Vec<BackgroundLayer>,
+    /// The entities.
+    pub entities: Vec<Entity>,
+    /// The entities associated with tiles (doors, switches).
+    pub tile_entities: HashMap<(usize, usize), Entity>,
+    /// The things that can be spawned.
+    pub spawnables: Vec<Box<Spawnable>>,
+    /// The identifier that will be given to the next entity.
+    pub next_entity_id: u32,
+    /// Set when the goal of the level has been reached.
+    pub level_complete: bool,
+    /// If set, only these entities get updated, for the remaining time.
+    exclusive: Option<(Vec<EntityId>, f64)>,
+    /// Time elapsed in this world, in seconds.
+    pub clock: f64,
+    /// Color cycle modulating the scene, if any.
+    pub ambient: Option<AmbientCycle>,
+    /// Entities bucketed by position, see `rebuild_grid()`.
+    grid: SpatialGrid,
+    /// Random generator seeded from the game's seed, so runs can be replayed.
+    pub rng: StdRng,
+    /// Events sent by the entities, see `apply_events()`.
+    events: Vec<WorldEvent>,
+}
+
+impl World {
+    /// The current ambient color, white if there is no cycle.
+    pub fn ambient_color(&self) -> [f32; 4] {
+        match self.ambient {
+            Some(ref ambient) => ambient.color_at(self.clock),
+            None => NO_TINT,
+        }
+    }
+
+    /// Add an entity to the world, giving it a new identifier.
+    pub fn add_entity(&mut self, mut entity: Entity) -> EntityId {
+        entity.id = EntityId(self.next_entity_id);
+        self.next_entity_id += 1;
+        let id = entity.id;
+        self.entities.push(entity);
+        id
+    }
+
+    /// Remove an entity from the world, running its `on_death` hook.
+    ///
+    /// Returns false if there is no entity with that identifier.
+    pub fn kill_entity(&mut self, id: EntityId) -> bool {
+        let entity = match self.entities.iter().position(|e| e.id == id) {
+            Some(index) => self.entities.remove(index),
+            None => return false,
+        };
+        self.bury(entity);
+        true
+    }
+
+    /// Count down the lifetime of the entities that have one, removing those
+    /// whose time is up.
+    ///
+    /// Paused entities don't age. The removed entities get their `on_death`
+    /// hook run.
+    pub fn expire_entities(&mut self, dt: f64) {
+        {
+            let exclusive = &self.exclusive;
+            for entity in self.entities.iter_mut() {
+                if let Some((ref ids, _)) = *exclusive {
+                    if !ids.contains(&entity.id) {
+                        continue;
+                    }
+                }
+                if let Some(ref mut ttl) = entity.ttl {
+                    *ttl -= dt;
+                }
+            }
+        }
+        // Take the expired ones out in a single pass, keeping the order
+        let entities = mem::replace(&mut self.entities, Vec::new());
+        let (expired, alive): (Vec<Entity>, Vec<Entity>) = entities.into_iter()
+            .partition(|e| e.ttl.map_or(false, |ttl| ttl <= 0.0));
+        self.entities = alive;
+        for entity in expired {
+            self.bury(entity);
+        }
+    }
+
+    /// Apply the events sent by the entities, in order.
+    ///
+    /// Events sent while applying them (from `on_death`) get applied too.
+    pub fn apply_events<F>(&mut self, mut play_sound: F) where F: FnMut(&str) {
+        while !self.events.is_empty() {
+            let events = mem::replace(&mut self.events, Vec::new());
+            for event in events {
+                match event {
+                    WorldEvent::Damage { target, amount } => {
+                        let alive = match self.entities.iter_mut().find(|e| e.id == target) {
+                            Some(entity) => entity.physics.hurt(amount),
+                            None => true,
+                        };
+                        if !alive {
+                            self.kill_entity(target);
+                        }
+                    }
+                    WorldEvent::Spawn(entity) => {
+                        self.add_entity(entity);
+                    }
+                    WorldEvent::PlaySound(name) => play_sound(&name),
+                    WorldEvent::Trigger(Trigger::CompleteLevel) => self.level_complete = true,
+                    WorldEvent::Trigger(Trigger::PlaySound(name)) => play_sound(&name),
+                }
+            }
+        }
+    }
+
+    /// Run the `on_death` hook of an entity that was removed from the world.
+    fn bury(&mut self, mut entity: Entity) {
+        let mut focus = None;
+        let mut world_view = WorldView {
+            map: &mut self.map,
+            physics: &self.physics,
+            entities: &mut self.entities,
+            spawnables: &mut self.spawnables,
+            next_entity_id: &mut self.next_entity_id,
+            focus: &mut focus,
+            level_complete: &mut self.level_complete,
+            rng: &mut self.rng,
+            events: &mut self.events,
+            grid: &self.grid,
+        };
+        entity.logic.on_death(&mut entity.physics, &mut world_view);
+    }
+
+    /// Snapshot the entities that can be saved, for a saved game.
+    pub fn save_entities(&self) -> Vec<SavedEntity> {
+        self.entities.iter().filter_map(|entity| {
+            entity.logic.type_id().map(|type_id| SavedEntity {
+                id: entity.id.0,
+                type_id: type_id.to_owned(),
+                pos: entity.physics.pos,
+                speed: entity.physics.speed,
+                health: entity.physics.health,
+                state: entity.logic.save_state(),
+            })
+        }).collect()
+    }
+
+    /// Replace the entities with those from a saved game.
+    ///
+    /// The function creates the entity from its type and saved state, its
+    /// identifier and physical state then get restored. It can return `None`
+    /// to drop an entity.
+    pub fn restore_entities<F>(&mut self, saved: Vec<SavedEntity>,
+                               mut create: F) -> Result<(), MapLoadError>
+        where F: FnMut(&SavedEntity) -> Result<Option<Entity>, MapLoadError>
+    {
+        self.entities.clear();
+        for s in saved {
+            if let Some(mut entity) = try!(create(&s)) {
+                entity.id = EntityId(s.id);
+                entity.physics.pos = s.pos;
+                entity.physics.speed = s.speed;
+                entity.physics.health = s.health;
+                entity.render_pos = s.pos;
+                entity.prev_render_pos = s.pos;
+                self.next_entity_id = self.next_entity_id.max(s.id + 1);
+                self.entities.push(entity);
+            }
+        }
+        self.sort_entities();
+        Ok(())
+    }
+
+    /// Write the map size and all the entities as JSON, to attach to bug
+    /// reports.
+    ///
+    /// Unlike `save_entities()`, this includes the entities that aren't
+    /// saved and the tile entities, and can't be loaded back.
+    pub fn debug_dump<W: Write>(&self, writer: W) -> serde_json::Result<()> {
+        let describe = |entity: &Entity, tile: Option<(usize, usize)>| DebugEntity {
+            id: entity.id.0,
+            type_id: entity.logic.type_id().map(|t| t.to_owned()),
+            logic: format!("{:?}", entity.logic),
+            tile: tile,
+            pos: entity.physics.pos,
+            speed: entity.physics.speed,
+            health: entity.physics.health,
+            state: entity.logic.debug_state(),
+        };
+        let mut tiles: Vec<_> = self.tile_entities.keys().cloned().collect();
+        tiles.sort();
+        let dump = DebugDump {
+            width: self.map.width,
+            height: self.map.height,
+            clock: self.clock,
+            entities: self.entities.iter().map(|e| describe(e, None)).collect(),
+            tile_entities: tiles.into_iter()
+                .map(|tile| describe(&self.tile_entities[&tile], Some(tile)))
+                .collect(),
+        };
+        serde_json::to_writer_pretty(writer, &dump)
+    }
+
+    /// Index the entities by position, for `WorldView::neighbors()`.
+    pub fn rebuild_grid(&mut self) {
+        self.grid.clear();
+        for entity in self.entities.iter() {
+            self.grid.insert(entity.id.0 as usize, entity.physics.pos);
+        }
+    }
+
+    /// Put the entities in identifier order, in which they get updated.
+    ///
+    /// This makes the simulation independent of the order in which entities
+    /// got added and removed from the vector.
+    pub fn sort_entities(&mut self) {
+        self.entities.sort_by_key(|e| e.id);
+    }
+
+    /// Notify the entities that stepped onto a new type of tile.
+    pub fn notify_tiles(&mut self) {
+        let map = &self.map;
+        for entity in self.entities.iter_mut() {
+            let pos = entity.physics.pos;
+            let (x, y) = map.tile_index(pos.x(), pos.y());
+            let tile = if map.tile(x, y).is_some() {
+                Some(map.tiles()[y as usize * map.width + x as usize])
+            } else {
+                None
+            };
+            if tile == entity.tile {
+                continue;
+            }
+            entity.tile = tile;
+            if let Some(tile) = tile {
+                let tile_type = &map.tiletypes[tile as usize];
+                if tile_type.notify_enter {
+                    entity.logic.on_tile_enter(&mut entity.physics, tile, tile_type);
+                }
+            }
+        }
+    }
+
+    /// Pause every entity except the given ones, for some time.
+    ///
+    /// This is meant for cinematics, such as a boss doing its intro while the
+    /// players wait.
+    pub fn pause_all_except(&mut self, ids: Vec<EntityId>, duration: f64) {
+        self.exclusive = Some((ids, duration));
+    }
+
+    /// Whether the entity is currently paused.
+    pub fn is_paused(&self, id: EntityId) -> bool {
+        match self.exclusive {
+            Some((ref ids, _)) => !ids.contains(&id),
+            None => false,
+        }
+    }
+
+    /// Count down the time left on a pause.
+    pub fn advance_pause(&mut self, dt: f64) {
+        let over = match self.exclusive {
+            Some((_, ref mut remaining)) => {
+                *remaining -= dt;
+                *remaining <= 0.0
+            }
+            None => false,
+        };
+        if over {
+            self.exclusive = None;
+        }
+    }
+
+    /// Run a pass over the entities, giving each a view of the rest of the
+    /// world.
+    ///
+    /// Paused entities are skipped, but are still visible to the others.
+    pub fn entities_pass<F>(&mut self, focus: &mut Option<(Vector2, Vector2)>, mut f: F)
+        where F: FnMut(&mut Entity, &mut WorldView)
+    {
+        self.entities_retain_pass(focus, |entity, world_view| {
+            f(entity, world_view);
+            true
+        });
+    }
+
+    /// Run a pass over the entities, removing those for which the function
+    /// returns false.
+    ///
+    /// Paused entities are kept. The removed entities get their `on_death`
+    /// hook run once the pass is over.
+    pub fn entities_retain_pass<F>(&mut self, focus: &mut Option<(Vector2, Vector2)>,
+                                   mut f: F)
+        where F: FnMut(&mut Entity, &mut WorldView) -> bool
+    {
+        let dead = {
+            let exclusive = &self.exclusive;
+            let map = &mut self.map;
+            let physics = &self.physics;
+            let spawnables = &mut self.spawnables;
+            let next_entity_id = &mut self.next_entity_id;
+            let level_complete = &mut self.level_complete;
+            let rng = &mut self.rng;
+            let events = &mut self.events;
+            let grid = &self.grid;
+            one_rest_split_retain(&mut self.entities, |entity, other_entities| {
+                if let Some((ref ids, _)) = *exclusive {
+                    if !ids.contains(&entity.id) {
+                        return true;
+                    }
+                }
+                let mut world_view = WorldView {
+                    map: map,
+                    physics: physics,
+                    entities: other_entities,
+                    spawnables: spawnables,
+                    next_entity_id: next_entity_id,
+                    focus: focus,
+                    level_complete: level_complete,
+                    rng: rng,
+                    events: events,
+                    grid: grid,
+                };
+                f(entity, &mut world_view)
+            })
+        };
+        for entity in dead {
+            self.bury(entity);
+        }
+    }
+
+    /// Run a pass over the tile entities, giving each a view of the world.
+    ///
+    /// They run in order of position, and get removed if the function returns
+    /// false.
+    pub fn tile_entities_pass<F>(&mut self, focus: &mut Option<(Vector2, Vector2)>, mut f: F)
+        where F: FnMut(&mut Entity, &mut WorldView) -> bool
+    {
+        let mut positions: Vec<_> = self.tile_entities.keys().cloned().collect();
+        positions.sort();
+        for pos in positions {
+            let mut entity = self.tile_entities.remove(&pos).unwrap();
+            if self.is_paused(entity.id) {
+                self.tile_entities.insert(pos, entity);
+                continue;
+            }
+            let keep = {
+                let mut world_view = WorldView {
+                    map: &mut self.map,
+                    physics: &self.physics,
+                    entities: &mut self.entities,
+                    spawnables: &mut self.spawnables,
+                    next_entity_id: &mut self.next_entity_id,
+                    focus: focus,
+                    level_complete: &mut self.level_complete,
+                    rng: &mut self.rng,
+                    events: &mut self.events,
+                            grid: &self.grid,
+                };
+                f(&mut entity, &mut world_view)
+            };
+            if keep {
+                self.tile_entities.insert(pos, entity);
+            } else {
+                self.bury(entity);
+            }
+        }
+    }
+}
+
+/// The box of an entity others can stand on, see
+/// `EntityLogic::platform_size()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlatformBounds {
+    pub min: Vector2,
+    pub max: Vector2,
+    /// Speed of the platform, carrying what stands on it.
+    pub velocity: Vector2,
+}
+
+pub struct WorldView<'a> {
+    pub map: &'a mut Map,
+    pub physics: &'a Physics,
+    pub entities: &'a mut Vec<Entity>,
+    pub spawnables: &'a mut Vec<Box<Spawnable>>,
+    pub next_entity_id: &'a mut u32,
+    pub focus: &'a mut Option<(Vector2, Vector2)>,
+    pub level_complete: &'a mut bool,
+    /// Randomness for the entities, from the game's seed.
+    pub rng: &'a mut StdRng,
+    /// Effects on the world, applied once all the entities are updated.
+    pub events: &'a mut Vec<WorldEvent>,
+    grid: &'a SpatialGrid,
+}
+
+impl<'a> WorldView<'a> {
+    /// Find the entities near a position, as of the last `rebuild_grid()`.
+    ///
+    /// This includes the entity asking, if it is within the distance.
+    pub fn neighbors(&self, pos: Vector2, radius: f64) -> Vec<EntityId> {
+        self.grid.query_radius(pos, radius).into_iter()
+            .map(|id| EntityId(id as u32))
+            .collect()
+    }
+
+    /// Queue an event, applied once all the entities are updated.
+    pub fn emit(&mut self, event: WorldEvent) {
+        self.events.push(event);
+    }
+
+    /// Damage per second suffered by an entity at a position.
+    pub fn damage_at(&self, pos: Vector2) -> f64 {
+        self.map.damage_at(pos.x(), pos.y())
+    }
+
+    /// The platforms provided by the other entities.
+    pub fn platforms(&self) -> Vec<PlatformBounds> {
+        self.entities.iter().filter_map(|entity| {
+            entity.logic.platform_size().map(|size| {
+                let pos = entity.physics.pos;
+                PlatformBounds {
+                    min: [pos.x() - size.x() / 2.0, pos.y()],
+                    max: [pos.x() + size.x() / 2.0, pos.y() + size.y()],
+                    velocity: entity.physics.speed,
+                }
+            })
+        }).collect()
+    }
+
+    /// Add an entity to the world, giving it a new identifier.
+    pub fn add_entity(&mut self, mut entity: Entity) -> EntityId {
+        entity.id = EntityId(*self.next_entity_id);
+        *self.next_entity_id += 1;
+        let id = entity.id;
+        self.entities.push(entity);
+        id
+    }
+
+    /// Add an entity to the world once all the entities are updated, with a
+    /// `WorldEvent::Spawn`.
+    ///
+    /// Unlike `add_entity()`, the entity doesn't show up in the current pass.
+    pub fn spawn_entity(&mut self, entity: Entity) {
+        self.emit(WorldEvent::Spawn(entity));
+    }
+
+    /// Find the first thing along a ray.
+    ///
+    /// Colliding tiles are always considered; entities only if `entities`
+    /// is set, by their hitbox. The entity currently being updated is never
+    /// hit.
+    pub fn raycast(&self, origin: Vector2, dir: Vector2, max_dist: f64,
+                   entities: bool) -> Option<Hit> {
+        let len = vec2_len(dir);
+        if len == 0.0 {
+            return None;
+        }
+        let mut hit = self.map.raycast(origin, dir, max_dist);
+        if entities {
+            let dir = vec2_scale(dir, 1.0 / len);
+            for entity in self.entities.iter() {
+                let hitbox = match entity.logic.hitbox(entity.physics.pos) {
+                    Some(hitbox) => hitbox,
+                    None => continue,
+                };
+                if let Some(dist) = ray_box(origin, dir, hitbox.min, hitbox.max) {
+                    if dist <= max_dist && hit.map_or(true, |h| dist < h.dist) {
+                        hit = Some(Hit {
+                            pos: vec2_add(origin, vec2_scale(dir, dist)),
+                            dist: dist,
+                            target: HitTarget::Entity(entity.id),
+                        });
+                    }
+                }
+            }
+        }
+        hit
+    }
+
+    /// Signal that the goal of the level was reached.
+    pub fn complete_level(&mut self) {
+        *self.level_complete = true;
+    }
+
+    pub fn focus(&mut self, pos: &Vector2) {
+        *self.focus = Some(self.focus.map(|old| {
+            ([old.0.x().min(pos.x()), old.0.y().min(pos.y())],
+             [old.1.x().max(pos.x()), old.1.y().max(pos.y())])
+        }).unwrap_or_else(|| { (pos.clone(), pos.clone()) }));
+    }
+}
+
+struct EntityDefinition {
+    type_id: String,
+    position: Vector2,
+}
+
+/// Creates an entity at a position, given the game's seed.
+pub type EntityFactory = Box<Fn(&Vector2, u32) -> Option<Entity>>;
+
+/// Creates an entity from its position and state in a saved game.
+pub type EntityRestorer = Box<Fn(&Vector2, &serde_json::Value) -> Option<Entity>>;
+
+/// The entity types that can be found in map files, by `type_id`.
+pub struct EntityRegistry {
+    factories: HashMap<String, EntityFactory>,
+    restorers: HashMap<String, EntityRestorer>,
+}
+
+impl EntityRegistry {
+    pub fn new() -> EntityRegistry {
+        EntityRegistry {
+            factories: HashMap::new(),
+            restorers: HashMap::new(),
+        }
+    }
+
+    /// Add an entity type, replacing the one with the same `type_id`.
+    pub fn register<F>(&mut self, type_id: &str, factory: F)
+        where F: Fn(&Vector2, u32) -> Option<Entity> + 'static
+    {
+        self.factories.insert(type_id.to_owned(), Box::new(factory));
+    }
+
+    /// Set how an entity type is restored from a saved game, for entities
+    /// whose state isn't the one they are created with.
+    pub fn register_restore<F>(&mut self, type_id: &str, restorer: F)
+        where F: Fn(&Vector2, &serde_json::Value) -> Option<Entity> + 'static
+    {
+        self.restorers.insert(type_id.to_owned(), Box::new(restorer));
+    }
+
+    /// Restore an entity from a saved game, creating it anew if its type
+    /// has no restore function.
+    pub fn restore(&self, type_id: &str, pos: &Vector2, state: &serde_json::Value,
+                   seed: u32) -> Option<Entity> {
+        match self.restorers.get(type_id) {
+            Some(restorer) => restorer(pos, state),
+            None => self.create(type_id, pos, seed),
+        }
+    }
+
+    /// Create an entity from its `type_id`.
+    pub fn create(&self, type_id: &str, pos: &Vector2, seed: u32) -> Option<Entity> {
+        match self.factories.get(type_id) {
+            Some(factory) => factory(pos, seed),
+            None => {
+                warn!("Can't create unknown entity type {}", type_id);
+                None
+            }
+        }
+    }
+}
+
+/// An entity in a saved game.
+#[derive(Serialize, Deserialize)]
+pub struct SavedEntity {
+    id: u32,
+    pub type_id: String,
+    pos: Vector2,
+    speed: Vector2,
+    health: f64,
+    pub state: serde_json::Value,
+}
+
+/// An entity in a debug dump.
+#[derive(Serialize)]
+struct DebugEntity {
+    id: u32,
+    type_id: Option<String>,
+    /// The `Debug` output of the logic.
+    logic: String,
+    /// The tile it is attached to, for tile entities.
+    tile: Option<(usize, usize)>,
+    pos: Vector2,
+    speed: Vector2,
+    health: f64,
+    state: serde_json::Value,
+}
+
+/// The state of a world, see `World::debug_dump()`.
+#[derive(Serialize)]
+struct DebugDump {
+    width: usize,
+    height: usize,
+    clock: f64,
+    entities: Vec<DebugEntity>,
+    tile_entities: Vec<DebugEntity>,
+}
+
+impl SavedEntity {
+    /// Create the entity types that can be found in map files.
+    pub fn create(&self, registry: &EntityRegistry, seed: u32) -> Option<Entity> {
+        registry.restore(&self.type_id, &self.pos, &self.state, seed)
+    }
+}
+
+impl EntityDefinition {
+    fn create(&self, registry: &EntityRegistry, seed: u32) -> Option<Entity> {
+        registry.create(&self.type_id, &self.position, seed)
+    }
+}
+
+/// A background layer of a map definition.
+struct BackgroundDefinition {
+    sprite_sheet: String,
+    /// How much the layer follows the camera, see `parallax_offset()`.
+    parallax: f64,
+    /// Automatic scrolling speed.
+    scroll: Vector2,
+}
+
+#[derive(Default)]
+pub struct TileTypeDefinition {
+    /// Image file, if the tile is visible.
+    pub sprite_sheet: Option<String>,
+    /// Coordinates of sprite within image file.
+    pub sprite_coords: [f64; 4],
+    /// Damage suffered from touching that tile.
+    pub damage: f64,
+    /// How much weaker the damage is at the edge of the tile than at its
+    /// center, from 0 (uniform) to 1 (no damage at the edge).
+    pub damage_falloff: f64,
+    /// Whether entities will collide with that tile, or pass through.
+    pub collide: bool,
+    /// Velocity given to entities standing on that tile (conveyor belts).
+    pub surface_velocity: Vector2,
+    /// Offset of the sprite from the center of the cell, in tiles.
+    pub render_offset: Vector2,
+    /// Shape of the ground, if the tile collides.
+    pub shape: TileShape,
+    /// Whether the tile only collides with what lands on it from above.
+    pub one_way: bool,
+    /// Whether characters can climb that tile (ladders).
+    pub climbable: bool,
+    /// Whether entities get notified when they step on that tile.
+    pub notify_enter: bool,
+    /// What happens when a character enters the tile.
+    pub trigger: Option<Trigger>,
+    /// For doors, the tile type they switch to when open.
+    pub open_tile: Option<Tile>,
+    /// Factory function (creates entity).
+    tile_entity: Option<Box<TileEntityFactory>>,
+}
+
+/// Error loading a map from a file.
+#[derive(Debug)]
+pub enum MapLoadError {
+    Io(io::Error),
+    Xml(xml::reader::Error),
+    Json(serde_json::Error),
+    /// The file was read but its content is not a valid map.
+    Invalid(String),
+    /// A sprite sheet used by the map couldn't be loaded.
+    Sprite(SpriteError),
+}
+
+impl fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapLoadError::Io(ref e) => write!(f, "I/O error: {}", e),
+            MapLoadError::Xml(ref e) => write!(f, "XML error: {}", e),
+            MapLoadError::Json(ref e) => write!(f, "JSON error: {}", e),
+            MapLoadError::Invalid(ref msg) => write!(f, "Invalid map: {}", msg),
+            MapLoadError::Sprite(ref e) => write!(f, "Sprite error: {}", e),
+        }
+    }
+}
+
+impl Error for MapLoadError {
+    fn description(&self) -> &str {
+        match *self {
+            MapLoadError::Io(_) => "I/O error",
+            MapLoadError::Xml(_) => "XML error",
+            MapLoadError::Json(_) => "JSON error",
+            MapLoadError::Invalid(_) => "Invalid map",
+            MapLoadError::Sprite(_) => "Sprite error",
+        }
+    }
+}
+
+impl From<io::Error> for MapLoadError {
+    fn from(e: io::Error) -> MapLoadError {
+        MapLoadError::Io(e)
+    }
+}
+
+impl From<xml::reader::Error> for MapLoadError {
+    fn from(e: xml::reader::Error) -> MapLoadError {
+        MapLoadError::Xml(e)
+    }
+}
+
+impl From<serde_json::Error> for MapLoadError {
+    fn from(e: serde_json::Error) -> MapLoadError {
+        MapLoadError::Json(e)
+    }
+}
+
+impl From<SpriteError> for MapLoadError {
+    fn from(e: SpriteError) -> MapLoadError {
+        MapLoadError::Sprite(e)
+    }
+}
+
+/// Initial map definition, loaded from disk.
+///
+/// This can be turned into a live Map using `create()`.
+pub struct MapFactory {
+    pub width: usize,
+    pub height: usize,
+    pub nb_players: usize,
+    pub physics: Physics,
+    pub camera: CameraConfig,
+    /// Size of a tile in world units, see `Map::tile_size`.
+    pub tile_size: f64,
+    tiletypes: Vec<TileTypeDefinition>,
+    /// The layers of tiles, exactly one of them being the main layer.
+    layers: Vec<TileLayer>,
+    entities: Vec<EntityDefinition>,
+    /// Background layers, from back to front.
+    backgrounds: Vec<BackgroundDefinition>,
+    /// Music looped during the level, see `SoundManager::play_music()`.
+    pub music: Option<String>,
+}
+
+impl MapFactory {
+    /// Load a map file, in JSON format if it has the `.json` extension,
+    /// else in the TMX format of the Tiled editor.
+    pub fn from_file(filename: &Path) -> Result<MapFactory, MapLoadError> {
+        let file = try!(File::open(filename));
+        if filename.extension().map_or(false, |ext| ext == "json") {
+            MapFactory::from_json(BufReader::new(file))
+        } else {
+            tmx::load(BufReader::new(file), filename.parent().unwrap_or(Path::new("")))
+        }
+    }
+
+    /// Load a map in JSON format.
+    pub fn from_json<R: Read>(reader: R) -> Result<MapFactory, MapLoadError> {
+        let map = try!(json::read(reader));
+        try!(map.validate());
+        Ok(map)
+    }
+
+    /// Write the map in JSON format.
+    ///
+    /// Factories for tile entities are not written.
+    pub fn to_json<W: Write>(&self, writer: W) -> Result<(), serde_json::Error> {
+        json::write(self, writer)
+    }
+
+    /// Check that the tiles are consistent with the size and tile types.
+    pub fn validate(&self) -> Result<(), MapLoadError> {
+        for layer in self.layers.iter() {
+            if layer.tiles.len() != self.width * self.height {
+                return Err(MapLoadError::Invalid(format!(
+                    "Map has {} tiles, expected {}x{}",
+                    layer.tiles.len(), self.width, self.height)));
+            }
+        }
+        let main_layers = self.layers.iter()
+            .filter(|l| l.render_order == RenderOrder::Main)
+            .count();
+        if main_layers != 1 {
+            return Err(MapLoadError::Invalid(format!(
+                "Map has {} main layers, expected 1", main_layers)));
+        }
+        if !(self.tile_size > 0.0) {
+            return Err(MapLoadError::Invalid(format!(
+                "Invalid tile size {}", self.tile_size)));
+        }
+        if let Some((x, y, tile)) = self.invalid_tile() {
+            return Err(MapLoadError::Invalid(format!(
+                "Tile {} at ({}, {}) is out of range, there are {} tile types",
+                tile, x, y, self.tiletypes.len())));
+        }
+        for (i, tiletype) in self.tiletypes.iter().enumerate() {
+            if let Some(open) = tiletype.open_tile {
+                if open as usize >= self.tiletypes.len() {
+                    return Err(MapLoadError::Invalid(format!(
+                        "Tile type {} opens to tile type {}, there are {} tile types",
+                        i, open, self.tiletypes.len())));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The layer things collide with, see `validate()`.
+    fn main_layer(&self) -> &TileLayer {
+        self.layers.iter().find(|l| l.render_order == RenderOrder::Main)
+            .expect("Map has no main layer")
+    }
+
+    /// Find the first tile using a tile type that doesn't exist, in any layer.
+    pub fn invalid_tile(&self) -> Option<(usize, usize, Tile)> {
+        for layer in self.layers.iter() {
+            let tiles = &layer.tiles;
+            if let Some(pos) = tiles.iter().position(|&t| t as usize >= self.tiletypes.len()) {
+                return Some((pos % self.width, pos / self.width, tiles[pos]));
+            }
+        }
+        None
+    }
+
+    /// Create the hardcoded example map.
+    pub fn example() -> MapFactory {
+        let mut builder = MapFactoryBuilder::new();
+        builder.dimensions(100, 100).nb_players(4);
+        let wall = builder.tile_type(TileTypeDefinition {
+            sprite_sheet: Some("map/castleCenter.png".to_owned()),
+            sprite_coords: [0., 0., 256., 256.],
+            collide: true,
+            ..TileTypeDefinition::default()
+        });
+        let background = builder.tile_type(TileTypeDefinition {
+            sprite_sheet: Some("map/bg_castle.png".to_owned()),
+            sprite_coords: [0., 0., 256., 256.],
+            ..TileTypeDefinition::default()
+        });
+        let sky = builder.tile_type(TileTypeDefinition {
+            sprite_sheet: Some("map/bg.png".to_owned()),
+            sprite_coords: [0., 0., 256., 256.],
+            ..TileTypeDefinition::default()
+        });
+        let lava = builder.tile_type(TileTypeDefinition {
+            sprite_sheet: Some("map/liquidLava.png".to_owned()),
+            sprite_coords: [0., 0., 256., 256.],
+            damage: 1.0,
+            damage_falloff: 0.5,
+            ..TileTypeDefinition::default()
+        });
+
+        builder.fill(background)
+            // Different background for top part
+            .rect(0, 70, 100, 30, sky)
+            // Walls all around
+            .rect(0, 0, 100, 1, wall)
+            .rect(0, 99, 100, 1, wall)
+            .rect(0, 0, 1, 100, wall)
+            .rect(99, 0, 1, 100, wall)
+            // Lava at the bottom
+            .rect(40, 0, 20, 1, lava);
+        for x in 0..19 {
+            builder.set_tile(2 + 5 * x, 1, wall);
+        }
+        for &x in [15.0, 25.0, 75.0, 85.0].iter() {
+            builder.entity("f.spawn", [x, 1.0]);
+        }
+        builder.build().expect("Example map is invalid")
+    }
+
+    /// The sprite sheets used by the tiles and backgrounds, each once.
+    ///
+    /// These are loaded by `create()`, this lets them be loaded beforehand.
+    pub fn sprite_sheets(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        let tiles = self.tiletypes.iter().filter_map(|td| td.sprite_sheet.as_ref());
+        let backgrounds = self.backgrounds.iter().map(|bd| &bd.sprite_sheet);
+        for name in tiles.chain(backgrounds) {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// Create a live `Map` from this map definition.
+    pub fn create(&self, resources: &mut Resources, registry: &EntityRegistry,
+                  seed: u32) -> Result<World, MapLoadError> {
+        self.create_with(|name| resources.load_spritesheet(name), registry, seed)
+    }
+
+    /// Create a live `Map`, loading the sprite sheets with the given function.
+    ///
+    /// The same definition can be used again, to start the level over.
+    pub fn create_with<F>(&self, mut load_spritesheet: F, registry: &EntityRegistry,
+                          seed: u32) -> Result<World, MapLoadError>
+        where F: FnMut(&str) -> Result<Rc<SpriteSheet>, SpriteError>
+    {
+        let mut tiletypes: Vec<TileType> = Vec::with_capacity(self.tiletypes.len());
+        for td in self.tiletypes.iter() {
+            let sprite = match td.sprite_sheet {
+                Some(ref sheet) => Some(Sprite {
+                    sheet: try!(load_spritesheet(sheet)),
+                    coords: td.sprite_coords,
+                    size: [1.0, 1.0],
+                    flip_h: false,
+                    flip_v: false,
+                }),
+                None => None,
+            };
+            tiletypes.push(TileType {
+                sprite: sprite,
+                damage: td.damage,
+                damage_falloff: td.damage_falloff,
+                collide: td.collide,
+                surface_velocity: td.surface_velocity,
+                render_offset: td.render_offset,
+                shape: td.shape,
+                one_way: td.one_way,
+                climbable: td.climbable,
+                notify_enter: td.notify_enter,
+                trigger: td.trigger.clone(),
+                open_tile: td.open_tile,
+                tile_entity: td.tile_entity.is_some(),
+            });
+        }
+
+        let mut tile_entities = HashMap::new();
+        let mut next_entity_id = 1;
+
+        let mut map = Map::new(self.width, self.height, tiletypes, self.layers.clone());
+        map.tile_size = self.tile_size;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let tile = map.tiles()[y * self.width + x];
+                let tiletype = &self.tiletypes[tile as usize];
+                if let Some(ref factory) = tiletype.tile_entity {
+                    if let Some(mut entity) = factory(tile, &map.tiletypes[tile as usize],
+                                                      (x, y)) {
+                        // Tile entities are placed in tiles, not world units
+                        let pos = vec2_scale(entity.physics.pos, self.tile_size);
+                        entity.physics.pos = pos;
+                        entity.render_pos = pos;
+                        entity.prev_render_pos = pos;
+                        entity.id = EntityId(next_entity_id);
+                        next_entity_id += 1;
+                        tile_entities.insert((x, y), entity);
+                    }
+                }
+            }
+        }
+
+        let mut backgrounds = Vec::with_capacity(self.backgrounds.len());
+        for bd in self.backgrounds.iter() {
+            backgrounds.push(BackgroundLayer {
+                sheet: try!(load_spritesheet(&bd.sprite_sheet)),
+                parallax: bd.parallax,
+                scroll: Scroll::new(bd.scroll),
+            });
+        }
+
+        let mut world = World {
+            physics: self.physics,
+            map: map,
+            backgrounds: backgrounds,
+            entities: Vec::new(),
+            tile_entities: tile_entities,
+            spawnables: Vec::new(),
+            next_entity_id: next_entity_id,
+            level_complete: false,
+            exclusive: None,
+            clock: 0.0,
+            ambient: None,
+            grid: SpatialGrid::new(GRID_CELL_SIZE),
+            rng: seeded_rng(seed),
+            events: Vec::new(),
+        };
+        for entity in self.entities.iter().filter_map(|e| e.create(registry, seed)) {
+            world.add_entity(entity);
+        }
+        Ok(world)
+    }
+}
+
+/// Build a map from rows of text, top to bottom, '#' being a wall, '/' and
+/// '\\' ramps going up to the right and to the left, '=' a one-way platform,
+/// 'H' a ladder, 'D' a closed door that opens to the empty tile.
+#[cfg(test)]
+pub fn grid_map(rows: &[&str]) -> Map {
+    let tile_type = |collide, shape| TileType {
+        sprite: None,
+        damage: 0.0,
+        damage_falloff: 0.0,
+        collide: collide,
+        surface_velocity: [0.0, 0.0],
+        render_offset: [0.0, 0.0],
+        shape: shape,
+        one_way: false,
+        climbable: false,
+        notify_enter: false,
+        trigger: None,
+        open_tile: None,
+        tile_entity: false,
+    };
+    let mut tiles = Vec::new();
+    for row in rows.iter().rev() {
+        tiles.extend(row.chars().map(|c| match c {
+            '#' => 1,
+            '/' => 2,
+            '\\' => 3,
+            '=' => 4,
+            'H' => 5,
+            'D' => 6,
+            _ => 0,
+        }));
+    }
+    let mut platform = tile_type(true, TileShape::Full);
+    platform.one_way = true;
+    let mut ladder = tile_type(false, TileShape::Full);
+    ladder.climbable = true;
+    let mut door = tile_type(true, TileShape::Full);
+    door.open_tile = Some(0);
+    Map {
+        width: rows[0].len(),
+        height: rows.len(),
+        tile_size: 1.0,
+        tiletypes: vec![tile_type(false, TileShape::Full),
+                        tile_type(true, TileShape::Full),
+                        tile_type(true, TileShape::SlopeUpRight),
+                        tile_type(true, TileShape::SlopeUpLeft),
+                        platform,
+                        ladder,
+                        door],
+        layers: vec![TileLayer::new(RenderOrder::Main, tiles)],
+        main: 0,
+    }
+}
+
+/// Build a map factory from rows of text, top to bottom, '#' being a wall,
+/// digits being tile indices (possibly out of range), 'S' a spawn point.
+#[cfg(test)]
+pub fn grid_map_factory(rows: &[&str]) -> MapFactory {
+    let tile_type = |collide| TileTypeDefinition {
+        sprite_sheet: None,
+        sprite_coords: [0.0, 0.0, 1.0, 1.0],
+        damage: 0.0,
+        damage_falloff: 0.0,
+        collide: collide,
+        surface_velocity: [0.0, 0.0],
+        render_offset: [0.0, 0.0],
+        shape: TileShape::Full,
+        one_way: false,
+        climbable: false,
+        notify_enter: false,
+        trigger: None,
+        open_tile: None,
+        tile_entity: None,
+    };
+    let mut tiles = Vec::new();
+    let mut entities = Vec::new();
+    for (y, row) in rows.iter().rev().enumerate() {
+        for (x, c) in row.chars().enumerate() {
+            if c == 'S' {
+                entities.push(EntityDefinition {
+                    type_id: "f.spawn".to_owned(),
+                    position: [x as f64 + 0.5, y as f64],
+                });
+            }
+        }
+        tiles.extend(row.chars().map(|c| match c {
+            '#' => 1,
+            c => c.to_digit(10).unwrap_or(0) as Tile,
+        }));
+    }
+    MapFactory {
+        width: rows[0].len(),
+        height: rows.len(),
+        nb_players: 1,
+        physics: Physics::default(),
+        camera: CameraConfig::default(),
+        tile_size: 1.0,
+        tiletypes: vec![tile_type(false), tile_type(true)],
+        layers: vec![TileLayer::new(RenderOrder::Main, tiles)],
+        entities: entities,
+        backgrounds: Vec::new(),
+        music: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use serde_json;
+
+    use spatial::SpatialGrid;
+    use sprites::Sprite;
+    use utils::one_rest_split_iter;
+    use vecmath::*;
+    use super::{AmbientCycle, Entity, EntityDefinition, EntityId, EntityLogic, EntityPhysics,
+                EntityRegistry, GRID_CELL_SIZE, Hit, HitTarget, Map, MapFactory, Physics,
+                PlatformBounds, RenderOrder, SavedEntity, Scroll, SimContext, Tile, TileLayer,
+                TileShape, TileType, Trigger, TriggerState, World, WorldEvent, WorldView,
+                grid_map, grid_map_factory, parallax_offset, seeded_rng};
+
+    #[derive(Debug)]
+    struct Dummy;
+
+    impl EntityLogic for Dummy {
+        fn update(&mut self, _entity: &mut EntityPhysics, _dt: f64,
+                  _world: &mut WorldView, _ctx: &SimContext,
+                  _sprite: &mut Option<Sprite>) -> bool {
+            true
+        }
+    }
+
+    /// An entity with a hitbox of the given size, centered on its position.
+    #[derive(Debug)]
+    struct Solid(Vector2);
+
+    impl EntityLogic for Solid {
+        fn update(&mut self, _entity: &mut EntityPhysics, _dt: f64,
+                  _world: &mut WorldView, _ctx: &SimContext,
+                  _sprite: &mut Option<Sprite>) -> bool {
+            true
+        }
+
+        fn hitbox(&self, pos: Vector2) -> Option<Aabb> {
+            Some(Aabb::from_center_size(pos, self.0))
+        }
+    }
+
+    fn entity(id: u32) -> Entity {
+        let mut entity = Entity::new([0.0, 0.0], Dummy);
+        entity.id = EntityId(id);
+        entity
+    }
+
+    #[test]
+    fn test_new_entity() {
+        let entity = Entity::new([1.0, 2.0], Dummy);
+        assert_eq!(entity.id, EntityId::default());
+        assert_eq!(entity.physics.pos, [1.0, 2.0]);
+        assert_eq!(entity.physics.speed, [0.0, 0.0]);
+        assert_eq!(entity.physics.gravity_scale, 1.0);
+        assert!(entity.sprite.is_none());
+        assert!(entity.effect.is_none());
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let mut entity = entity(1);
+        entity.physics.pos = [1.0, 2.0];
+        entity.smooth_render_pos(0.1);
+        assert_eq!(entity.render_pos, [1.0, 2.0]);
+
+        entity.smoothing = Some(2.0);
+        entity.physics.pos = [2.0, 2.0];
+        entity.smooth_render_pos(0.5);
+        let expected = 2.0 - (-1.0f64).exp();
+        assert!((entity.render_pos[0] - expected).abs() < 1e-9);
+        assert_eq!(entity.render_pos[1], 2.0);
+        // Same result in smaller steps
+        entity.render_pos = [1.0, 2.0];
+        for _ in 0..5 {
+            entity.smooth_render_pos(0.1);
+        }
+        assert!((entity.render_pos[0] - expected).abs() < 1e-9);
+        // Gets there eventually
+        entity.smooth_render_pos(10.0);
+        assert!((entity.render_pos[0] - 2.0).abs() < 1e-6);
+
+        // Drawn between the last two updates
+        entity.smoothing = None;
+        entity.physics.pos = [3.0, 2.0];
+        entity.render_pos = [2.0, 2.0];
+        entity.smooth_render_pos(0.1);
+        assert_eq!(entity.prev_render_pos, [2.0, 2.0]);
+        assert_eq!(entity.draw_pos(0.25), [2.25, 2.0]);
+    }
+
+    fn empty_world(entities: Vec<Entity>) -> World {
+        World {
+            physics: Physics::default(),
+            map: Map::new(0, 0, Vec::new(), vec![TileLayer::new(RenderOrder::Main, Vec::new())]),
+            backgrounds: Vec::new(),
+            entities: entities,
+            tile_entities: HashMap::new(),
+            spawnables: Vec::new(),
+            next_entity_id: 10,
+            level_complete: false,
+            exclusive: None,
+            clock: 0.0,
+            ambient: None,
+            grid: SpatialGrid::new(GRID_CELL_SIZE),
+            rng: seeded_rng(0),
+            events: Vec::new(),
+        }
+    }
+
+    fn update_order(world: &mut World) -> Vec<EntityId> {
+        world.sort_entities();
+        let mut order = Vec::new();
+        one_rest_split_iter(&mut world.entities, |e, _| order.push(e.id));
+        order
+    }
+
+    #[test]
+    fn test_update_order() {
+        let mut world1 = empty_world(vec![entity(1), entity(2), entity(3)]);
+        let mut world2 = empty_world(vec![entity(3), entity(1), entity(2)]);
+        assert_eq!(update_order(&mut world1), update_order(&mut world2));
+        assert_eq!(update_order(&mut world1),
+                   vec![EntityId(1), EntityId(2), EntityId(3)]);
+
+        // Removing then adding back in a different order
+        world1.entities.remove(0);
+        world1.entities.insert(2, entity(1));
+        assert_eq!(update_order(&mut world1), update_order(&mut world2));
+
+        // New entities go last
+        let new = world1.add_entity(entity(0));
+        assert_eq!(new, EntityId(10));
+        assert_eq!(update_order(&mut world1).last(), Some(&new));
+    }
+
+    #[test]
+    fn test_parallax() {
+        let camera = [10.0, 4.0];
+        assert_eq!(parallax_offset(camera, 0.0, [0.0, 0.0]), [0.0, 0.0]);
+        assert_eq!(parallax_offset(camera, 1.0, [0.0, 0.0]), [10.0, 4.0]);
+        assert_eq!(parallax_offset(camera, 0.5, [0.0, 0.0]), [5.0, 2.0]);
+        assert_eq!(parallax_offset(camera, 0.5, [1.5, -1.0]), [6.5, 1.0]);
+    }
+
+    #[test]
+    fn test_autoscroll() {
+        let mut scroll = Scroll::new([2.0, -0.5]);
+        scroll.advance(0.5);
+        assert_eq!(scroll.offset, [1.0, -0.25]);
+        scroll.advance(0.25);
+        assert_eq!(scroll.offset, [1.5, -0.375]);
+    }
+
+    #[test]
+    fn test_ambient_cycle() {
+        let mut world = empty_world(Vec::new());
+        assert_eq!(world.ambient_color(), [1.0, 1.0, 1.0, 1.0]);
+        world.ambient = Some(AmbientCycle {
+            length: 10.0,
+            keyframes: vec![
+                (0.0, [0.0, 0.0, 0.5, 1.0]),
+                (0.5, [1.0, 1.0, 1.0, 1.0]),
+            ],
+        });
+        let at = |world: &mut World, clock: f64| {
+            world.clock = clock;
+            world.ambient_color()
+        };
+        assert_eq!(at(&mut world, 0.0), [0.0, 0.0, 0.5, 1.0]);
+        assert_eq!(at(&mut world, 2.5), [0.5, 0.5, 0.75, 1.0]);
+        assert_eq!(at(&mut world, 5.0), [1.0, 1.0, 1.0, 1.0]);
+        // Going back to the first keyframe
+        assert_eq!(at(&mut world, 7.5), [0.5, 0.5, 0.75, 1.0]);
+        assert_eq!(at(&mut world, 12.5), [0.5, 0.5, 0.75, 1.0]);
+    }
+
+    #[test]
+    fn test_phases() {
+        // Whatever the order, each entity sees the other after its move
+        for &(first, second) in [(1, 2), (2, 1)].iter() {
+            let mut world = empty_world(vec![entity(first), entity(second)]);
+            let mut focus = None;
+            world.entities_pass(&mut focus, |e, _| {
+                e.physics.pos[0] += e.id.0 as f64;
+            });
+            let mut seen = Vec::new();
+            world.entities_pass(&mut focus, |e, w| {
+                seen.push((e.id.0, w.entities[0].physics.pos.x()));
+            });
+            seen.sort_by_key(|&(id, _)| id);
+            assert_eq!(seen, vec![(1, 2.0), (2, 1.0)]);
+        }
+    }
+
+    /// Breaks into two fragments when it dies.
+    #[derive(Debug)]
+    struct Barrel;
+
+    impl EntityLogic for Barrel {
+        fn update(&mut self, _entity: &mut EntityPhysics, _dt: f64,
+                  _world: &mut WorldView, _ctx: &SimContext,
+                  _sprite: &mut Option<Sprite>) -> bool {
+            true
+        }
+
+        fn on_death(&mut self, entity: &mut EntityPhysics, world: &mut WorldView) {
+            for &dir in [-1.0, 1.0].iter() {
+                let mut fragment = Entity::new(entity.pos, Dummy);
+                fragment.physics.speed = [dir, 1.0];
+                world.add_entity(fragment);
+            }
+        }
+    }
+
+    #[test]
+    fn test_world_events() {
+        let mut world = empty_world(vec![entity(1), entity(2), Entity::new([3.0, 0.0], Barrel)]);
+        world.entities[2].id = EntityId(3);
+        world.entities_pass(&mut None, |e, w| {
+            if e.id == EntityId(1) {
+                w.emit(WorldEvent::Damage { target: EntityId(2), amount: 0.25 });
+                w.emit(WorldEvent::Damage { target: EntityId(3), amount: 2.0 });
+                w.emit(WorldEvent::PlaySound("hit".to_owned()));
+            }
+        });
+        // Nothing happens until the events are applied
+        assert_eq!(world.entities[1].physics.health, 1.0);
+        assert_eq!(world.entities.len(), 3);
+
+        let mut sounds = Vec::new();
+        world.apply_events(|name| sounds.push(name.to_owned()));
+        assert_eq!(world.entities[1].physics.health, 0.75);
+        // The barrel died, leaving fragments
+        let ids: Vec<_> = world.entities.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![EntityId(1), EntityId(2), EntityId(10), EntityId(11)]);
+        assert_eq!(sounds, vec!["hit".to_owned()]);
+
+        world.entities_pass(&mut None, |e, w| {
+            if e.id == EntityId(2) {
+                w.emit(WorldEvent::Spawn(Entity::new([5.0, 0.0], Dummy)));
+            }
+        });
+        world.apply_events(|_| {});
+        assert_eq!(world.entities.len(), 5);
+        assert_eq!(world.entities[4].id, EntityId(12));
+    }
+
+    #[test]
+    fn test_spawn_entity() {
+        let mut world = empty_world(vec![entity(1), entity(2)]);
+        let mut seen = Vec::new();
+        world.entities_pass(&mut None, |e, w| {
+            seen.push(e.id);
+            if e.id == EntityId(1) {
+                w.spawn_entity(Entity::new([5.0, 0.0], Dummy));
+            }
+        });
+        // Not added during the pass
+        assert_eq!(seen, vec![EntityId(1), EntityId(2)]);
+        assert_eq!(world.entities.len(), 2);
+
+        world.apply_events(|_| {});
+        let ids: Vec<_> = world.entities.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![EntityId(1), EntityId(2), EntityId(10)]);
+        assert_eq!(world.entities[2].physics.pos, [5.0, 0.0]);
+        world.apply_events(|_| {});
+        assert_eq!(world.entities.len(), 3);
+    }
+
+    #[test]
+    fn test_on_death() {
+        let mut barrel = Entity::new([3.0, 4.0], Barrel);
+        barrel.id = EntityId(1);
+        let mut world = empty_world(vec![barrel, entity(2)]);
+        assert!(world.kill_entity(EntityId(1)));
+        assert!(!world.kill_entity(EntityId(1)));
+
+        world.sort_entities();
+        let ids: Vec<_> = world.entities.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![EntityId(2), EntityId(10), EntityId(11)]);
+        assert_eq!(world.entities[1].physics.pos, [3.0, 4.0]);
+        assert_eq!(world.entities[1].physics.speed, [-1.0, 1.0]);
+        assert_eq!(world.entities[2].physics.speed, [1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_despawn() {
+        let mut barrel = Entity::new([3.0, 4.0], Barrel);
+        barrel.id = EntityId(1);
+        let mut world = empty_world(vec![barrel, entity(2)]);
+        let mut frames = 0;
+        for frame in 0..5 {
+            // The barrel asks to be removed on its third update
+            world.entities_retain_pass(&mut None, |e, _| {
+                if e.id == EntityId(1) {
+                    frames += 1;
+                    frame < 2
+                } else {
+                    true
+                }
+            });
+            world.sort_entities();
+            let ids: Vec<_> = world.entities.iter().map(|e| e.id).collect();
+            if frame < 2 {
+                assert_eq!(ids, vec![EntityId(1), EntityId(2)]);
+            } else {
+                assert_eq!(ids, vec![EntityId(2), EntityId(10), EntityId(11)]);
+            }
+        }
+        assert_eq!(frames, 3);
+    }
+
+    #[test]
+    fn test_ttl() {
+        let mut short = entity(1);
+        short.ttl = Some(0.5);
+        let mut world = empty_world(vec![short, entity(2)]);
+        for _ in 0..4 {
+            world.expire_entities(0.1);
+        }
+        assert_eq!(world.entities.len(), 2);
+        for _ in 0..2 {
+            world.expire_entities(0.1);
+        }
+        let ids: Vec<_> = world.entities.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![EntityId(2)]);
+
+        // Paused entities don't age
+        let mut short = entity(1);
+        short.ttl = Some(0.5);
+        let mut world = empty_world(vec![short]);
+        world.pause_all_except(vec![], 10.0);
+        world.expire_entities(1.0);
+        assert_eq!(world.entities[0].ttl, Some(0.5));
+    }
+
+    #[test]
+    fn test_neighbors() {
+        let mut world = empty_world(vec![entity(1), entity(2), entity(3)]);
+        world.entities[1].physics.pos = [1.0, 1.0];
+        world.entities[2].physics.pos = [10.0, 0.0];
+        world.rebuild_grid();
+        let mut found = Vec::new();
+        world.entities_pass(&mut None, |e, w| {
+            found.push((e.id, w.neighbors(e.physics.pos, 2.0)));
+        });
+        assert_eq!(found, vec![
+            (EntityId(1), vec![EntityId(1), EntityId(2)]),
+            (EntityId(2), vec![EntityId(1), EntityId(2)]),
+            (EntityId(3), vec![EntityId(3)]),
+        ]);
+    }
+
+    #[derive(Debug)]
+    struct Platform;
+
+    impl EntityLogic for Platform {
+        fn update(&mut self, _entity: &mut EntityPhysics, _dt: f64,
+                  _world: &mut WorldView, _ctx: &SimContext,
+                  _sprite: &mut Option<Sprite>) -> bool {
+            true
+        }
+
+        fn platform_size(&self) -> Option<Vector2> {
+            Some([2.0, 0.5])
+        }
+    }
+
+    #[test]
+    fn test_platforms() {
+        let mut platform = Entity::new([3.0, 1.0], Platform);
+        platform.id = EntityId(2);
+        platform.physics.speed = [1.0, 0.0];
+        let mut world = empty_world(vec![entity(1), platform]);
+        let mut found = Vec::new();
+        world.entities_pass(&mut None, |e, w| {
+            found.push((e.id, w.platforms()));
+        });
+        assert_eq!(found, vec![
+            (EntityId(1), vec![PlatformBounds {
+                min: [2.0, 1.0],
+                max: [4.0, 1.5],
+                velocity: [1.0, 0.0],
+            }]),
+            // Not its own
+            (EntityId(2), vec![]),
+        ]);
+    }
+
+    #[derive(Debug)]
+    struct Saved(u32);
+
+    impl EntityLogic for Saved {
+        fn update(&mut self, _entity: &mut EntityPhysics, _dt: f64,
+                  _world: &mut WorldView, _ctx: &SimContext,
+                  _sprite: &mut Option<Sprite>) -> bool {
+            true
+        }
+
+        fn type_id(&self) -> Option<&str> {
+            Some("test.saved")
+        }
+
+        fn save_state(&self) -> serde_json::Value {
+            serde_json::Value::from(self.0)
+        }
+    }
+
+    #[test]
+    fn test_save_entities() {
+        let mut saved = Entity::new([2.0, 3.0], Saved(42));
+        saved.id = EntityId(7);
+        saved.physics.speed = [1.0, -2.0];
+        saved.physics.health = 0.5;
+        let world = empty_world(vec![entity(1), saved]);
+        let json = serde_json::to_string(&world.save_entities()).unwrap();
+
+        // Dummy entities aren't saved
+        let mut loaded = empty_world(vec![entity(1), entity(2)]);
+        loaded.next_entity_id = 3;
+        loaded.restore_entities(serde_json::from_str(&json).unwrap(), |s| {
+            assert_eq!(s.type_id, "test.saved");
+            Ok(Some(Entity::new([0.0, 0.0], Saved(s.state.as_u64().unwrap() as u32))))
+        }).unwrap();
+        assert_eq!(loaded.entities.len(), 1);
+        let entity = &loaded.entities[0];
+        assert_eq!(entity.id, EntityId(7));
+        assert_eq!(entity.physics.pos, [2.0, 3.0]);
+        assert_eq!(entity.physics.speed, [1.0, -2.0]);
+        assert_eq!(entity.physics.health, 0.5);
+        assert_eq!(entity.render_pos, [2.0, 3.0]);
+        assert_eq!(entity.logic.save_state(), serde_json::Value::from(42));
+        assert_eq!(loaded.next_entity_id, 8);
+    }
+
+    #[test]
+    fn test_debug_dump() {
+        let mut saved = Entity::new([2.0, 3.0], Saved(42));
+        saved.id = EntityId(7);
+        let mut world = empty_world(vec![entity(1), saved]);
+        world.tile_entities.insert((4, 1), entity(3));
+        let mut dump = Vec::new();
+        world.debug_dump(&mut dump).unwrap();
+
+        let dump: serde_json::Value = serde_json::from_slice(&dump).unwrap();
+        let entities = dump["entities"].as_array().unwrap();
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[1]["id"], 7);
+        assert_eq!(entities[1]["type_id"], "test.saved");
+        assert_eq!(entities[1]["pos"], serde_json::to_value([2.0, 3.0]).unwrap());
+        assert_eq!(entities[1]["state"], 42);
+        assert!(entities[0]["type_id"].is_null());
+        let tile_entities = dump["tile_entities"].as_array().unwrap();
+        assert_eq!(tile_entities.len(), 1);
+        assert_eq!(tile_entities[0]["tile"], serde_json::to_value((4, 1)).unwrap());
+    }
+
+    #[test]
+    fn test_entity_registry() {
+        let mut registry = EntityRegistry::new();
+        registry.register("test.saved", |pos, seed| Some(Entity::new(*pos, Saved(seed))));
+        let definition = EntityDefinition {
+            type_id: "test.saved".to_owned(),
+            position: [4.0, 2.0],
+        };
+        let entity = definition.create(&registry, 12).unwrap();
+        assert_eq!(entity.physics.pos, [4.0, 2.0]);
+        assert_eq!(entity.logic.save_state(), serde_json::Value::from(12));
+
+        let unknown = EntityDefinition {
+            type_id: "test.unknown".to_owned(),
+            position: [0.0, 0.0],
+        };
+        assert!(unknown.create(&registry, 12).is_none());
+
+        // Saved entities are created anew, unless their type can be restored
+        let saved = SavedEntity {
+            id: 3,
+            type_id: "test.saved".to_owned(),
+            pos: [1.0, 5.0],
+            speed: [0.0, 0.0],
+            health: 1.0,
+            state: serde_json::Value::from(42),
+        };
+        let entity = saved.create(&registry, 12).unwrap();
+        assert_eq!(entity.logic.save_state(), serde_json::Value::from(12));
+        registry.register_restore("test.saved", |pos, state| {
+            state.as_u64().map(|n| Entity::new(*pos, Saved(n as u32)))
+        });
+        let entity = saved.create(&registry, 12).unwrap();
+        assert_eq!(entity.physics.pos, [1.0, 5.0]);
+        assert_eq!(entity.logic.save_state(), serde_json::Value::from(42));
+    }
+
+    #[test]
+    fn test_create_again() {
+        let map_factory = grid_map_factory(&[
+            "S...S",
+            "#####",
+        ]);
+        let registry = ::entities::entity_registry();
+        let create = || {
+            map_factory.create_with(|name| panic!("Loaded {}", name), &registry, 1).unwrap()
+        };
+        let mut world = create();
+        assert_eq!(world.entities.len(), 2);
+        assert_eq!(world.entities[1].physics.pos, [4.5, 1.0]);
+        world.entities.pop();
+        world.add_entity(entity(0));
+        world.add_entity(entity(0));
+
+        // The definition is unchanged, the level starts over
+        let world = create();
+        assert_eq!(world.entities.len(), 2);
+        assert_eq!(world.next_entity_id, 3);
+    }
+
+    #[test]
+    fn test_sprite_sheets() {
+        assert_eq!(MapFactory::example().sprite_sheets(),
+                   vec!["map/castleCenter.png", "map/bg_castle.png", "map/bg.png",
+                        "map/liquidLava.png"]);
+        // Walls and platforms share a sheet
+        assert_eq!(MapFactory::generate(12, 8, 1, 0).sprite_sheets().len(), 3);
+        assert!(grid_map_factory(&["S."]).sprite_sheets().is_empty());
+    }
+
+    #[test]
+    fn test_pause_all_except() {
+        let mut world = empty_world(vec![entity(1), entity(2), entity(3)]);
+        world.pause_all_except(vec![EntityId(2)], 1.0);
+        let mut updated = Vec::new();
+        world.entities_pass(&mut None, |e, w| updated.push((e.id, w.entities.len())));
+        assert_eq!(updated, vec![(EntityId(2), 2)]);
+        assert!(world.is_paused(EntityId(1)));
+        assert!(!world.is_paused(EntityId(2)));
+
+        world.advance_pause(0.6);
+        assert!(world.is_paused(EntityId(1)));
+        world.advance_pause(0.6);
+        assert!(!world.is_paused(EntityId(1)));
+        updated.clear();
+        world.entities_pass(&mut None, |e, _| updated.push((e.id, 0)));
+        assert_eq!(updated.len(), 3);
+    }
+
+    fn hazard_map() -> Map {
+        Map {
+            width: 1,
+            height: 1,
+            tile_size: 1.0,
+            tiletypes: vec![
+                TileType {
+                    sprite: None,
+                    damage: 2.0,
+                    damage_falloff: 0.5,
+                    collide: false,
+                    surface_velocity: [0.0, 0.0],
+                    render_offset: [0.0, 0.0],
+                    shape: TileShape::Full,
+                    one_way: false,
+                    climbable: false,
+                    notify_enter: false,
+                    trigger: None,
+                    open_tile: None,
+                    tile_entity: false,
+                },
+            ],
+            layers: vec![TileLayer::new(RenderOrder::Main, vec![0])],
+            main: 0,
+        }
+    }
+
+    #[test]
+    fn test_visible_tiles() {
+        let map = grid_map(&[".........."; 10]);
+
+        // At the corner, only part of the view is on the map
+        let tiles: Vec<_> = map.visible_tiles(0, [-0.5, 0.5], [2.5, 2.5])
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert_eq!(map.visible_rect([-0.5, 0.5], [2.5, 2.5]), (0, 0, 3, 4));
+        assert_eq!(tiles.len(), 12);
+        assert_eq!(tiles[0], (0, 0));
+        assert_eq!(tiles[3], (0, 1));
+        assert_eq!(tiles[11], (2, 3));
+        assert!(map.visible_tiles(0, [-20.0, 0.0], [2.5, 2.5]).next().is_none());
+
+        // Inside, with a margin of one tile
+        let tiles: Vec<_> = map.visible_tiles(0, [4.5, 4.5], [2.0, 1.0])
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert_eq!(map.visible_rect([4.5, 4.5], [2.0, 1.0]), (1, 2, 7, 6));
+        assert_eq!(tiles.len(), 24);
+        assert_eq!(tiles[0], (1, 2));
+        assert_eq!(tiles[23], (6, 5));
+    }
+
+    #[test]
+    fn test_area_clear() {
+        let map = grid_map(&[
+            "....",
+            ".#..",
+            "....",
+        ]);
+        assert!(map.area_clear([0.0, 0.0], [1.0, 3.0]));
+        assert!(map.area_clear([2.0, 0.5], [3.9, 2.5]));
+        assert!(!map.area_clear([0.5, 0.5], [1.5, 1.5]));
+        assert!(!map.area_clear([3.5, 0.0], [4.5, 1.0]));
+    }
+
+    #[test]
+    fn test_nearest_clear() {
+        let map = grid_map(&[
+            ".....",
+            "..#..",
+            "..#..",
+            "..#..",
+            ".....",
+        ]);
+        let size = [0.6, 1.2];
+        // Already clear
+        assert_eq!(map.nearest_clear([0.5, 1.0], size, 2), Some([0.5, 1.0]));
+        // Inside the wall
+        let pos = map.nearest_clear([2.5, 2.0], size, 2).unwrap();
+        assert!(map.area_clear([pos[0] - 0.3, pos[1]], [pos[0] + 0.3, pos[1] + 1.2]));
+        assert!((pos[0] - 2.5).abs() + (pos[1] - 2.0).abs() == 1.0);
+        // Nowhere to go
+        assert_eq!(map.nearest_clear([2.5, 2.0], [5.0, 1.0], 1), None);
+    }
+
+    #[test]
+    fn test_raycast() {
+        let map = grid_map(&[
+            "......",
+            "....#.",
+            "......",
+        ]);
+        assert_eq!(map.raycast([0.5, 1.5], [2.0, 0.0], 10.0),
+                   Some(Hit { pos: [4.0, 1.5], dist: 3.5, target: HitTarget::Tile(4, 1) }));
+        assert_eq!(map.raycast([4.5, 0.5], [0.0, 1.0], 10.0),
+                   Some(Hit { pos: [4.5, 1.0], dist: 0.5, target: HitTarget::Tile(4, 1) }));
+        // Too short
+        assert_eq!(map.raycast([0.5, 1.5], [1.0, 0.0], 3.0), None);
+        assert_eq!(map.cast([0.5, 1.5], [3.5, 1.5]), None);
+        // Through a thin wall
+        assert_eq!(map.cast([0.5, 1.5], [5.5, 1.5]), Some([4.0, 1.5]));
+        assert_eq!(map.cast([5.5, 1.5], [0.5, 1.5]), Some([5.0, 1.5]));
+        // Leaves the map
+        assert_eq!(map.raycast([0.5, 1.5], [-1.0, 0.0], 10.0), None);
+        assert_eq!(map.raycast([0.5, 0.5], [1.0, 0.0], 10.0), None);
+
+        // Entities in the way
+        let mut shooter = entity(1);
+        shooter.physics.pos = [0.5, 1.0];
+        let mut target = Entity::new([2.5, 1.6], Solid([0.6, 1.2]));
+        target.id = EntityId(2);
+        let mut world = empty_world(vec![shooter, target]);
+        world.map = map;
+        let mut hits = Vec::new();
+        world.entities_pass(&mut None, |e, w| {
+            if e.id == EntityId(1) {
+                hits.push(w.raycast([0.5, 1.5], [1.0, 0.0], 10.0, false));
+                hits.push(w.raycast([0.5, 1.5], [1.0, 0.0], 10.0, true));
+                hits.push(w.raycast([0.5, 2.5], [1.0, 0.0], 10.0, true));
+            }
+        });
+        assert_eq!(hits[0].map(|h| h.target), Some(HitTarget::Tile(4, 1)));
+        let hit = hits[1].unwrap();
+        assert_eq!(hit.target, HitTarget::Entity(EntityId(2)));
+        assert!((hit.dist - 1.7).abs() < 1e-9);
+        assert_eq!(hits[2], None);
+    }
+
+    #[test]
+    fn test_render_offset() {
+        let mut map = hazard_map();
+        assert_eq!(map.tiletypes[0].render_center(3, 2), [3.5, 2.5]);
+        map.tiletypes[0].render_offset = [0.25, -0.5];
+        assert_eq!(map.tiletypes[0].render_center(3, 2), [3.75, 2.0]);
+        assert!(!map.tiletypes[0].collide);
+    }
+
+    /// Counts the times it stepped on a notifying tile.
+    #[derive(Debug)]
+    struct Walker {
+        /// The notifying tile it expects.
+        tile: Tile,
+        entered: Rc<Cell<u32>>,
+    }
+
+    impl EntityLogic for Walker {
+        fn update(&mut self, _entity: &mut EntityPhysics, _dt: f64,
+                  _world: &mut WorldView, _ctx: &SimContext,
+                  _sprite: &mut Option<Sprite>) -> bool {
+            true
+        }
+
+        fn on_tile_enter(&mut self, _entity: &mut EntityPhysics, tile: Tile,
+                         tile_type: &TileType) {
+            assert_eq!(tile, self.tile);
+            assert!(tile_type.notify_enter);
+            self.entered.set(self.entered.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_tile_entities_pass() {
+        let mut world = empty_world(vec![entity(1)]);
+        world.tile_entities.insert((2, 0), entity(3));
+        world.tile_entities.insert((1, 4), entity(4));
+        world.tile_entities.insert((1, 0), entity(5));
+        let mut updated = Vec::new();
+        world.tile_entities_pass(&mut None, |e, w| {
+            updated.push((e.id, w.entities.len()));
+            e.id != EntityId(4)
+        });
+        assert_eq!(updated, vec![(EntityId(5), 1), (EntityId(4), 1), (EntityId(3), 1)]);
+        assert_eq!(world.tile_entities.len(), 2);
+        assert!(!world.tile_entities.contains_key(&(1, 4)));
+    }
+
+    #[test]
+    fn test_set_tile() {
+        let mut map = grid_map(&[
+            "..",
+            "#.",
+        ]);
+        assert!(map.set_tile(0, 0, 0));
+        assert!(map.set_tile(1, 1, 1));
+        assert!(!map.tile(0, 0).unwrap().collide);
+        assert!(map.tile(1, 1).unwrap().collide);
+        assert!(!map.set_tile(2, 0, 0));
+        let missing = map.tiletypes.len() as Tile;
+        assert!(!map.set_tile(0, 0, missing));
+    }
+
+    #[test]
+    fn test_slope_height() {
+        for &x in [0.0, 0.25, 0.5, 0.9, 1.0].iter() {
+            assert_eq!(TileShape::Full.height_at(x), 1.0);
+            assert_eq!(TileShape::Empty.height_at(x), 0.0);
+            assert_eq!(TileShape::SlopeUpRight.height_at(x), x);
+            assert_eq!(TileShape::SlopeUpLeft.height_at(x), 1.0 - x);
+        }
+        // Clamped to the tile
+        assert_eq!(TileShape::SlopeUpRight.height_at(-0.5), 0.0);
+        assert_eq!(TileShape::SlopeUpRight.height_at(1.5), 1.0);
+        assert_eq!(TileShape::SlopeUpLeft.height_at(1.5), 0.0);
+    }
+
+    #[test]
+    fn test_ground_at() {
+        let map = grid_map(&[
+            "..../\\..",
+            "########",
+        ]);
+        assert_eq!(map.ground_at(0.5, 1.5).map(|(h, _)| h), None);
+        assert_eq!(map.ground_at(0.5, 0.9).map(|(h, _)| h), Some(1.0));
+        // Inside the slope, or just under it
+        assert_eq!(map.ground_at(4.25, 1.1).map(|(h, _)| h), Some(1.25));
+        assert_eq!(map.ground_at(4.25, 0.9).map(|(h, _)| h), Some(1.25));
+        assert_eq!(map.ground_at(5.75, 0.9).map(|(h, _)| h), Some(1.25));
+        let (_, tile) = map.ground_at(4.5, 0.9).unwrap();
+        assert_eq!(tile.shape, TileShape::SlopeUpRight);
+        // Slopes don't stop rays
+        assert_eq!(map.cast([0.5, 1.5], [7.5, 1.5]), None);
+    }
+
+    #[test]
+    fn test_ground_below() {
+        let map = grid_map(&[
+            "...",
+            "#..",
+            "...",
+            "..=",
+            "#/#",
+        ]);
+        assert_eq!(map.ground_below([0.5, 4.5], 10.0), Some(4.0));
+        assert_eq!(map.ground_below([0.5, 1.0], 10.0), Some(1.0));
+        // Across the gap
+        assert_eq!(map.ground_below([0.5, 2.5], 10.0), Some(1.0));
+        assert_eq!(map.ground_below([0.5, 2.5], 1.5), Some(1.0));
+        assert_eq!(map.ground_below([0.5, 2.5], 1.0), None);
+        // Slopes and platforms
+        assert_eq!(map.ground_below([1.25, 3.5], 10.0), Some(0.25));
+        assert_eq!(map.ground_below([2.5, 3.0], 10.0), Some(2.0));
+        // Outside of the map
+        assert_eq!(map.ground_below([-0.5, 2.5], 10.0), None);
+    }
+
+    #[test]
+    fn test_platform_crossed() {
+        let map = grid_map(&[
+            "...",
+            "=#.",
+            "...",
+            "=..",
+        ]);
+        assert_eq!(map.platform_crossed(0.5, 3.5, 2.5), Some(3.0));
+        assert_eq!(map.platform_crossed(0.5, 3.0, 2.5), Some(3.0));
+        assert_eq!(map.platform_crossed(0.5, 2.9, 0.5), Some(1.0));
+        assert_eq!(map.platform_crossed(0.5, 3.5, 3.1), None);
+        // Only platforms, not walls
+        assert_eq!(map.platform_crossed(1.5, 3.5, 0.5), None);
+        // They don't stop rays
+        assert_eq!(map.cast([0.5, 3.5], [0.5, 0.5]), None);
+    }
+
+    #[test]
+    fn test_trigger_state() {
+        let exit = Trigger::CompleteLevel;
+        let bell = Trigger::PlaySound("bell".to_owned());
+        let mut state = TriggerState::default();
+        assert_eq!(state.update(None), None);
+        // Entering fires once
+        assert_eq!(state.update(Some(&bell)), Some(bell.clone()));
+        assert_eq!(state.update(Some(&bell)), None);
+        assert_eq!(state.update(Some(&bell)), None);
+        // Leaving doesn't fire, entering again does
+        assert_eq!(state.update(None), None);
+        assert_eq!(state.update(Some(&bell)), Some(bell.clone()));
+        // Going straight into another zone
+        assert_eq!(state.update(Some(&exit)), Some(exit.clone()));
+
+        assert_eq!("complete_level".parse(), Ok(Trigger::CompleteLevel));
+        assert_eq!("play_sound:bell".parse(), Ok(bell));
+        assert_eq!("explode".parse::<Trigger>(), Err(()));
+    }
+
+    #[test]
+    fn test_tile_enter() {
+        let mut map = grid_map(&["....."]);
+        let mut fire = grid_map(&["."]).tiletypes.remove(0);
+        fire.notify_enter = true;
+        map.tiletypes.push(fire);
+        let fire = (map.tiletypes.len() - 1) as Tile;
+        map.set_tile(2, 0, fire);
+        map.set_tile(3, 0, fire);
+
+        let entered = Rc::new(Cell::new(0));
+        let walker = Entity::new([0.5, 0.5], Walker { tile: fire, entered: entered.clone() });
+        let mut world = empty_world(vec![walker]);
+        world.map = map;
+        let walk = |world: &mut World, x: f64| {
+            world.entities[0].physics.pos[0] = x;
+            world.notify_tiles();
+            entered.get()
+        };
+        assert_eq!(walk(&mut world, 0.5), 0);
+        assert_eq!(walk(&mut world, 2.5), 1);
+        assert_eq!(walk(&mut world, 2.7), 1);
+        // Still the same tile type
+        assert_eq!(walk(&mut world, 3.5), 1);
+        assert_eq!(walk(&mut world, 4.5), 1);
+        assert_eq!(walk(&mut world, 3.5), 2);
+        // Out of the map and back
+        assert_eq!(walk(&mut world, 5.5), 2);
+        assert_eq!(walk(&mut world, 3.5), 3);
+    }
+
+    #[test]
+    fn test_gradient_damage() {
+        let map = hazard_map();
+        let center = map.damage_at(0.5, 0.5);
+        let edge = map.damage_at(0.0, 0.5);
+        assert_eq!(center, 2.0);
+        assert_eq!(edge, 1.0);
+        assert!(map.damage_at(0.25, 0.5) > edge);
+        assert!(map.damage_at(0.25, 0.5) < center);
+        assert_eq!(map.damage_at(1.5, 0.5), 0.0);
+        assert_eq!(map.damage_at(-0.5, 0.5), 0.0);
+    }
+
+    #[test]
+    fn test_is_solid() {
+        let mut map = grid_map(&[
+            "#####",
+            "#.=/#",
+            "#####",
+        ]);
+        assert!(map.is_solid(0.5, 0.5));
+        assert!(map.is_solid(4.99, 2.99));
+        assert!(!map.is_solid(1.5, 1.5));
+        // Platforms and slopes can be entered from some sides
+        assert!(!map.is_solid(2.5, 1.5));
+        assert!(!map.is_solid(3.5, 1.5));
+        // Outside of the map
+        assert!(!map.is_solid(-0.5, 0.5));
+        assert!(!map.is_solid(5.0, 0.5));
+        assert!(!map.is_solid(2.5, 3.5));
+
+        assert_eq!(map.tile_rect(2.5, 1.25), ([2.0, 1.0], [3.0, 2.0]));
+        assert_eq!(map.tile_rect(-0.5, 3.0), ([-1.0, 3.0], [0.0, 4.0]));
+
+        // Lava in the middle of the map
+        let mut lava = grid_map(&["."]).tiletypes.remove(0);
+        lava.damage = 2.0;
+        map.tiletypes.push(lava);
+        let lava = (map.tiletypes.len() - 1) as Tile;
+        assert!(map.set_tile(1, 1, lava));
+        assert!(!map.is_solid(1.5, 1.5));
+        assert_eq!(map.damage_at(1.5, 1.5), 2.0);
+        assert_eq!(map.damage_at(2.5, 1.5), 0.0);
+    }
+
+    #[test]
+    fn test_tile_size() {
+        let mut map = grid_map(&[
+            "####",
+            "#./#",
+            "####",
+        ]);
+        map.tile_size = 0.5;
+        assert_eq!(map.tile_index(0.75, 0.6), (1, 1));
+        assert_eq!(map.tile_index(1.99, 1.49), (3, 2));
+        assert_eq!(map.tile_index(-0.25, 0.25), (-1, 0));
+        assert!(map.tilef(0.75, 0.6).map_or(false, |t| !t.collide));
+        assert!(map.is_solid(1.75, 0.75));
+        assert!(!map.is_solid(0.75, 0.75));
+        assert!(map.tilef(2.0, 0.5).is_none());
+        assert_eq!(map.tile_rect(0.75, 0.6), ([0.5, 0.5], [1.0, 1.0]));
+
+        // Ground follows the scaled slope, rays stop at scaled walls
+        assert_eq!(map.ground_at(1.25, 0.75).map(|(h, _)| h), Some(0.75));
+        assert_eq!(map.ground_at(0.75, 0.25).map(|(h, _)| h), Some(0.5));
+        let hit = map.raycast([0.75, 0.75], [-1.0, 0.0], 10.0).unwrap();
+        assert_eq!(hit.pos, [0.5, 0.75]);
+        assert_eq!(hit.dist, 0.25);
+        assert_eq!(map.visible_rect([1.0, 0.75], [0.5, 0.25]), (0, 0, 4, 3));
+    }
+}
@@ -0,0 +1,569 @@
+//! Loading of maps made with the Tiled editor (TMX format).
+//!
+//! Only orthogonal maps with embedded tilesets and CSV-encoded layers are
+//! supported. Objects that have a type become entities with that `type_id`.
+//!
+//! Tile layers have a `render_order` property, `background`, `main` or
+//! `foreground`. If no layer sets it to `main`, the first layer without it is
+//! the main one (which things collide with), and the others are drawn in the
+//! foreground.
+//!
+//! The tile properties `collide`, `one_way`, `climbable`, `notify_enter`
+//! (bools), `damage`, `damage_falloff`, `offset_x` and `offset_y` (floats) and
+//! `shape` (`full`, `slope_up_left`, `slope_up_right` or `empty`) set the
+//! attributes of the tile types, `trigger` (`complete_level` or
+//! `play_sound:<name>`) makes them fire when a character enters them, and
+//! `tile_entity` attaches an entity to each tile of that type (for example
+//! `f.door`). The `open_tile` property of a door is the id of the tile it
+//! becomes when open, in the same tileset. The map property `nb_players`
+//! sets the number of players. The map properties `gravity`, `run_speed`,
+//! `jump_speed` and `air_accel` override the default physics, and
+//! `camera_smoothing_x`, `camera_smoothing_y`, `camera_deadzone_x`,
+//! `camera_deadzone_y` and `camera_max_speed` the way the camera follows the
+//! players. The map property `tile_size` is the size of a tile in world
+//! units (1 by default), object positions being scaled along. The map
+//! property `music` names the music played during the level.
+//!
+//! Image layers become background layers, using their `parallaxx` attribute
+//! as the parallax factor, and their `scroll_x` and `scroll_y` properties as
+//! the automatic scrolling speed.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent};
+
+use entities::tile_entity_factory;
+use vecmath::vec2_scale;
+
+use super::{BackgroundDefinition, CameraConfig, EntityDefinition, MapFactory, MapLoadError,
+            Physics, RenderOrder, Tile, TileLayer, TileShape, TileTypeDefinition};
+
+/// Flags that Tiled stores in the high bits of the tile GIDs (flipping).
+const GID_FLAGS: u32 = 0xE000_0000;
+
+fn attr<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes.iter()
+        .find(|a| a.name.local_name == name)
+        .map(|a| a.value.as_ref())
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, MapLoadError> {
+    value.trim().parse().map_err(|_| {
+        MapLoadError::Invalid(format!("Invalid value for {}: {:?}", name, value))
+    })
+}
+
+fn parse_attr<T: FromStr>(attributes: &[OwnedAttribute],
+                          name: &str) -> Result<T, MapLoadError> {
+    match attr(attributes, name) {
+        Some(value) => parse(name, value),
+        None => Err(MapLoadError::Invalid(format!("Missing attribute {}", name))),
+    }
+}
+
+fn parse_attr_or<T: FromStr>(attributes: &[OwnedAttribute], name: &str,
+                             default: T) -> Result<T, MapLoadError> {
+    match attr(attributes, name) {
+        Some(value) => parse(name, value),
+        None => Ok(default),
+    }
+}
+
+/// Turn an image path, relative to the map file, into an asset name.
+fn asset_name(map_dir: &Path, source: &str) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for component in map_dir.join(source).components() {
+        match component {
+            Component::ParentDir => { parts.pop(); }
+            Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+            _ => {}
+        }
+    }
+    if let Some(pos) = parts.iter().rposition(|p| p == "assets") {
+        parts.drain(..pos + 1);
+    }
+    parts.join("/")
+}
+
+/// A tileset being read, turned into tile types when it ends.
+struct Tileset {
+    firstgid: u32,
+    tilewidth: f64,
+    tileheight: f64,
+    columns: u32,
+    tilecount: u32,
+    image: Option<String>,
+    properties: HashMap<u32, Vec<(String, String)>>,
+}
+
+impl Tileset {
+    /// Make the tile types, `first_index` being the index of the first one in
+    /// the map.
+    fn tile_types(&self, first_index: usize) -> Result<Vec<TileTypeDefinition>, MapLoadError> {
+        if self.columns == 0 {
+            return Err(MapLoadError::Invalid("Tileset has no columns".to_owned()));
+        }
+        let no_properties = Vec::new();
+        let mut tiletypes = Vec::new();
+        for id in 0..self.tilecount {
+            let (col, row) = (id % self.columns, id / self.columns);
+            let mut tiletype = TileTypeDefinition {
+                sprite_sheet: self.image.clone(),
+                sprite_coords: [col as f64 * self.tilewidth, row as f64 * self.tileheight,
+                                self.tilewidth, self.tileheight],
+                damage: 0.0,
+                damage_falloff: 0.0,
+                collide: false,
+                surface_velocity: [0.0, 0.0],
+                render_offset: [0.0, 0.0],
+                shape: TileShape::Full,
+                one_way: false,
+                climbable: false,
+                notify_enter: false,
+                trigger: None,
+                open_tile: None,
+                tile_entity: None,
+            };
+            for &(ref name, ref value) in self.properties.get(&id).unwrap_or(&no_properties) {
+                match name.as_ref() {
+                    "collide" => tiletype.collide = try!(parse(name, value)),
+                    "one_way" => tiletype.one_way = try!(parse(name, value)),
+                    "climbable" => tiletype.climbable = try!(parse(name, value)),
+                    "notify_enter" => tiletype.notify_enter = try!(parse(name, value)),
+                    "damage" => tiletype.damage = try!(parse(name, value)),
+                    "damage_falloff" => tiletype.damage_falloff = try!(parse(name, value)),
+                    "offset_x" => tiletype.render_offset[0] = try!(parse(name, value)),
+                    "offset_y" => tiletype.render_offset[1] = try!(parse(name, value)),
+                    "shape" => tiletype.shape = try!(parse(name, value)),
+                    "trigger" => tiletype.trigger = Some(try!(parse(name, value))),
+                    "open_tile" => {
+                        let open: u32 = try!(parse(name, value));
+                        if open >= self.tilecount {
+                            return Err(MapLoadError::Invalid(format!(
+                                "Tile {} opens to tile {}, not in the tileset", id, open)));
+                        }
+                        tiletype.open_tile = Some((first_index + open as usize) as Tile);
+                    }
+                    "tile_entity" => match tile_entity_factory(value) {
+                        Some(factory) => tiletype.tile_entity = Some(Box::new(factory)),
+                        None => warn!("Ignoring unknown tile entity type {}", value),
+                    },
+                    _ => warn!("Ignoring unknown tile property {}", name),
+                }
+            }
+            tiletypes.push(tiletype);
+        }
+        Ok(tiletypes)
+    }
+}
+
+/// Load a TMX map.
+///
+/// `map_dir` is the directory of the map file, that image paths are
+/// relative to.
+pub fn load<R: Read>(reader: R, map_dir: &Path) -> Result<MapFactory, MapLoadError> {
+    let mut size = None;
+    let mut tile_pixels = (1.0, 1.0);
+    let mut tile_size = 1.0;
+    let mut nb_players = 1;
+    let mut physics = Physics::default();
+    let mut camera = CameraConfig::default();
+    // Tile type 0 is the empty tile, for GID 0
+    let mut tiletypes = vec![
+        TileTypeDefinition {
+            sprite_sheet: None,
+            sprite_coords: [0.0, 0.0, 0.0, 0.0],
+            damage: 0.0,
+            damage_falloff: 0.0,
+            collide: false,
+            surface_velocity: [0.0, 0.0],
+            render_offset: [0.0, 0.0],
+            shape: TileShape::Full,
+            one_way: false,
+            climbable: false,
+            notify_enter: false,
+            trigger: None,
+            open_tile: None,
+            tile_entity: None,
+        },
+    ];
+    // (firstgid, index of first tile type, tile count) for each tileset
+    let mut gid_ranges: Vec<(u32, usize, u32)> = Vec::new();
+    // Render order (if set) and rows of each tile layer
+    let mut layers: Vec<(Option<RenderOrder>, Vec<Tile>)> = Vec::new();
+    let mut layer_order = None;
+    let mut entities = Vec::new();
+    let mut backgrounds = Vec::new();
+    let mut music = None;
+
+    let mut elements: Vec<String> = Vec::new();
+    let mut tileset: Option<Tileset> = None;
+    let mut tile_id = None;
+    let mut data: Option<String> = None;
+    let mut background: Option<BackgroundDefinition> = None;
+
+    for event in EventReader::new(reader) {
+        match try!(event) {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                let parent = elements.last().map(|s| s.as_ref()).unwrap_or("");
+                match name.local_name.as_ref() {
+                    "map" => {
+                        if let Some(orientation) = attr(&attributes, "orientation") {
+                            if orientation != "orthogonal" {
+                                return Err(MapLoadError::Invalid(format!(
+                                    "Unsupported orientation {}", orientation)));
+                            }
+                        }
+                        size = Some((try!(parse_attr::<usize>(&attributes, "width")),
+                                     try!(parse_attr::<usize>(&attributes, "height"))));
+                        tile_pixels = (try!(parse_attr(&attributes, "tilewidth")),
+                                       try!(parse_attr(&attributes, "tileheight")));
+                    }
+                    "tileset" => {
+                        if attr(&attributes, "source").is_some() {
+                            return Err(MapLoadError::Invalid(
+                                "External tilesets are not supported".to_owned()));
+                        }
+                        tileset = Some(Tileset {
+                            firstgid: try!(parse_attr(&attributes, "firstgid")),
+                            tilewidth: try!(parse_attr(&attributes, "tilewidth")),
+                            tileheight: try!(parse_attr(&attributes, "tileheight")),
+                            columns: try!(parse_attr_or(&attributes, "columns", 0)),
+                            tilecount: try!(parse_attr_or(&attributes, "tilecount", 0)),
+                            image: None,
+                            properties: HashMap::new(),
+                        });
+                    }
+                    "image" if parent == "tileset" => {
+                        let tileset = tileset.as_mut().unwrap();
+                        let source = try!(attr(&attributes, "source").ok_or_else(|| {
+                            MapLoadError::Invalid("Image has no source".to_owned())
+                        }));
+                        tileset.image = Some(asset_name(map_dir, source));
+                        let width: f64 = try!(parse_attr(&attributes, "width"));
+                        let height: f64 = try!(parse_attr(&attributes, "height"));
+                        if tileset.columns == 0 {
+                            tileset.columns = (width / tileset.tilewidth) as u32;
+                        }
+                        if tileset.tilecount == 0 {
+                            tileset.tilecount = tileset.columns *
+                                (height / tileset.tileheight) as u32;
+                        }
+                    }
+                    "imagelayer" => {
+                        background = Some(BackgroundDefinition {
+                            sprite_sheet: String::new(),
+                            parallax: try!(parse_attr_or(&attributes, "parallaxx", 1.0)),
+                            scroll: [0.0, 0.0],
+                        });
+                    }
+                    "image" if parent == "imagelayer" => {
+                        let source = try!(attr(&attributes, "source").ok_or_else(|| {
+                            MapLoadError::Invalid("Image has no source".to_owned())
+                        }));
+                        background.as_mut().unwrap().sprite_sheet = asset_name(map_dir, source);
+                    }
+                    "layer" => layer_order = None,
+                    "tile" if parent == "tileset" => {
+                        tile_id = Some(try!(parse_attr::<u32>(&attributes, "id")));
+                    }
+                    "property" => {
+                        let name = try!(parse_attr::<String>(&attributes, "name"));
+                        let value = try!(parse_attr::<String>(&attributes, "value"));
+                        let owner = if elements.len() >= 2 {
+                            elements[elements.len() - 2].as_ref()
+                        } else {
+                            ""
+                        };
+                        match (owner, tileset.as_mut(), tile_id) {
+                            ("tile", Some(tileset), Some(id)) => {
+                                tileset.properties.entry(id).or_insert_with(Vec::new)
+                                    .push((name, value));
+                            }
+                            ("layer", _, _) => match name.as_ref() {
+                                "render_order" => layer_order = Some(try!(parse(&name, &value))),
+                                _ => warn!("Ignoring unknown layer property {}", name),
+                            },
+                            ("imagelayer", _, _) => {
+                                let background = background.as_mut().unwrap();
+                                match name.as_ref() {
+                                    "scroll_x" => background.scroll[0] = try!(parse(&name, &value)),
+                                    "scroll_y" => background.scroll[1] = try!(parse(&name, &value)),
+                                    _ => warn!("Ignoring unknown image layer property {}", name),
+                                }
+                            }
+                            ("map", _, _) => match name.as_ref() {
+                                "nb_players" => nb_players = try!(parse(&name, &value)),
+                                "tile_size" => tile_size = try!(parse(&name, &value)),
+                                "gravity" => physics.gravity = try!(parse(&name, &value)),
+                                "run_speed" => physics.run_speed = try!(parse(&name, &value)),
+                                "jump_speed" => physics.jump_speed = try!(parse(&name, &value)),
+                                "air_accel" => physics.air_accel = try!(parse(&name, &value)),
+                                "camera_smoothing_x" =>
+                                    camera.smoothing[0] = try!(parse(&name, &value)),
+                                "camera_smoothing_y" =>
+                                    camera.smoothing[1] = try!(parse(&name, &value)),
+                                "camera_deadzone_x" =>
+                                    camera.deadzone[0] = try!(parse(&name, &value)),
+                                "camera_deadzone_y" =>
+                                    camera.deadzone[1] = try!(parse(&name, &value)),
+                                "camera_max_speed" =>
+                                    camera.max_speed = try!(parse(&name, &value)),
+                                "music" => music = Some(value),
+                                _ => warn!("Ignoring unknown map property {}", name),
+                            },
+                            _ => {}
+                        }
+                    }
+                    "data" if parent == "layer" => {
+                        if attr(&attributes, "encoding") != Some("csv") {
+                            return Err(MapLoadError::Invalid(
+                                "Only CSV encoding of layer data is supported".to_owned()));
+                        } else {
+                            data = Some(String::new());
+                        }
+                    }
+                    "object" => {
+                        let type_id = attr(&attributes, "type")
+                            .or_else(|| attr(&attributes, "class"));
+                        if let (Some(type_id), Some((_, height))) = (type_id, size) {
+                            let x: f64 = try!(parse_attr(&attributes, "x"));
+                            let y: f64 = try!(parse_attr(&attributes, "y"));
+                            let w: f64 = try!(parse_attr_or(&attributes, "width", 0.0));
+                            let h: f64 = try!(parse_attr_or(&attributes, "height", 0.0));
+                            // Use the bottom center of the object, with Y
+                            // going up, in tiles until the tile size is known
+                            entities.push(EntityDefinition {
+                                type_id: type_id.to_owned(),
+                                position: [(x + w / 2.0) / tile_pixels.0,
+                                           height as f64 - (y + h) / tile_pixels.1],
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+                elements.push(name.local_name);
+            }
+            XmlEvent::Characters(text) => {
+                if let Some(ref mut data) = data {
+                    data.push_str(&text);
+                }
+            }
+            XmlEvent::EndElement { name } => {
+                elements.pop();
+                match name.local_name.as_ref() {
+                    "tileset" => {
+                        let tileset = tileset.take().unwrap();
+                        let first_index = tiletypes.len();
+                        gid_ranges.push((tileset.firstgid, first_index, tileset.tilecount));
+                        tiletypes.extend(try!(tileset.tile_types(first_index)));
+                    }
+                    "tile" => tile_id = None,
+                    "imagelayer" => {
+                        let background = background.take().unwrap();
+                        if background.sprite_sheet.is_empty() {
+                            warn!("Ignoring image layer without an image");
+                        } else {
+                            backgrounds.push(background);
+                        }
+                    }
+                    "data" => {
+                        if let Some(data) = data.take() {
+                            let mut gids = Vec::new();
+                            for gid in data.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+                                let gid: u32 = try!(parse("tile", gid));
+                                gids.push(try!(gid_to_tile(gid & !GID_FLAGS, &gid_ranges)));
+                            }
+                            layers.push((layer_order, gids));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (width, height) = try!(size.ok_or_else(|| {
+        MapLoadError::Invalid("No map element".to_owned())
+    }));
+    if layers.is_empty() {
+        return Err(MapLoadError::Invalid("No tile layer".to_owned()));
+    }
+    let mut has_main = layers.iter().any(|&(order, _)| order == Some(RenderOrder::Main));
+    let mut tile_layers = Vec::with_capacity(layers.len());
+    for (order, rows) in layers {
+        if rows.len() != width * height {
+            return Err(MapLoadError::Invalid(format!(
+                "Layer has {} tiles, expected {}x{}", rows.len(), width, height)));
+        }
+        let order = order.unwrap_or_else(|| if has_main {
+            RenderOrder::Foreground
+        } else {
+            has_main = true;
+            RenderOrder::Main
+        });
+        // Tiled stores rows top to bottom, we go bottom to top
+        let mut tiles = Vec::with_capacity(width * height);
+        for row in rows.chunks(width).rev() {
+            tiles.extend_from_slice(row);
+        }
+        tile_layers.push(TileLayer::new(order, tiles));
+    }
+
+    for entity in entities.iter_mut() {
+        entity.position = vec2_scale(entity.position, tile_size);
+    }
+
+    let map = MapFactory {
+        width: width,
+        height: height,
+        nb_players: nb_players,
+        physics: physics,
+        camera: camera,
+        tile_size: tile_size,
+        tiletypes: tiletypes,
+        layers: tile_layers,
+        entities: entities,
+        backgrounds: backgrounds,
+        music: music,
+    };
+    try!(map.validate());
+    Ok(map)
+}
+
+fn gid_to_tile(gid: u32, gid_ranges: &[(u32, usize, u32)]) -> Result<Tile, MapLoadError> {
+    if gid == 0 {
+        return Ok(0);
+    }
+    for &(firstgid, first_index, count) in gid_ranges {
+        if firstgid <= gid && gid < firstgid + count {
+            return Ok((first_index + (gid - firstgid) as usize) as Tile);
+        }
+    }
+    Err(MapLoadError::Invalid(format!("Tile GID {} is not in any tileset", gid)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use world::{MapFactory, MapLoadError, Physics, RenderOrder};
+    use super::{asset_name, load};
+
+    #[test]
+    fn test_asset_name() {
+        assert_eq!(asset_name(Path::new("assets/maps"), "../map/bg.png"), "map/bg.png");
+        assert_eq!(asset_name(Path::new("/home/me/game/assets/maps"), "tiles.png"),
+                   "maps/tiles.png");
+    }
+
+    #[test]
+    fn test_load_fixture() {
+        let map = MapFactory::from_file(Path::new("assets/maps/test.tmx")).unwrap();
+        assert_eq!((map.width, map.height), (4, 3));
+        assert_eq!(map.nb_players, 2);
+        assert_eq!(map.physics.gravity, -5.0);
+        assert_eq!(map.physics.jump_speed, Physics::default().jump_speed);
+        assert_eq!(map.camera.deadzone, [0.5, 2.0]);
+
+        // Empty tile, wall, lava
+        assert_eq!(map.tiletypes.len(), 3);
+        assert_eq!(map.tiletypes[0].sprite_sheet, None);
+        assert_eq!(map.tiletypes[1].sprite_sheet, Some("map/castleCenter.png".to_owned()));
+        assert!(map.tiletypes[1].collide);
+        assert_eq!(map.tiletypes[2].sprite_sheet, Some("map/liquidLava.png".to_owned()));
+        assert!(!map.tiletypes[2].collide);
+        assert_eq!(map.tiletypes[2].damage, 1.0);
+
+        // Bottom row first
+        assert_eq!(map.layers.len(), 1);
+        assert_eq!(map.layers[0].render_order, RenderOrder::Main);
+        assert_eq!(map.layers[0].tiles, vec![1, 1, 2, 1,
+                                             0, 0, 0, 1,
+                                             0, 0, 0, 0]);
+
+        assert_eq!(map.entities.len(), 1);
+        assert_eq!(map.entities[0].type_id, "f.spawn");
+        assert_eq!(map.entities[0].position, [0.5, 1.0]);
+
+        assert_eq!(map.backgrounds.len(), 1);
+        assert_eq!(map.backgrounds[0].sprite_sheet, "map/bg.png");
+        assert_eq!(map.backgrounds[0].parallax, 0.5);
+        assert_eq!(map.backgrounds[0].scroll, [0.25, 0.0]);
+    }
+
+    #[test]
+    fn test_layers() {
+        let map = r#"<map width="1" height="1" tilewidth="1" tileheight="1">
+            <layer><data encoding="csv">0</data></layer>
+            <layer><data encoding="csv">0</data></layer>
+            <layer>
+              <properties><property name="render_order" value="background"/></properties>
+              <data encoding="csv">0</data>
+            </layer></map>"#;
+        let map = load(map.as_bytes(), Path::new("")).unwrap();
+        let orders: Vec<_> = map.layers.iter().map(|l| l.render_order).collect();
+        assert_eq!(orders, vec![RenderOrder::Main, RenderOrder::Foreground,
+                                RenderOrder::Background]);
+    }
+
+    #[test]
+    fn test_open_tile() {
+        let door = |open: &str| format!(r#"<map width="2" height="1" tilewidth="1" tileheight="1">
+            <tileset firstgid="1" tilewidth="1" tileheight="1" columns="2" tilecount="2">
+              <tile id="0"><properties>
+                <property name="collide" value="true"/>
+                <property name="open_tile" value="{}"/>
+              </properties></tile>
+            </tileset>
+            <layer><data encoding="csv">1,2</data></layer></map>"#, open);
+        let map = load(door("1").as_bytes(), Path::new("")).unwrap();
+        assert_eq!(map.tiletypes[1].open_tile, Some(map.main_layer().tiles[1]));
+        assert_eq!(map.tiletypes[2].open_tile, None);
+
+        match load(door("2").as_bytes(), Path::new("")) {
+            Err(MapLoadError::Invalid(_)) => {}
+            r => panic!("Unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_tile_size() {
+        let map = r#"<map width="2" height="2" tilewidth="16" tileheight="16">
+            <properties><property name="tile_size" value="0.5"/></properties>
+            <layer><data encoding="csv">0,0,0,0</data></layer>
+            <objectgroup>
+              <object type="f.spawn" x="16" y="8" width="16" height="8"/>
+            </objectgroup></map>"#;
+        let map = load(map.as_bytes(), Path::new("")).unwrap();
+        assert_eq!(map.tile_size, 0.5);
+        assert_eq!(map.entities[0].position, [0.75, 0.5]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        match MapFactory::from_file(Path::new("assets/maps/missing.tmx")) {
+            Err(MapLoadError::Io(_)) => {}
+            r => panic!("Unexpected result {:?}", r.map(|_| ())),
+        }
+
+        let short = r#"<map width="2" height="2" tilewidth="1" tileheight="1">
+            <layer><data encoding="csv">0,0,0</data></layer></map>"#;
+        match load(short.as_bytes(), Path::new("")) {
+            Err(MapLoadError::Invalid(_)) => {}
+            r => panic!("Unexpected result {:?}", r.map(|_| ())),
+        }
+
+        let bad_gid = r#"<map width="1" height="1" tilewidth="1" tileheight="1">
+            <layer><data encoding="csv">3</data></layer></map>"#;
+        match load(bad_gid.as_bytes(), Path::new("")) {
+            Err(MapLoadError::Invalid(_)) => {}
+            r => panic!("Unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+}